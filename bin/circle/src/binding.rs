@@ -0,0 +1,86 @@
+//! Binding a voucher to the peer that presents it.
+//!
+//! A voucher only names a locus, so anyone who saw it gossiped could announce it as their
+//! own. Along with its voucher, a peer therefore announces a [`PeerBinding`]: a signature
+//! by the locus key over its libp2p [`PeerId`]. Gossipsub signs every message with the key
+//! of its author, so a verified binding ties the locus to the peer that sent the
+//! announcement, and to no other.
+
+use dos::signature::Signature;
+use libp2p::PeerId;
+use plonky2::field::types::Field;
+use simple_crypto::{PrivateKey, PublicKey, F};
+use std::error::Error;
+
+// separates bindings from other signatures of the locus key
+const PEER_BINDING_TAG: &[u8; 8] = b"circ-pid";
+// bytes of the peer id packed in every field element, so that they stay canonical
+const BYTES_PER_ELEMENT: usize = 7;
+
+/// A signature by a locus key over the peer id that presents its voucher.
+pub struct PeerBinding {
+    signature: Signature,
+}
+
+impl PeerBinding {
+    /// bind `peer_id` to the locus of `locus_private_key`
+    pub fn new(peer_id: &PeerId, locus_private_key: PrivateKey) -> Self {
+        PeerBinding {
+            signature: Signature::sign(locus_private_key, &binding_message(peer_id)),
+        }
+    }
+
+    /// a binding is valid if `locus` signed exactly `peer_id`
+    pub fn verify(&self, peer_id: &PeerId, locus: &PublicKey) -> bool {
+        self.signature.signer() == *locus && self.signature.verify(&binding_message(peer_id))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.signature.to_bytes()
+    }
+
+    /// decode a binding; it still has to be verified
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Box<dyn Error>> {
+        Ok(PeerBinding {
+            signature: Signature::from_bytes(bytes)?,
+        })
+    }
+}
+
+/// the tag followed by the bytes of the peer id, packed into field elements
+fn binding_message(peer_id: &PeerId) -> Vec<F> {
+    let tag = F::from_canonical_u64(u64::from_le_bytes(*PEER_BINDING_TAG));
+    let peer_id_bytes = peer_id.to_bytes();
+    let peer_id_elements = peer_id_bytes.chunks(BYTES_PER_ELEMENT).map(|chunk| {
+        let mut element = [0u8; 8];
+        element[..chunk.len()].copy_from_slice(chunk);
+        F::from_canonical_u64(u64::from_le_bytes(element))
+    });
+    let length = F::from_canonical_usize(peer_id_bytes.len());
+    [tag, length].into_iter().chain(peer_id_elements).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simple_crypto::KeyPair;
+
+    #[test]
+    fn it_works_peer_binding() {
+        let locus = KeyPair::generate_key_pair();
+        let peer_id = PeerId::random();
+        let binding = PeerBinding::new(&peer_id, locus.private_key);
+
+        let decoded = PeerBinding::from_bytes(&binding.to_bytes()).unwrap();
+        assert!(decoded.verify(&peer_id, &locus.public_key));
+
+        // a peer replaying the voucher and binding of another peer is refused
+        assert!(!decoded.verify(&PeerId::random(), &locus.public_key));
+        // and so is a binding by another locus
+        let stranger = KeyPair::generate_key_pair();
+        assert!(!decoded.verify(&peer_id, &stranger.public_key));
+        assert!(
+            !PeerBinding::new(&peer_id, stranger.private_key).verify(&peer_id, &locus.public_key)
+        );
+    }
+}
@@ -0,0 +1,96 @@
+use clap::{Args, Parser, Subcommand};
+use std::path::PathBuf;
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "circle",
+    about = "Gossip with peers vouched for through zkSocial"
+)]
+pub struct Cli {
+    #[command(flatten)]
+    pub node: NodeArgs,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+/// Settings shared by all subcommands. Anything given here overrides
+/// the value in the config file.
+#[derive(Debug, Args)]
+pub struct NodeArgs {
+    /// TOML config file
+    #[arg(short, long, global = true)]
+    pub config: Option<PathBuf>,
+
+    /// multiaddress to listen on (repeatable, replaces the configured list)
+    #[arg(long, global = true)]
+    pub listen: Vec<String>,
+
+    /// multiaddress of a peer to dial on startup (repeatable, added to the configured list)
+    #[arg(long, global = true)]
+    pub bootstrap: Vec<String>,
+
+    /// gossipsub topic to join (repeatable, replaces the configured list)
+    #[arg(long, global = true)]
+    pub topic: Vec<String>,
+
+    /// gossipsub heartbeat interval in seconds
+    #[arg(long, global = true)]
+    pub heartbeat: Option<u64>,
+
+    /// path of the libp2p identity keypair
+    #[arg(long, global = true)]
+    pub identity: Option<PathBuf>,
+
+    /// directory holding the locus key and voucher
+    #[arg(long, global = true)]
+    pub wallet: Option<PathBuf>,
+
+    /// only accept messages from peers that presented a voucher
+    #[arg(long, global = true)]
+    pub require_voucher: bool,
+
+    /// largest voucher degree accepted from a peer
+    #[arg(long, global = true)]
+    pub max_degree: Option<u64>,
+
+    /// hex encoded origin whose vouchers are accepted (repeatable, added to the configured list)
+    #[arg(long, global = true)]
+    pub trusted_origin: Vec<String>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// join the configured topics and relay stdin to them
+    Run,
+    /// print the peer id and, if a wallet is configured, the locus and voucher degree
+    Id,
+    /// connect to the network for a while and list the peers that were found
+    Peers {
+        /// seconds to wait for peers before printing
+        #[arg(long, default_value_t = 10)]
+        wait: u64,
+    },
+    /// create an origin voucher, or extend the wallet voucher to another locus
+    Vouch(VouchArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct VouchArgs {
+    /// hex encoded locus (public key) to vouch for
+    #[arg(required_unless_present = "origin", requires = "out")]
+    pub locus: Option<String>,
+
+    /// become an origin: store a degree zero voucher for our own locus in the wallet
+    #[arg(long, conflicts_with = "locus")]
+    pub origin: bool,
+
+    /// epoch to issue the origin voucher in; bump it after a revocation to reissue the
+    /// vouchers still trusted
+    #[arg(long, requires = "origin", default_value_t = 0)]
+    pub epoch: u64,
+
+    /// file to write the extended voucher to
+    #[arg(long)]
+    pub out: Option<PathBuf>,
+}
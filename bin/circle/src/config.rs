@@ -0,0 +1,264 @@
+use libp2p::Multiaddr;
+use serde::{Deserialize, Serialize};
+use simple_crypto::PublicKey;
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::cli::NodeArgs;
+use crate::scoring::{peer_score_params, peer_score_thresholds};
+use crate::wallet::decode_key;
+
+/// Persistent settings for a `circle` node, read from a TOML file.
+///
+/// Every field has a default, so an empty file (or no file at all)
+/// reproduces the behaviour of the original chat demo.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// multiaddresses to listen on
+    pub listen_addresses: Vec<String>,
+    /// multiaddresses (ending in `/p2p/<peer id>`) to dial on startup
+    pub bootstrap_peers: Vec<String>,
+    /// gossipsub topics to join; lines read from stdin are published on the first one
+    pub topics: Vec<String>,
+    pub heartbeat_interval_secs: u64,
+    /// protobuf encoded libp2p keypair; generated on first use if missing.
+    /// Without it the node gets a fresh identity on every start.
+    pub identity_path: Option<PathBuf>,
+    /// directory holding the locus key and the voucher of this node
+    pub wallet_path: Option<PathBuf>,
+    pub gating: GatingPolicy,
+    pub scoring: ScoringConfig,
+}
+
+/// Which peers a node is willing to talk to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GatingPolicy {
+    /// drop messages from peers that have not presented a voucher
+    pub require_voucher: bool,
+    /// largest voucher degree accepted from a peer
+    pub max_degree: u64,
+    /// hex encoded origins (as printed by `circle id`) whose vouchers are accepted. Anyone
+    /// can mint an origin voucher for a fresh key, so vouchers from any other origin count
+    /// as no voucher at all.
+    pub trusted_origins: Vec<String>,
+}
+
+/// Gossipsub peer scoring derived from vouchers, see [`crate::scoring`].
+/// Scores are weighted by `app_specific_weight` before being compared to the thresholds.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScoringConfig {
+    /// application score of an origin (degree zero) peer
+    pub origin_score: f64,
+    /// application score of a peer without a valid voucher
+    pub unvouched_score: f64,
+    /// seconds a new peer has to present a voucher before it counts as unvouched
+    pub voucher_grace_period_secs: u64,
+    pub app_specific_weight: f64,
+    pub ip_colocation_factor_weight: f64,
+    pub gossip_threshold: f64,
+    pub publish_threshold: f64,
+    pub graylist_threshold: f64,
+    pub accept_px_threshold: f64,
+    pub opportunistic_graft_threshold: f64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            listen_addresses: vec!["/ip4/0.0.0.0/tcp/0".to_string()],
+            bootstrap_peers: Vec::new(),
+            topics: vec!["test-net".to_string()],
+            heartbeat_interval_secs: 10,
+            identity_path: None,
+            wallet_path: None,
+            gating: GatingPolicy::default(),
+            scoring: ScoringConfig::default(),
+        }
+    }
+}
+
+impl Default for GatingPolicy {
+    fn default() -> Self {
+        GatingPolicy {
+            require_voucher: false,
+            max_degree: 3,
+            trusted_origins: Vec::new(),
+        }
+    }
+}
+
+impl Default for ScoringConfig {
+    fn default() -> Self {
+        ScoringConfig {
+            origin_score: 10.0,
+            unvouched_score: -10.0,
+            voucher_grace_period_secs: 30,
+            app_specific_weight: 10.0,
+            ip_colocation_factor_weight: 0.0,
+            gossip_threshold: -10.0,
+            publish_threshold: -50.0,
+            graylist_threshold: -80.0,
+            accept_px_threshold: 10.0,
+            opportunistic_graft_threshold: 20.0,
+        }
+    }
+}
+
+impl Config {
+    /// read the config file (if any) and apply the command line overrides on top of it
+    pub fn load(args: &NodeArgs) -> Result<Self, Box<dyn Error>> {
+        let mut config = match &args.config {
+            Some(path) => Self::from_file(path)?,
+            None => Config::default(),
+        };
+
+        if !args.listen.is_empty() {
+            config.listen_addresses = args.listen.clone();
+        }
+        config
+            .bootstrap_peers
+            .extend(args.bootstrap.iter().cloned());
+        if !args.topic.is_empty() {
+            config.topics = args.topic.clone();
+        }
+        if let Some(heartbeat) = args.heartbeat {
+            config.heartbeat_interval_secs = heartbeat;
+        }
+        if let Some(identity) = &args.identity {
+            config.identity_path = Some(identity.clone());
+        }
+        if let Some(wallet) = &args.wallet {
+            config.wallet_path = Some(wallet.clone());
+        }
+        if args.require_voucher {
+            config.gating.require_voucher = true;
+        }
+        if let Some(max_degree) = args.max_degree {
+            config.gating.max_degree = max_degree;
+        }
+        config
+            .gating
+            .trusted_origins
+            .extend(args.trusted_origin.iter().cloned());
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read config {}: {e}", path.display()))?;
+        let config = toml::from_str(&contents)
+            .map_err(|e| format!("Invalid config {}: {e}", path.display()))?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if self.topics.is_empty() {
+            return Err("At least one topic must be configured".into());
+        }
+        if self.heartbeat_interval_secs == 0 {
+            return Err("Heartbeat interval must be at least one second".into());
+        }
+        self.gating.trusted_origin_keys()?;
+        peer_score_params(&self.scoring).validate()?;
+        peer_score_thresholds(&self.scoring).validate()?;
+        self.listen_multiaddrs()?;
+        self.bootstrap_multiaddrs()?;
+        Ok(())
+    }
+
+    pub fn listen_multiaddrs(&self) -> Result<Vec<Multiaddr>, Box<dyn Error>> {
+        parse_multiaddrs(&self.listen_addresses)
+    }
+
+    pub fn bootstrap_multiaddrs(&self) -> Result<Vec<Multiaddr>, Box<dyn Error>> {
+        parse_multiaddrs(&self.bootstrap_peers)
+    }
+
+    pub fn heartbeat_interval(&self) -> Duration {
+        Duration::from_secs(self.heartbeat_interval_secs)
+    }
+}
+
+impl GatingPolicy {
+    pub fn trusted_origin_keys(&self) -> Result<HashSet<PublicKey>, Box<dyn Error>> {
+        self.trusted_origins
+            .iter()
+            .map(|origin| {
+                decode_key(origin)
+                    .map_err(|e| format!("Invalid trusted origin {origin}: {e}").into())
+            })
+            .collect()
+    }
+}
+
+fn parse_multiaddrs(addresses: &[String]) -> Result<Vec<Multiaddr>, Box<dyn Error>> {
+    addresses
+        .iter()
+        .map(|address| {
+            address
+                .parse()
+                .map_err(|e| format!("Invalid multiaddress {address}: {e}").into())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_works_empty_config_is_default() {
+        let config: Config = toml::from_str("").unwrap();
+        assert_eq!(config.listen_addresses, vec!["/ip4/0.0.0.0/tcp/0"]);
+        assert_eq!(config.topics, vec!["test-net"]);
+        assert_eq!(config.heartbeat_interval_secs, 10);
+        assert!(!config.gating.require_voucher);
+    }
+
+    #[test]
+    fn it_works_parse_config_file() {
+        let config: Config = toml::from_str(
+            r#"
+            listen_addresses = ["/ip4/127.0.0.1/tcp/4001"]
+            bootstrap_peers = ["/ip4/10.0.0.2/tcp/4001/p2p/12D3KooWD3eckifWpRn9wQpMG9R9hX3sD158z7EqHWmweQAJU5SA"]
+            topics = ["lisbon", "edge"]
+            identity_path = "node.key"
+
+            [gating]
+            require_voucher = true
+            max_degree = 2
+            trusted_origins = ["0100000000000000020000000000000003000000000000000400000000000000"]
+            "#,
+        )
+        .unwrap();
+
+        assert!(config.validate().is_ok());
+        assert_eq!(config.topics, vec!["lisbon", "edge"]);
+        assert_eq!(config.bootstrap_multiaddrs().unwrap().len(), 1);
+        assert_eq!(config.identity_path, Some(PathBuf::from("node.key")));
+        assert_eq!(config.gating.max_degree, 2);
+        assert_eq!(config.gating.trusted_origin_keys().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn it_rejects_unknown_fields_and_bad_addresses() {
+        assert!(toml::from_str::<Config>("topic = \"typo\"").is_err());
+
+        let config: Config = toml::from_str("listen_addresses = [\"not an address\"]").unwrap();
+        assert!(config.validate().is_err());
+
+        let config: Config = toml::from_str("[scoring]\ngraylist_threshold = 10.0").unwrap();
+        assert!(config.validate().is_err());
+
+        let config: Config = toml::from_str("[gating]\ntrusted_origins = [\"zz\"]").unwrap();
+        assert!(config.validate().is_err());
+    }
+}
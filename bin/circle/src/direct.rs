@@ -0,0 +1,258 @@
+//! Direct messages between vouched peers.
+//!
+//! A message is encrypted to the X25519 key derived from the ed25519 identity of the
+//! recipient: the sender picks an ephemeral X25519 key, derives a ChaCha20-Poly1305 key
+//! from the Diffie-Hellman shared secret, and sends the ephemeral public key, nonce and
+//! ciphertext over the `/circle/dm/1.0.0` request-response protocol. The recipient answers
+//! with whether it accepted the message.
+
+use async_trait::async_trait;
+use chacha20poly1305::{
+    aead::{Aead, NewAead},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use curve25519_dalek::edwards::CompressedEdwardsY;
+use futures::prelude::*;
+use libp2p::core::upgrade::{read_length_prefixed, write_length_prefixed, ProtocolName};
+use libp2p::{identity, request_response, PeerId};
+use rand::RngCore;
+use sha2::{Digest, Sha256, Sha512};
+use std::error::Error;
+use std::io;
+use x25519_dalek::{x25519, X25519_BASEPOINT_BYTES};
+
+const KEY_DERIVATION_DOMAIN: &[u8] = b"circle.direct.message";
+const EPHEMERAL_KEY_LENGTH: usize = 32;
+const NONCE_LENGTH: usize = 12;
+const MAX_MESSAGE_SIZE: usize = 64 * 1024;
+
+/// An encrypted direct message as it goes over the wire.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DirectMessage {
+    pub ephemeral_public_key: [u8; EPHEMERAL_KEY_LENGTH],
+    pub nonce: [u8; NONCE_LENGTH],
+    pub ciphertext: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DirectMessageResponse {
+    Accepted,
+    /// the recipient does not accept messages from the sender (e.g. it holds no valid voucher)
+    Rejected,
+}
+
+impl DirectMessage {
+    /// encrypt `plaintext` to the ed25519 identity of `recipient`
+    pub fn encrypt(recipient: &PeerId, plaintext: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let recipient_public_key = x25519_public_key(recipient)?;
+
+        let mut ephemeral_secret = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut ephemeral_secret);
+        let ephemeral_public_key = x25519(ephemeral_secret, X25519_BASEPOINT_BYTES);
+        let shared_secret = x25519(ephemeral_secret, recipient_public_key);
+
+        let mut nonce = [0u8; NONCE_LENGTH];
+        rand::thread_rng().fill_bytes(&mut nonce);
+
+        let cipher = message_cipher(&shared_secret, &ephemeral_public_key, &recipient_public_key);
+        let ciphertext = cipher
+            .encrypt(Nonce::from_slice(&nonce), plaintext)
+            .map_err(|_| "Failed to encrypt direct message")?;
+
+        Ok(DirectMessage {
+            ephemeral_public_key,
+            nonce,
+            ciphertext,
+        })
+    }
+
+    /// decrypt a message sent to the identity `local_key`
+    pub fn decrypt(&self, local_key: &identity::Keypair) -> Result<Vec<u8>, Box<dyn Error>> {
+        let secret_key = x25519_secret_key(local_key)?;
+        let public_key = x25519(secret_key, X25519_BASEPOINT_BYTES);
+        let shared_secret = x25519(secret_key, self.ephemeral_public_key);
+
+        let cipher = message_cipher(&shared_secret, &self.ephemeral_public_key, &public_key);
+        let plaintext = cipher
+            .decrypt(Nonce::from_slice(&self.nonce), self.ciphertext.as_ref())
+            .map_err(|_| "Failed to decrypt direct message")?;
+        Ok(plaintext)
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        [
+            self.ephemeral_public_key.as_ref(),
+            self.nonce.as_ref(),
+            self.ciphertext.as_ref(),
+        ]
+        .concat()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        if bytes.len() < EPHEMERAL_KEY_LENGTH + NONCE_LENGTH {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "direct message is too short",
+            ));
+        }
+        let (ephemeral_public_key, rest) = bytes.split_at(EPHEMERAL_KEY_LENGTH);
+        let (nonce, ciphertext) = rest.split_at(NONCE_LENGTH);
+        Ok(DirectMessage {
+            ephemeral_public_key: ephemeral_public_key.try_into().expect("split at length"),
+            nonce: nonce.try_into().expect("split at length"),
+            ciphertext: ciphertext.to_vec(),
+        })
+    }
+}
+
+fn message_cipher(
+    shared_secret: &[u8; 32],
+    ephemeral_public_key: &[u8; 32],
+    recipient_public_key: &[u8; 32],
+) -> ChaCha20Poly1305 {
+    let key = Sha256::new()
+        .chain_update(KEY_DERIVATION_DOMAIN)
+        .chain_update(shared_secret)
+        .chain_update(ephemeral_public_key)
+        .chain_update(recipient_public_key)
+        .finalize();
+    ChaCha20Poly1305::new(Key::from_slice(&key))
+}
+
+/// The X25519 (Montgomery) form of the ed25519 key embedded in a peer id.
+fn x25519_public_key(peer_id: &PeerId) -> Result<[u8; 32], Box<dyn Error>> {
+    // ed25519 peer ids inline the protobuf encoded public key as an identity multihash
+    let multihash = peer_id.as_ref();
+    if multihash.code() != 0 {
+        return Err(format!("Peer id {peer_id} does not inline its public key").into());
+    }
+    let public_key = identity::PublicKey::from_protobuf_encoding(multihash.digest())?
+        .into_ed25519()
+        .ok_or_else(|| format!("Peer id {peer_id} is not an ed25519 key"))?;
+
+    let montgomery_point = CompressedEdwardsY(public_key.encode())
+        .decompress()
+        .ok_or_else(|| format!("Peer id {peer_id} is not a valid curve point"))?
+        .to_montgomery();
+    Ok(montgomery_point.to_bytes())
+}
+
+/// The X25519 secret matching [`x25519_public_key`]: the (clamped) scalar half of the
+/// SHA-512 expansion of the ed25519 seed.
+fn x25519_secret_key(local_key: &identity::Keypair) -> Result<[u8; 32], Box<dyn Error>> {
+    let keypair = local_key
+        .clone()
+        .into_ed25519()
+        .ok_or("Direct messages need an ed25519 identity")?;
+    let expanded = Sha512::digest(keypair.secret().as_ref());
+    let mut secret_key = [0u8; 32];
+    secret_key.copy_from_slice(&expanded[..32]);
+    Ok(secret_key)
+}
+
+#[derive(Debug, Clone)]
+pub struct DirectMessageProtocol;
+
+impl ProtocolName for DirectMessageProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        b"/circle/dm/1.0.0"
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DirectMessageCodec;
+
+#[async_trait]
+impl request_response::Codec for DirectMessageCodec {
+    type Protocol = DirectMessageProtocol;
+    type Request = DirectMessage;
+    type Response = DirectMessageResponse;
+
+    async fn read_request<T>(
+        &mut self,
+        _: &DirectMessageProtocol,
+        io: &mut T,
+    ) -> io::Result<Self::Request>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let bytes = read_length_prefixed(io, MAX_MESSAGE_SIZE).await?;
+        DirectMessage::from_bytes(&bytes)
+    }
+
+    async fn read_response<T>(
+        &mut self,
+        _: &DirectMessageProtocol,
+        io: &mut T,
+    ) -> io::Result<Self::Response>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let mut accepted = [0u8; 1];
+        io.read_exact(&mut accepted).await?;
+        match accepted[0] {
+            1 => Ok(DirectMessageResponse::Accepted),
+            _ => Ok(DirectMessageResponse::Rejected),
+        }
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &DirectMessageProtocol,
+        io: &mut T,
+        request: DirectMessage,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_length_prefixed(io, request.to_bytes()).await?;
+        io.close().await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &DirectMessageProtocol,
+        io: &mut T,
+        response: DirectMessageResponse,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        let accepted = u8::from(response == DirectMessageResponse::Accepted);
+        io.write_all(&[accepted]).await?;
+        io.close().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_works_direct_message_encryption() {
+        let recipient_key = identity::Keypair::generate_ed25519();
+        let recipient = PeerId::from(recipient_key.public());
+        let plaintext = b"meet at the lisbon edge node";
+
+        let message = DirectMessage::encrypt(&recipient, plaintext).unwrap();
+        assert_ne!(message.ciphertext, plaintext);
+
+        let decoded = DirectMessage::from_bytes(&message.to_bytes()).unwrap();
+        assert_eq!(decoded, message);
+        assert_eq!(decoded.decrypt(&recipient_key).unwrap(), plaintext);
+    }
+
+    #[test]
+    fn it_rejects_direct_message_for_someone_else_or_tampered() {
+        let recipient_key = identity::Keypair::generate_ed25519();
+        let recipient = PeerId::from(recipient_key.public());
+        let stranger_key = identity::Keypair::generate_ed25519();
+
+        let message = DirectMessage::encrypt(&recipient, b"not for you").unwrap();
+        assert!(message.decrypt(&stranger_key).is_err());
+
+        let mut tampered = message;
+        tampered.ciphertext[0] ^= 1;
+        assert!(tampered.decrypt(&recipient_key).is_err());
+    }
+}
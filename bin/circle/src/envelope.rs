@@ -0,0 +1,162 @@
+//! The wire format of everything circle publishes over gossipsub.
+//!
+//! Every message is a CBOR encoded [`Envelope`] (a map with the field names below as keys),
+//! so other tools can parse it without linking against circle:
+//!
+//! | key            | CBOR type       | contents                                             |
+//! |----------------|-----------------|------------------------------------------------------|
+//! | `version`      | unsigned        | [`ENVELOPE_VERSION`]                                 |
+//! | `kind`         | text            | `chat`, `voucher-announcement`, `vouch-request` or `revocation-notice` |
+//! | `sender_locus` | bytes or null   | locus public key, 4 little-endian u64 field elements |
+//! | `timestamp`    | unsigned        | seconds since the unix epoch                         |
+//! | `voucher`      | bytes or null   | a voucher presentation (`Voucher::to_bytes`)         |
+//! | `body`         | bytes           | kind specific payload, UTF-8 text for chat and the peer binding (`PeerBinding::to_bytes`) for voucher announcements |
+//!
+//! Gossipsub message ids are the hex encoded SHA-256 of the encoded envelope.
+
+use libp2p::gossipsub;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use simple_crypto::{field_elements_from_bytes, field_elements_to_bytes, PublicKey};
+use std::error::Error;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Envelopes with a different version are rejected.
+pub const ENVELOPE_VERSION: u16 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MessageKind {
+    /// a line typed by the sender
+    Chat,
+    /// the sender presents its voucher
+    VoucherAnnouncement,
+    /// the sender asks to be vouched for its locus
+    VouchRequest,
+    /// the body revokes a voucher edge
+    RevocationNotice,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Envelope {
+    pub version: u16,
+    pub kind: MessageKind,
+    #[serde(with = "serde_bytes")]
+    sender_locus: Option<Vec<u8>>,
+    pub timestamp: u64,
+    #[serde(with = "serde_bytes")]
+    voucher: Option<Vec<u8>>,
+    #[serde(with = "serde_bytes")]
+    pub body: Vec<u8>,
+}
+
+impl Envelope {
+    /// a new envelope of the current version, stamped with the current time
+    pub fn new(kind: MessageKind, sender_locus: Option<&PublicKey>, body: Vec<u8>) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("clock after the unix epoch")
+            .as_secs();
+        Envelope {
+            version: ENVELOPE_VERSION,
+            kind,
+            sender_locus: sender_locus.map(|locus| field_elements_to_bytes(locus)),
+            timestamp,
+            voucher: None,
+            body,
+        }
+    }
+
+    /// attach a serialized voucher presentation
+    pub fn with_voucher(mut self, voucher_bytes: Vec<u8>) -> Self {
+        self.voucher = Some(voucher_bytes);
+        self
+    }
+
+    pub fn sender_locus(&self) -> Result<Option<PublicKey>, Box<dyn Error>> {
+        let Some(bytes) = &self.sender_locus else {
+            return Ok(None);
+        };
+        let locus: PublicKey = field_elements_from_bytes(bytes)?
+            .try_into()
+            .map_err(|_| "Sender locus has the wrong length")?;
+        Ok(Some(locus))
+    }
+
+    /// the attached voucher presentation; it still has to be verified
+    pub fn voucher_bytes(&self) -> Option<&[u8]> {
+        self.voucher.as_deref()
+    }
+
+    /// the body of a chat message
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(self, &mut bytes).expect("Writing to a vec never fails");
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let envelope: Envelope =
+            ciborium::de::from_reader(bytes).map_err(|e| format!("Malformed envelope: {e}"))?;
+        if envelope.version != ENVELOPE_VERSION {
+            return Err(format!("Unsupported envelope version {}", envelope.version).into());
+        }
+        Ok(envelope)
+    }
+}
+
+/// Content-address a gossipsub message by the SHA-256 of its data.
+pub fn message_id(message: &gossipsub::Message) -> gossipsub::MessageId {
+    gossipsub::MessageId::from(hex::encode(Sha256::digest(&message.data)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simple_crypto::KeyPair;
+
+    #[test]
+    fn it_works_envelope_roundtrip() {
+        let locus = KeyPair::generate_key_pair().public_key;
+        let envelope = Envelope::new(MessageKind::Chat, Some(&locus), b"hello circle".to_vec())
+            .with_voucher(vec![1, 2, 3]);
+
+        let decoded = Envelope::from_bytes(&envelope.to_bytes()).unwrap();
+        assert_eq!(decoded, envelope);
+        assert_eq!(decoded.sender_locus().unwrap(), Some(locus));
+        assert_eq!(decoded.voucher_bytes(), Some([1, 2, 3].as_ref()));
+        assert_eq!(decoded.text(), "hello circle");
+
+        let anonymous = Envelope::new(MessageKind::VouchRequest, None, Vec::new());
+        let decoded = Envelope::from_bytes(&anonymous.to_bytes()).unwrap();
+        assert_eq!(decoded.sender_locus().unwrap(), None);
+        assert_eq!(decoded.voucher_bytes(), None);
+    }
+
+    #[test]
+    fn it_rejects_malformed_or_unknown_envelopes() {
+        assert!(Envelope::from_bytes(b"plain text").is_err());
+
+        let mut envelope = Envelope::new(MessageKind::Chat, None, Vec::new());
+        envelope.version = ENVELOPE_VERSION + 1;
+        assert!(Envelope::from_bytes(&envelope.to_bytes()).is_err());
+    }
+
+    #[test]
+    fn it_works_kinds_are_named_on_the_wire() {
+        let envelope = Envelope::new(MessageKind::VoucherAnnouncement, None, Vec::new());
+        let value: ciborium::value::Value =
+            ciborium::de::from_reader(envelope.to_bytes().as_slice()).unwrap();
+        let kind = value
+            .as_map()
+            .unwrap()
+            .iter()
+            .find(|(key, _)| key.as_text() == Some("kind"))
+            .map(|(_, kind)| kind.as_text().unwrap().to_string());
+        assert_eq!(kind.as_deref(), Some("voucher-announcement"));
+    }
+}
@@ -0,0 +1,32 @@
+use libp2p::identity;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use crate::wallet::write_private_file;
+
+/// Load the libp2p keypair stored at `path`, generating (and storing) a new
+/// ed25519 keypair if the file does not exist yet. Without a path the node
+/// runs with a throw-away identity.
+pub fn load_or_generate_identity(path: Option<&Path>) -> Result<identity::Keypair, Box<dyn Error>> {
+    let path = match path {
+        Some(path) => path,
+        None => return Ok(identity::Keypair::generate_ed25519()),
+    };
+
+    if path.exists() {
+        let bytes = fs::read(path)?;
+        let keypair = identity::Keypair::from_protobuf_encoding(&bytes)
+            .map_err(|e| format!("Invalid identity file {}: {e}", path.display()))?;
+        return Ok(keypair);
+    }
+
+    let keypair = identity::Keypair::generate_ed25519();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    write_private_file(path, &keypair.to_protobuf_encoding()?)?;
+    println!("Generated new identity at {}", path.display());
+
+    Ok(keypair)
+}
@@ -1,4 +1,4 @@
-// this file is copied in from rust-libp2p/examples/chat-example (libp2p-v0.51.1) 
+// this file is copied in from rust-libp2p/examples/chat-example (libp2p-v0.51.1)
 // for building a demo of the zkPath protocol
 
 // Copyright 2018 Parity Technologies (UK) Ltd.
@@ -27,7 +27,7 @@
 //! Using two terminal windows, start two instances, typing the following in each:
 //!
 //! ```sh
-//! cargo run
+//! cargo run -- run
 //! ```
 //!
 //! Mutual mDNS discovery may take a few seconds. When each peer does discover the other
@@ -47,111 +47,174 @@
 //!
 //! If a participant exits (Control-C or otherwise) the other peers will receive an mDNS expired
 //! event and remove the expired peer from the list of known peers.
-
-use async_std::io;
-use futures::{prelude::*, select};
-use libp2p::{
-    gossipsub, identity, mdns, swarm::NetworkBehaviour, swarm::SwarmEvent, PeerId, Swarm,
-};
-use std::collections::hash_map::DefaultHasher;
+//!
+//! Long-lived nodes are configured with a TOML file (see [`config::Config`]) passed with
+//! `--config`; any command line flag overrides the value from the file:
+//!
+//! ```toml
+//! listen_addresses = ["/ip4/0.0.0.0/tcp/4001"]
+//! bootstrap_peers = ["/ip4/192.168.1.10/tcp/4001/p2p/12D3KooW..."]
+//! topics = ["test-net"]
+//! heartbeat_interval_secs = 10
+//! identity_path = "node/identity.key"
+//! wallet_path = "node/wallet"
+//!
+//! [gating]
+//! require_voucher = false
+//! max_degree = 3
+//! trusted_origins = ["<origin as printed by circle id>"]
+//! ```
+//!
+//! Peers announce their voucher on the `circle/vouchers` topic, bound to their peer id by a
+//! signature of their locus key (see [`binding`]). The degree of a verified
+//! voucher becomes the gossipsub application score of the peer (see [`scoring`]). Only
+//! vouchers from one of the `trusted_origins` count, anyone can become an origin: origins
+//! score highest, every hop scores less, and peers without a valid voucher are graylisted
+//! once `voucher_grace_period_secs` has passed. The weights and thresholds live in the
+//! `[scoring]` section of the config file.
+//!
+//! Everything published is a CBOR [`envelope::Envelope`] carrying the message kind, the
+//! sender locus and a timestamp; see that module for the layout other tools can parse.
+//! `/request-voucher` publishes a vouch request for the locus of this node.
+//!
+//! `/revoke [<issuer>] <locus>` signs a revocation of the edge `issuer -> locus` and gossips
+//! it on `circle/revocations`. The issuer defaults to this node, which attaches the voucher
+//! it issued to `<locus>` (kept in the `issued` directory of the wallet by `vouch`); only
+//! an origin can revoke edges issued by others. Edges further up a path cannot be seen, so
+//! a revocation refuses every peer whose path may run through the edge in the epoch it was
+//! issued in; the origin then reissues the vouchers it still trusts in a later epoch with
+//! `circle vouch --origin --epoch <epoch>` (see [`revocation`]). Only notices for the trees
+//! of trusted origins are accepted, a bounded number per peer and minute. Nodes sync a bounded number of revocations from every peer
+//! they connect to, at most once a minute per peer.
+//!
+//! Typing `/dm <locus> <message>` sends an end-to-end encrypted message to the peer that
+//! presented a voucher for `<locus>` (see [`direct`]). Both peers must hold a voucher
+//! from a trusted origin within `max_degree`, otherwise the message is refused.
+//!
+//! Besides `run` there are `id` (print the peer id and locus), `peers` (list the peers found
+//! within `--wait` seconds) and `vouch` (become an origin with `--origin`, in a later epoch
+//! with `--epoch`, or extend the
+//! wallet voucher to another locus with `circle vouch <locus> --out voucher.bin`; the
+//! receiver copies that file to `voucher.bin` in their wallet).
+
+use clap::Parser;
+use dos::capabilities::Capabilities;
+use dos::circuit_builder::DEFAULT_MAX_DEGREE;
+use dos::epoch::DEFAULT_EPOCH_SCOPE;
+use dos::voucher::Voucher;
 use std::error::Error;
-use std::hash::{Hash, Hasher};
+use std::fs;
 use std::time::Duration;
 
+mod binding;
+mod cli;
+mod config;
+mod direct;
+mod envelope;
+mod identity;
+mod node;
+mod revocation;
+mod scoring;
+mod wallet;
+
+use cli::{Cli, Command, VouchArgs};
+use config::Config;
+use wallet::{decode_key, encode_key, Wallet};
+
 #[async_std::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    // Create a random PeerId
-    let local_key = identity::Keypair::generate_ed25519();
-    let local_peer_id = PeerId::from(local_key.public());
-    println!("Local peer id: {local_peer_id}");
-
-    // Set up an encrypted DNS-enabled TCP Transport over the Mplex protocol.
-    let transport = libp2p::development_transport(local_key.clone()).await?;
-
-    // We create a custom network behaviour that combines Gossipsub and Mdns.
-    #[derive(NetworkBehaviour)]
-    struct MyBehaviour {
-        gossipsub: gossipsub::Behaviour,
-        mdns: mdns::async_io::Behaviour,
+    let cli = Cli::parse();
+    let config = Config::load(&cli.node)?;
+
+    match cli.command {
+        Command::Run => {
+            let local_key = identity::load_or_generate_identity(config.identity_path.as_deref())?;
+            let (locus_key, voucher) = match &config.wallet_path {
+                Some(wallet_path) => {
+                    let wallet = Wallet::open(wallet_path)?;
+                    (Some(wallet.locus_key_pair()?), wallet.voucher()?)
+                }
+                None => (None, None),
+            };
+            node::run(&config, local_key, locus_key, voucher).await
+        }
+        Command::Id => id(&config),
+        Command::Peers { wait } => {
+            let local_key = identity::load_or_generate_identity(config.identity_path.as_deref())?;
+            node::peers(&config, local_key, Duration::from_secs(wait)).await
+        }
+        Command::Vouch(args) => vouch(&config, args),
     }
+}
 
-    // To content-address message, we can take the hash of message and use it as an ID.
-    let message_id_fn = |message: &gossipsub::Message| {
-        let mut s = DefaultHasher::new();
-        message.data.hash(&mut s);
-        gossipsub::MessageId::from(s.finish().to_string())
-    };
-
-    // Set a custom gossipsub configuration
-    let gossipsub_config = gossipsub::ConfigBuilder::default()
-        .heartbeat_interval(Duration::from_secs(10)) // This is set to aid debugging by not cluttering the log space
-        .validation_mode(gossipsub::ValidationMode::Strict) // This sets the kind of message validation. The default is Strict (enforce message signing)
-        .message_id_fn(message_id_fn) // content-address messages. No two messages of the same content will be propagated.
-        .build()
-        .expect("Valid config");
-
-    // build a gossipsub network behaviour
-    let mut gossipsub = gossipsub::Behaviour::new(
-        gossipsub::MessageAuthenticity::Signed(local_key),
-        gossipsub_config,
-    )
-    .expect("Correct configuration");
-
-    // Create a Gossipsub topic
-    let topic = gossipsub::IdentTopic::new("test-net");
-
-    // subscribes to our topic
-    gossipsub.subscribe(&topic)?;
-
-    // Create a Swarm to manage peers and events
-    let mut swarm = {
-        let mdns = mdns::async_io::Behaviour::new(mdns::Config::default(), local_peer_id)?;
-        let behaviour = MyBehaviour { gossipsub, mdns };
-        Swarm::with_async_std_executor(transport, behaviour, local_peer_id)
-    };
-
-    // Read full lines from stdin
-    let mut stdin = io::BufReader::new(io::stdin()).lines().fuse();
-
-    // Listen on all interfaces and whatever port the OS assigns
-    swarm.listen_on("/ip4/0.0.0.0/tcp/0".parse()?)?;
-
-    println!("Enter messages via STDIN and they will be sent to connected peers using Gossipsub");
-
-    // Kick it off
-    loop {
-        select! {
-            line = stdin.select_next_some() => {
-                if let Err(e) = swarm
-                    .behaviour_mut().gossipsub
-                    .publish(topic.clone(), line.expect("Stdin not to close").as_bytes()) {
-                    println!("Publish error: {e:?}");
-                }
-            },
-            event = swarm.select_next_some() => match event {
-                SwarmEvent::Behaviour(MyBehaviourEvent::Mdns(mdns::Event::Discovered(list))) => {
-                    for (peer_id, _multiaddr) in list {
-                        println!("mDNS discovered a new peer: {peer_id}");
-                        swarm.behaviour_mut().gossipsub.add_explicit_peer(&peer_id);
-                    }
-                },
-                SwarmEvent::Behaviour(MyBehaviourEvent::Mdns(mdns::Event::Expired(list))) => {
-                    for (peer_id, _multiaddr) in list {
-                        println!("mDNS discover peer has expired: {peer_id}");
-                        swarm.behaviour_mut().gossipsub.remove_explicit_peer(&peer_id);
-                    }
-                },
-                SwarmEvent::Behaviour(MyBehaviourEvent::Gossipsub(gossipsub::Event::Message {
-                    propagation_source: peer_id,
-                    message_id: id,
-                    message,
-                })) => println!(
-                        "Got message: '{}' with id: {id} from peer: {peer_id}",
-                        String::from_utf8_lossy(&message.data),
-                    ),
-                _ => {}
-            }
+fn id(config: &Config) -> Result<(), Box<dyn Error>> {
+    let local_key = identity::load_or_generate_identity(config.identity_path.as_deref())?;
+    println!("Peer id: {}", local_key.public().to_peer_id());
+
+    if let Some(wallet_path) = &config.wallet_path {
+        let wallet = Wallet::open(wallet_path)?;
+        println!(
+            "Locus: {}",
+            encode_key(&wallet.locus_key_pair()?.public_key)
+        );
+        match wallet.voucher()? {
+            Some(voucher) => println!(
+                "Voucher: degree {} from origin {}",
+                voucher.degree(),
+                encode_key(&voucher.origin())
+            ),
+            None => println!("Voucher: none"),
         }
     }
+
+    Ok(())
 }
 
+fn vouch(config: &Config, args: VouchArgs) -> Result<(), Box<dyn Error>> {
+    let wallet_path = config
+        .wallet_path
+        .as_ref()
+        .ok_or("A wallet path is required to vouch")?;
+    let wallet = Wallet::open(wallet_path)?;
+    let key_pair = wallet.locus_key_pair()?;
+
+    if args.origin {
+        let voucher = Voucher::new_origin_in_epoch(
+            key_pair.public_key,
+            key_pair.private_key,
+            Capabilities::ALL,
+            DEFAULT_MAX_DEGREE,
+            DEFAULT_EPOCH_SCOPE,
+            args.epoch,
+        )?;
+        wallet.store_voucher(&voucher)?;
+        println!(
+            "Stored origin voucher for {} in epoch {}",
+            encode_key(&key_pair.public_key),
+            args.epoch
+        );
+        return Ok(());
+    }
+
+    let locus = decode_key(args.locus.as_deref().expect("clap requires a locus"))?;
+    let out = args.out.expect("clap requires an output file");
+    let voucher = wallet
+        .voucher()?
+        .ok_or("The wallet holds no voucher to extend")?;
+
+    let extended_voucher = voucher.extend_voucher_with_capabilities(
+        key_pair.private_key,
+        locus,
+        voucher.capabilities(),
+    )?;
+    fs::write(&out, extended_voucher.to_bytes())?;
+    wallet.store_issued_voucher(&extended_voucher)?;
+    println!(
+        "Wrote degree {} voucher for {} to {}",
+        extended_voucher.degree(),
+        encode_key(&locus),
+        out.display()
+    );
+
+    Ok(())
+}
@@ -0,0 +1,759 @@
+use async_std::{io, task};
+use dos::voucher::Voucher;
+use futures::{prelude::*, select, stream::FuturesUnordered};
+use libp2p::{
+    gossipsub, identity, mdns, multiaddr::Protocol, request_response, swarm::NetworkBehaviour,
+    swarm::SwarmBuilder, swarm::SwarmEvent, PeerId, Swarm,
+};
+use plonky2::field::types::PrimeField64;
+use simple_crypto::{KeyPair, PublicKey};
+use std::collections::HashSet;
+use std::error::Error;
+use std::iter;
+use std::time::{Duration, Instant};
+
+use crate::binding::PeerBinding;
+use crate::config::Config;
+use crate::direct::{
+    DirectMessage, DirectMessageCodec, DirectMessageProtocol, DirectMessageResponse,
+};
+use crate::envelope::{message_id, Envelope, MessageKind};
+use crate::revocation::{
+    RateLimit, RevocationNotice, RevocationSet, RevocationSyncCodec, RevocationSyncProtocol,
+    RevocationSyncRequest, RevocationSyncResponse, RevokedEdge, REVOCATION_TOPIC,
+};
+use crate::scoring::{peer_score_params, peer_score_thresholds, PeerVouchers, VerifiedVoucher};
+use crate::wallet::{decode_key, encode_key, Wallet};
+
+/// Topic on which every node announces its voucher.
+pub const VOUCHER_TOPIC: &str = "circle/vouchers";
+
+/// Vouchers and revocation notices carry proofs, which are well over the 64 KiB gossipsub
+/// allows by default.
+const MAX_TRANSMIT_SIZE: usize = 1024 * 1024;
+
+/// How many voucher announcements are verified at once, verifying one rebuilds the
+/// circuits of its degree (once) and checks a proof.
+const MAX_PENDING_VERIFICATIONS: usize = 8;
+
+/// How often peers that never presented a voucher are checked for.
+const GRACE_PERIOD_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+// We create a custom network behaviour that combines Gossipsub, Mdns and direct messages.
+#[derive(NetworkBehaviour)]
+pub struct CircleBehaviour {
+    gossipsub: gossipsub::Behaviour,
+    mdns: mdns::async_io::Behaviour,
+    direct: request_response::Behaviour<DirectMessageCodec>,
+    revocation_sync: request_response::Behaviour<RevocationSyncCodec>,
+}
+
+/// Build the swarm for `config`: subscribe to the configured topics,
+/// start listening and dial the bootstrap peers.
+pub async fn build_swarm(
+    config: &Config,
+    local_key: identity::Keypair,
+) -> Result<(Swarm<CircleBehaviour>, Vec<gossipsub::IdentTopic>), Box<dyn Error>> {
+    let local_peer_id = PeerId::from(local_key.public());
+
+    // Set up an encrypted DNS-enabled TCP Transport over the Mplex protocol.
+    let transport = libp2p::development_transport(local_key.clone()).await?;
+
+    // Set a custom gossipsub configuration
+    let gossipsub_config = gossipsub::ConfigBuilder::default()
+        .heartbeat_interval(config.heartbeat_interval())
+        .max_transmit_size(MAX_TRANSMIT_SIZE)
+        .validation_mode(gossipsub::ValidationMode::Strict) // This sets the kind of message validation. The default is Strict (enforce message signing)
+        .message_id_fn(message_id) // content-address messages by their SHA-256. No two messages of the same content will be propagated.
+        .build()
+        .expect("Valid config");
+
+    // build a gossipsub network behaviour
+    let mut gossipsub = gossipsub::Behaviour::new(
+        gossipsub::MessageAuthenticity::Signed(local_key),
+        gossipsub_config,
+    )
+    .expect("Correct configuration");
+
+    // peers are scored by the degree of their voucher, see `PeerVouchers`
+    gossipsub.with_peer_score(
+        peer_score_params(&config.scoring),
+        peer_score_thresholds(&config.scoring),
+    )?;
+
+    // subscribes to our topics
+    let topics: Vec<gossipsub::IdentTopic> = config
+        .topics
+        .iter()
+        .map(gossipsub::IdentTopic::new)
+        .collect();
+    for topic in &topics {
+        gossipsub.subscribe(topic)?;
+    }
+    gossipsub.subscribe(&gossipsub::IdentTopic::new(VOUCHER_TOPIC))?;
+    gossipsub.subscribe(&gossipsub::IdentTopic::new(REVOCATION_TOPIC))?;
+
+    // Create a Swarm to manage peers and events
+    let mut swarm = {
+        let mdns = mdns::async_io::Behaviour::new(mdns::Config::default(), local_peer_id)?;
+        let direct = request_response::Behaviour::new(
+            DirectMessageCodec,
+            iter::once((
+                DirectMessageProtocol,
+                request_response::ProtocolSupport::Full,
+            )),
+            request_response::Config::default(),
+        );
+        let revocation_sync = request_response::Behaviour::new(
+            RevocationSyncCodec,
+            iter::once((
+                RevocationSyncProtocol,
+                request_response::ProtocolSupport::Full,
+            )),
+            request_response::Config::default(),
+        );
+        let behaviour = CircleBehaviour {
+            gossipsub,
+            mdns,
+            direct,
+            revocation_sync,
+        };
+        SwarmBuilder::with_async_std_executor(transport, behaviour, local_peer_id).build()
+    };
+
+    for address in config.listen_multiaddrs()? {
+        swarm.listen_on(address)?;
+    }
+
+    for address in config.bootstrap_multiaddrs()? {
+        if let Some(Protocol::P2p(multihash)) = address.iter().last() {
+            let peer_id = PeerId::from_multihash(multihash)
+                .map_err(|_| format!("Invalid peer id in bootstrap address {address}"))?;
+            swarm.behaviour_mut().gossipsub.add_explicit_peer(&peer_id);
+        }
+        if let Err(e) = swarm.dial(address.clone()) {
+            println!("Failed to dial bootstrap peer {address}: {e}");
+        }
+    }
+
+    Ok((swarm, topics))
+}
+
+/// Relay stdin as chat envelopes to the first configured topic and print messages from
+/// all topics. A line of the form `/dm <locus> <message>` is instead sent encrypted to
+/// the peer that presented a voucher for `<locus>`, `/request-voucher` asks the peers on
+/// the voucher topic to vouch for our locus, and `/revoke [<issuer>] <locus>` revokes an
+/// edge we issued (or, as origin, any edge of our tree).
+///
+/// Our voucher (if any) is announced to every peer that joins the voucher topic, and
+/// the vouchers announced by other peers are verified and turned into peer scores.
+/// Peers whose path may run through a revoked edge are refused, see [`RevocationSet`].
+pub async fn run(
+    config: &Config,
+    local_key: identity::Keypair,
+    locus_key: Option<KeyPair>,
+    voucher: Option<Voucher>,
+) -> Result<(), Box<dyn Error>> {
+    let locus = locus_key.as_ref().map(|key_pair| key_pair.public_key);
+    let local_peer_id = PeerId::from(local_key.public());
+    println!("Local peer id: {local_peer_id}");
+    let (mut swarm, topics) = build_swarm(config, local_key.clone()).await?;
+    let publish_topic = topics[0].clone();
+    let voucher_topic = gossipsub::IdentTopic::new(VOUCHER_TOPIC);
+    let revocation_topic = gossipsub::IdentTopic::new(REVOCATION_TOPIC);
+    let trusted_origins = config.gating.trusted_origin_keys()?;
+    // direct messages are only exchanged if we hold a voucher the policy accepts ourselves
+    let may_message_directly = voucher.as_ref().map_or(false, |voucher| {
+        voucher.degree().to_canonical_u64() <= config.gating.max_degree
+            && trusted_origins.contains(&voucher.origin())
+    });
+    // built once, so that repeated announcements are recognised as duplicates; the locus
+    // key binds the voucher to our peer id, so that no other peer can present it
+    let voucher_announcement =
+        voucher
+            .as_ref()
+            .zip(locus_key.as_ref())
+            .map(|(voucher, key_pair)| {
+                let binding = PeerBinding::new(&local_peer_id, key_pair.private_key);
+                Envelope::new(
+                    MessageKind::VoucherAnnouncement,
+                    locus.as_ref(),
+                    binding.to_bytes(),
+                )
+                .with_voucher(voucher.to_bytes())
+                .to_bytes()
+            });
+
+    let mut peer_vouchers = PeerVouchers::new(
+        config.scoring.clone(),
+        config.gating.clone(),
+        trusted_origins,
+    );
+    // proofs are verified off the event loop, the results come back here
+    let mut verifications = FuturesUnordered::new();
+    // the peers whose announcement is being verified, at most one each
+    let mut verifying = HashSet::new();
+    // notices we signed (to be published) or received (to be verified)
+    let mut pending_revocations = FuturesUnordered::new();
+    let mut revocations = RevocationSet::default();
+    // how often we answer sync requests of a peer, and send ours to it
+    let mut answered_syncs = RateLimit::syncs();
+    let mut requested_syncs = RateLimit::syncs();
+    // how many notices of a peer are verified
+    let mut verified_notices = RateLimit::notices();
+    let mut grace_period_check = Box::pin(task::sleep(GRACE_PERIOD_CHECK_INTERVAL)).fuse();
+
+    // Read full lines from stdin
+    let mut stdin = io::BufReader::new(io::stdin()).lines().fuse();
+
+    println!(
+        "Enter messages via STDIN and they will be sent to connected peers on topic {publish_topic}"
+    );
+
+    // Kick it off
+    loop {
+        select! {
+            line = stdin.select_next_some() => {
+                let line = line.expect("Stdin not to close");
+                if let Some(direct_message) = line.strip_prefix("/dm ") {
+                    if let Err(e) = send_direct_message(
+                        &mut swarm,
+                        &peer_vouchers,
+                        may_message_directly,
+                        direct_message,
+                    ) {
+                        println!("Direct message error: {e}");
+                    }
+                } else if let Some(edge) = line.strip_prefix("/revoke ") {
+                    match revoked_edge(config, edge, locus, voucher.as_ref()) {
+                        Ok((edge, issued_voucher)) => {
+                            if issued_voucher.is_none() {
+                                println!(
+                                    "This ends epoch {} for the tree below degree one, reissue the vouchers you still trust with: circle vouch --origin --epoch {}",
+                                    edge.epoch,
+                                    edge.epoch + 1
+                                );
+                            }
+                            let private_key = locus_key.as_ref().expect("checked by revoked_edge").private_key;
+                            pending_revocations.push(task::spawn_blocking(move || {
+                                let notice = match issued_voucher {
+                                    Some(issued_voucher) => RevocationNotice::from_voucher(&issued_voucher, private_key),
+                                    None => RevocationNotice::new(edge, private_key),
+                                };
+                                (true, Some(notice))
+                            }));
+                        }
+                        Err(e) => println!("Revocation error: {e}"),
+                    }
+                } else if line == "/request-voucher" {
+                    match locus {
+                        Some(locus) => {
+                            let request =
+                                Envelope::new(MessageKind::VouchRequest, Some(&locus), Vec::new());
+                            if let Err(e) = swarm
+                                .behaviour_mut().gossipsub
+                                .publish(voucher_topic.clone(), request.to_bytes()) {
+                                println!("Publish error: {e:?}");
+                            }
+                        }
+                        None => println!("A wallet is required to request a voucher"),
+                    }
+                } else {
+                    let chat = Envelope::new(MessageKind::Chat, locus.as_ref(), line.into_bytes());
+                    if let Err(e) = swarm
+                        .behaviour_mut().gossipsub
+                        .publish(publish_topic.clone(), chat.to_bytes()) {
+                        println!("Publish error: {e:?}");
+                    }
+                }
+            },
+            (peer_id, verified_voucher) = verifications.select_next_some() => {
+                verifying.remove(&peer_id);
+                let score = peer_vouchers.voucher_verified(peer_id, verified_voucher);
+                swarm.behaviour_mut().gossipsub.set_application_score(&peer_id, score);
+                match verified_voucher {
+                    Some(voucher) => println!(
+                        "Peer {peer_id} presented a degree {} voucher for locus {}",
+                        voucher.degree,
+                        encode_key(&voucher.locus)
+                    ),
+                    None => println!("Peer {peer_id} presented an invalid voucher"),
+                }
+                apply_revocations(&mut swarm, &mut peer_vouchers, &revocations);
+            },
+            (local, notice) = pending_revocations.select_next_some() => {
+                let Some(notice) = notice else {
+                    println!("Dropped revocation notice with an invalid signature");
+                    continue;
+                };
+                if !revocations.insert(&notice) {
+                    continue;
+                }
+                println!(
+                    "Revoked edge {} -> {} in epoch {}",
+                    encode_key(&notice.edge.issuer),
+                    encode_key(&notice.edge.locus),
+                    notice.edge.epoch
+                );
+                if local {
+                    let envelope =
+                        Envelope::new(MessageKind::RevocationNotice, locus.as_ref(), notice.to_bytes());
+                    if let Err(e) = swarm
+                        .behaviour_mut().gossipsub
+                        .publish(revocation_topic.clone(), envelope.to_bytes()) {
+                        println!("Publish error: {e:?}");
+                    }
+                }
+                apply_revocations(&mut swarm, &mut peer_vouchers, &revocations);
+            },
+            _ = grace_period_check => {
+                for (peer_id, score) in peer_vouchers.expire_pending(Instant::now()) {
+                    println!("Peer {peer_id} did not present a voucher in time");
+                    swarm.behaviour_mut().gossipsub.set_application_score(&peer_id, score);
+                }
+                grace_period_check = Box::pin(task::sleep(GRACE_PERIOD_CHECK_INTERVAL)).fuse();
+            },
+            event = swarm.select_next_some() => match event {
+                SwarmEvent::NewListenAddr { address, .. } => {
+                    println!("Listening on {address}");
+                },
+                SwarmEvent::ConnectionEstablished { peer_id, num_established, .. }
+                    if num_established.get() == 1 => {
+                    // catch up on the revocations gossiped before we joined, but not every
+                    // time a peer reconnects
+                    if requested_syncs.allow(peer_id, Instant::now()) {
+                        swarm.behaviour_mut().revocation_sync.send_request(&peer_id, RevocationSyncRequest);
+                    }
+                },
+                SwarmEvent::Behaviour(CircleBehaviourEvent::Gossipsub(gossipsub::Event::Subscribed {
+                    peer_id,
+                    topic,
+                })) => {
+                    peer_vouchers.peer_seen(peer_id);
+                    if topic == voucher_topic.hash() {
+                        if let Some(voucher_announcement) = &voucher_announcement {
+                            announce_voucher(&mut swarm, &voucher_topic, voucher_announcement);
+                        }
+                    }
+                },
+                SwarmEvent::Behaviour(CircleBehaviourEvent::Gossipsub(gossipsub::Event::Message {
+                    message,
+                    ..
+                })) if message.topic == voucher_topic.hash() => {
+                    // the voucher belongs to the (signed) author, not to whoever relayed it
+                    let Some(source) = message.source else { continue };
+                    let envelope = match Envelope::from_bytes(&message.data) {
+                        Ok(envelope) => envelope,
+                        Err(e) => {
+                            println!("Dropped message on {} from peer {source}: {e}", message.topic);
+                            continue;
+                        }
+                    };
+                    match envelope.kind {
+                        MessageKind::VoucherAnnouncement => {
+                            if peer_vouchers.is_revoked(&source)
+                                || verifying.contains(&source)
+                                || verifying.len() >= MAX_PENDING_VERIFICATIONS {
+                                println!("Dropped voucher announcement from peer {source}");
+                                continue;
+                            }
+                            // refuse vouchers beyond the policy before decoding their proof
+                            let degree = envelope
+                                .voucher_bytes()
+                                .and_then(|bytes| Voucher::encoded_degree(bytes).ok());
+                            if !degree.map_or(false, |degree| degree <= config.gating.max_degree) {
+                                let score = peer_vouchers.voucher_verified(source, None);
+                                swarm.behaviour_mut().gossipsub.set_application_score(&source, score);
+                                println!("Peer {source} presented a voucher beyond degree {}", config.gating.max_degree);
+                                continue;
+                            }
+                            verifying.insert(source);
+                            verifications.push(task::spawn_blocking(move || {
+                                (source, verify_announcement(&envelope, &source))
+                            }));
+                        }
+                        MessageKind::VouchRequest => match envelope.sender_locus() {
+                            Ok(Some(locus)) => println!(
+                                "Peer {source} asks for a voucher, vouch with: circle vouch {} --out voucher.bin",
+                                encode_key(&locus)
+                            ),
+                            _ => println!("Peer {source} asks for a voucher without a locus"),
+                        },
+                        kind => println!("Ignored {kind:?} from peer {source}"),
+                    }
+                },
+                SwarmEvent::Behaviour(CircleBehaviourEvent::Gossipsub(gossipsub::Event::Message {
+                    message,
+                    ..
+                })) if message.topic == revocation_topic.hash() => {
+                    let Some(source) = message.source else { continue };
+                    match Envelope::from_bytes(&message.data) {
+                        Ok(envelope) if envelope.kind == MessageKind::RevocationNotice => {
+                            verify_revocation(
+                                &mut pending_revocations,
+                                &revocations,
+                                &peer_vouchers,
+                                &mut verified_notices,
+                                source,
+                                &envelope.body,
+                            );
+                        }
+                        Ok(envelope) => println!("Ignored {:?} on {}", envelope.kind, message.topic),
+                        Err(e) => println!("Dropped message on {}: {e}", message.topic),
+                    }
+                },
+                SwarmEvent::Behaviour(CircleBehaviourEvent::Gossipsub(gossipsub::Event::Message {
+                    message,
+                    ..
+                })) if message.source.map_or(false, |source| peer_vouchers.is_revoked(&source)) => {
+                    println!("Dropped message on {} from revoked peer", message.topic);
+                },
+                SwarmEvent::Behaviour(CircleBehaviourEvent::Gossipsub(gossipsub::Event::Message {
+                    message,
+                    ..
+                })) if config.gating.require_voucher
+                    && !message.source.map_or(false, |source| peer_vouchers.is_vouched(&source)) => {
+                    println!("Dropped message on {} from unvouched peer", message.topic);
+                },
+                SwarmEvent::Behaviour(CircleBehaviourEvent::Direct(request_response::Event::Message {
+                    peer,
+                    message: request_response::Message::Request { request, channel, .. },
+                })) => {
+                    let response = if may_message_directly && peer_vouchers.is_vouched(&peer) {
+                        match request.decrypt(&local_key) {
+                            Ok(plaintext) => {
+                                println!(
+                                    "Direct message from peer {peer}: '{}'",
+                                    String::from_utf8_lossy(&plaintext)
+                                );
+                                DirectMessageResponse::Accepted
+                            }
+                            Err(e) => {
+                                println!("Undecryptable direct message from peer {peer}: {e}");
+                                DirectMessageResponse::Rejected
+                            }
+                        }
+                    } else {
+                        println!("Rejected direct message from unvouched peer {peer}");
+                        DirectMessageResponse::Rejected
+                    };
+                    // the sender may already have given up on the response
+                    let _ = swarm.behaviour_mut().direct.send_response(channel, response);
+                },
+                SwarmEvent::Behaviour(CircleBehaviourEvent::RevocationSync(request_response::Event::Message {
+                    peer,
+                    message: request_response::Message::Request { channel, .. },
+                })) => {
+                    if !answered_syncs.allow(peer, Instant::now()) {
+                        // dropping the channel lets the request time out
+                        println!("Ignored revocation sync request from peer {peer}, it synced recently");
+                        continue;
+                    }
+                    let response = RevocationSyncResponse(revocations.encoded_notices());
+                    // the requester may already have disconnected
+                    let _ = swarm.behaviour_mut().revocation_sync.send_response(channel, response);
+                },
+                SwarmEvent::Behaviour(CircleBehaviourEvent::RevocationSync(request_response::Event::Message {
+                    peer,
+                    message: request_response::Message::Response { response: RevocationSyncResponse(notices), .. },
+                })) => {
+                    for notice in notices {
+                        verify_revocation(
+                            &mut pending_revocations,
+                            &revocations,
+                            &peer_vouchers,
+                            &mut verified_notices,
+                            peer,
+                            &notice,
+                        );
+                    }
+                },
+                SwarmEvent::Behaviour(event) => handle_behaviour_event(&mut swarm, &peer_vouchers, event),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Verify the voucher attached to an announcement by `source`.
+/// The voucher must be for the locus the envelope claims to come from, and the body must
+/// bind that locus to `source`, otherwise any peer could replay the voucher.
+fn verify_announcement(envelope: &Envelope, source: &PeerId) -> Option<VerifiedVoucher> {
+    let voucher = Voucher::from_bytes(envelope.voucher_bytes()?).ok()?;
+    if envelope.sender_locus().ok()? != Some(voucher.locus()) {
+        return None;
+    }
+    if !PeerBinding::from_bytes(&envelope.body)
+        .ok()?
+        .verify(source, &voucher.locus())
+    {
+        return None;
+    }
+    Some(VerifiedVoucher {
+        origin: voucher.origin(),
+        issuer: voucher.issuer(),
+        locus: voucher.locus(),
+        degree: voucher.degree().to_canonical_u64(),
+        epoch_scope: voucher.epoch_scope(),
+        epoch: voucher.epoch(),
+    })
+}
+
+/// Queue the verification of an encoded notice from `source` we do not know yet. Only
+/// notices for the trees of trusted origins are verified, and only a bounded number per
+/// `source`, anyone can sign notices for an origin of its own.
+fn verify_revocation(
+    pending_revocations: &mut FuturesUnordered<task::JoinHandle<(bool, Option<RevocationNotice>)>>,
+    revocations: &RevocationSet,
+    peer_vouchers: &PeerVouchers,
+    verified_notices: &mut RateLimit,
+    source: PeerId,
+    notice_bytes: &[u8],
+) {
+    let notice = match RevocationNotice::from_bytes(notice_bytes) {
+        Ok(notice) => notice,
+        Err(e) => {
+            println!("Dropped malformed revocation notice from peer {source}: {e}");
+            return;
+        }
+    };
+    if revocations.contains(&notice.edge) {
+        return;
+    }
+    if !peer_vouchers.is_trusted_origin(&notice.edge.origin) {
+        println!("Dropped revocation notice for an untrusted origin from peer {source}");
+        return;
+    }
+    if !verified_notices.allow(source, Instant::now()) {
+        println!("Dropped revocation notice from peer {source}, it sent too many");
+        return;
+    }
+    pending_revocations.push(task::spawn_blocking(move || {
+        let valid = notice.verify();
+        (false, valid.then_some(notice))
+    }));
+}
+
+/// Refuse the peers whose path may run through a revoked edge.
+fn apply_revocations(
+    swarm: &mut Swarm<CircleBehaviour>,
+    peer_vouchers: &mut PeerVouchers,
+    revocations: &RevocationSet,
+) {
+    for (peer_id, score) in peer_vouchers.revoke(revocations) {
+        println!("Peer {peer_id} has a revoked voucher");
+        swarm
+            .behaviour_mut()
+            .gossipsub
+            .set_application_score(&peer_id, score);
+    }
+}
+
+/// Parse `[<issuer>] <locus>` into an edge of our tree we are allowed to revoke: any edge
+/// if we are the origin, otherwise only edges we issued ourselves, for which the voucher we
+/// issued (kept in the wallet) is returned to show the edge is ours. The edge is revoked in
+/// the epoch of our voucher, or of the voucher we issued.
+fn revoked_edge(
+    config: &Config,
+    edge: &str,
+    locus: Option<PublicKey>,
+    voucher: Option<&Voucher>,
+) -> Result<(RevokedEdge, Option<Voucher>), Box<dyn Error>> {
+    let locus = locus.ok_or("A wallet is required to revoke")?;
+    let voucher = voucher.ok_or("A voucher is required to revoke")?;
+    let origin = voucher.origin();
+    let keys = edge
+        .split_whitespace()
+        .map(decode_key)
+        .collect::<Result<Vec<_>, _>>()?;
+    let (issuer, revoked_locus) = match keys[..] {
+        [revoked_locus] => (locus, revoked_locus),
+        [issuer, revoked_locus] => (issuer, revoked_locus),
+        _ => return Err("Usage: /revoke [<issuer>] <locus>".into()),
+    };
+    if origin == locus {
+        // we cannot see the degree of edges issued by others, degree one refuses every
+        // path that may run through the edge
+        let edge = RevokedEdge {
+            origin,
+            issuer,
+            locus: revoked_locus,
+            degree: 1,
+            epoch_scope: voucher.epoch_scope(),
+            epoch: voucher.epoch(),
+        };
+        return Ok((edge, None));
+    }
+    if issuer != locus {
+        return Err("Only the origin can revoke edges issued by others".into());
+    }
+    let wallet_path = config
+        .wallet_path
+        .as_ref()
+        .ok_or("A wallet is required to revoke")?;
+    let issued_voucher = Wallet::open(wallet_path)?
+        .issued_voucher(&revoked_locus)?
+        .ok_or("The wallet holds no voucher we issued to this locus")?;
+    let edge = RevokedEdge::of(&issued_voucher);
+    if edge.origin != origin || edge.issuer != issuer {
+        return Err("The voucher we issued to this locus is from another tree".into());
+    }
+    Ok((edge, Some(issued_voucher)))
+}
+
+/// Encrypt and send `<locus> <message>` to the peer that claimed that locus with a voucher
+/// bound to its peer id, which the message is encrypted to.
+fn send_direct_message(
+    swarm: &mut Swarm<CircleBehaviour>,
+    peer_vouchers: &PeerVouchers,
+    may_message_directly: bool,
+    direct_message: &str,
+) -> Result<(), Box<dyn Error>> {
+    if !may_message_directly {
+        return Err("Direct messages need a voucher within the configured degree".into());
+    }
+    let (locus, text) = direct_message
+        .split_once(' ')
+        .ok_or("Usage: /dm <locus> <message>")?;
+    let locus = decode_key(locus)?;
+    let peer_id = peer_vouchers
+        .peer_for_locus(&locus)
+        .ok_or("No vouched peer is known for this locus")?;
+
+    let message = DirectMessage::encrypt(&peer_id, text.as_bytes())?;
+    swarm.behaviour_mut().direct.send_request(&peer_id, message);
+    Ok(())
+}
+
+fn announce_voucher(
+    swarm: &mut Swarm<CircleBehaviour>,
+    voucher_topic: &gossipsub::IdentTopic,
+    voucher_announcement: &[u8],
+) {
+    match swarm
+        .behaviour_mut()
+        .gossipsub
+        .publish(voucher_topic.clone(), voucher_announcement)
+    {
+        // announced recently enough for the message to be in the seen cache of our peers
+        Ok(_) | Err(gossipsub::PublishError::Duplicate) => {}
+        Err(e) => println!("Failed to announce voucher: {e:?}"),
+    }
+}
+
+/// Run the node for `wait` and list the peers it found.
+pub async fn peers(
+    config: &Config,
+    local_key: identity::Keypair,
+    wait: Duration,
+) -> Result<(), Box<dyn Error>> {
+    let (mut swarm, _topics) = build_swarm(config, local_key).await?;
+    let mut deadline = Box::pin(task::sleep(wait)).fuse();
+    // no vouchers are verified here, so chat senders are all shown as unverified
+    let peer_vouchers = PeerVouchers::new(
+        config.scoring.clone(),
+        config.gating.clone(),
+        HashSet::new(),
+    );
+
+    loop {
+        select! {
+            _ = deadline => break,
+            event = swarm.select_next_some() => {
+                if let SwarmEvent::Behaviour(event) = event {
+                    handle_behaviour_event(&mut swarm, &peer_vouchers, event);
+                }
+            }
+        }
+    }
+
+    let connected: HashSet<PeerId> = swarm.connected_peers().cloned().collect();
+    println!("Connected peers: {}", connected.len());
+    for (peer_id, topics) in swarm.behaviour().gossipsub.all_peers() {
+        let topics: Vec<String> = topics.iter().map(|topic| topic.to_string()).collect();
+        let status = if connected.contains(peer_id) {
+            "connected"
+        } else {
+            "known"
+        };
+        println!("{peer_id} ({status}) topics: [{}]", topics.join(", "));
+    }
+
+    Ok(())
+}
+
+/// Handle the events `run` and `peers` treat alike. Chat messages are shown with their
+/// signed author and, only if that peer presented a voucher for it, the locus of the
+/// envelope; anyone can put any locus in an envelope.
+fn handle_behaviour_event(
+    swarm: &mut Swarm<CircleBehaviour>,
+    peer_vouchers: &PeerVouchers,
+    event: CircleBehaviourEvent,
+) {
+    match event {
+        CircleBehaviourEvent::Mdns(mdns::Event::Discovered(list)) => {
+            for (peer_id, _multiaddr) in list {
+                println!("mDNS discovered a new peer: {peer_id}");
+                swarm.behaviour_mut().gossipsub.add_explicit_peer(&peer_id);
+            }
+        }
+        CircleBehaviourEvent::Mdns(mdns::Event::Expired(list)) => {
+            for (peer_id, _multiaddr) in list {
+                println!("mDNS discover peer has expired: {peer_id}");
+                swarm
+                    .behaviour_mut()
+                    .gossipsub
+                    .remove_explicit_peer(&peer_id);
+            }
+        }
+        CircleBehaviourEvent::Direct(request_response::Event::Message {
+            peer,
+            message:
+                request_response::Message::Response {
+                    response: DirectMessageResponse::Rejected,
+                    ..
+                },
+        }) => println!("Peer {peer} rejected our direct message"),
+        CircleBehaviourEvent::Direct(request_response::Event::OutboundFailure {
+            peer,
+            error,
+            ..
+        }) => println!("Failed to deliver direct message to peer {peer}: {error}"),
+        CircleBehaviourEvent::Gossipsub(gossipsub::Event::Message {
+            message_id: id,
+            message,
+            ..
+        }) => {
+            // the signed author, not whoever relayed the message
+            let peer_id = message
+                .source
+                .map_or_else(|| "unknown".to_string(), |source| source.to_string());
+            match Envelope::from_bytes(&message.data) {
+                Ok(envelope) if envelope.kind == MessageKind::Chat => {
+                    let sender = match (message.source, envelope.sender_locus()) {
+                        (Some(source), Ok(Some(locus)))
+                            if peer_vouchers.vouched_locus(&source) == Some(locus) =>
+                        {
+                            encode_key(&locus)
+                        }
+                        _ => "unverified".to_string(),
+                    };
+                    println!(
+                        "Got message on {}: '{}' with id: {id} from peer: {peer_id} ({sender})",
+                        message.topic,
+                        envelope.text(),
+                    )
+                }
+                Ok(envelope) => println!(
+                    "Ignored {:?} on {} with id: {id} from peer: {peer_id}",
+                    envelope.kind, message.topic
+                ),
+                Err(e) => println!(
+                    "Dropped message on {} from peer {peer_id}: {e}",
+                    message.topic
+                ),
+            }
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,568 @@
+//! Revocation of voucher edges.
+//!
+//! A [`RevocationNotice`] withdraws the edge `issuer -> locus` of the tree rooted at
+//! `origin`, as issued in an epoch of a scope of `dos::epoch`. The origin may revoke any edge of its tree. The issuer may only revoke the
+//! edges it issued, so its notices carry the revoked voucher, whose proof shows that the
+//! issuer vouched for the locus; otherwise anyone could sign itself in as the issuer of an
+//! edge into any locus. Notices are gossiped on [`REVOCATION_TOPIC`] inside
+//! [`crate::envelope::Envelope`]s, and a node that connects to a peer asks it for the
+//! notices it knows over the `/circle/revocations/1.0.0` request-response protocol, so late
+//! joiners catch up. Verifying a notice checks a proof, so nodes only verify notices for the
+//! trees of origins they trust, a bounded number per peer (see [`RateLimit`]), and keep a
+//! bounded number of them.
+//!
+//! Only the last edge of a path, and the edge into its issuer, are public, so a revoked
+//! edge further up a path cannot be seen. A revocation therefore ends its epoch for every
+//! path that may run through the edge: besides the voucher of its locus and the vouchers
+//! that locus issued, every voucher of the scope issued in that epoch (or earlier) whose
+//! degree is at least two above the edge is refused. An origin revocation names no degree
+//! and refuses everything beyond degree one. The origin then bumps the epoch of the scope
+//! and reissues the vouchers it still trusts (`circle vouch --origin --epoch <epoch>`).
+
+use async_trait::async_trait;
+use dos::signature::Signature;
+use dos::voucher::Voucher;
+use futures::prelude::*;
+use libp2p::core::upgrade::{read_length_prefixed, write_length_prefixed, ProtocolName};
+use libp2p::{request_response, PeerId};
+use plonky2::field::types::{Field, PrimeField64};
+use serde_bytes::ByteBuf;
+use simple_crypto::{
+    field_elements_from_bytes, field_elements_to_bytes, Digest, PrivateKey, PublicKey,
+    DIGEST_LENGTH, F, PUBLIC_KEY_LENGTH,
+};
+use std::collections::HashMap;
+use std::error::Error;
+use std::io;
+use std::time::{Duration, Instant};
+
+use crate::scoring::VerifiedVoucher;
+
+/// Topic on which revocation notices are gossiped.
+pub const REVOCATION_TOPIC: &str = "circle/revocations";
+
+/// Notices carry a signature proof and possibly a voucher proof, but must fit in a
+/// gossipsub message.
+const MAX_NOTICE_SIZE: usize = 1024 * 1024;
+/// Every synced notice is verified one by one, so a peer answers with (and accepts) a
+/// bounded number of them; gossip and other peers fill in the rest.
+const MAX_SYNC_NOTICES: usize = 32;
+// the CBOR array of notices, with a few bytes of framing per notice
+const MAX_SYNC_SIZE: usize = MAX_SYNC_NOTICES * (MAX_NOTICE_SIZE + 16);
+/// A peer is synced with (in either direction) at most once per interval.
+pub const SYNC_INTERVAL: Duration = Duration::from_secs(60);
+/// How many notices of a peer, gossiped or synced, are verified per [`SYNC_INTERVAL`]; a
+/// sync response fits.
+const MAX_PEER_NOTICES: usize = MAX_SYNC_NOTICES;
+/// How many notices are kept. Only origins we trust and the members of their trees can sign
+/// notices we accept, so the set fills up only if one of them floods it.
+const MAX_REVOCATIONS: usize = 1024;
+
+const EDGE_LENGTH: usize = (3 * PUBLIC_KEY_LENGTH + 1 + DIGEST_LENGTH + 1) * 8;
+
+/// The edge `issuer -> locus` in the voucher tree of `origin`, into a locus at `degree`, as
+/// issued in `epoch` of `epoch_scope`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RevokedEdge {
+    pub origin: PublicKey,
+    pub issuer: PublicKey,
+    pub locus: PublicKey,
+    /// the degree of the voucher of `locus`; an origin, which does not see it, names one
+    pub degree: u64,
+    pub epoch_scope: Digest,
+    pub epoch: u64,
+}
+
+impl RevokedEdge {
+    /// the last edge of the path of `voucher`
+    pub fn of(voucher: &Voucher) -> Self {
+        RevokedEdge {
+            origin: voucher.origin(),
+            issuer: voucher.issuer(),
+            locus: voucher.locus(),
+            degree: voucher.degree().to_canonical_u64(),
+            epoch_scope: voucher.epoch_scope(),
+            epoch: voucher.epoch(),
+        }
+    }
+
+    fn message(self) -> Vec<F> {
+        [
+            &self.origin[..],
+            &self.issuer[..],
+            &self.locus[..],
+            &[F::from_canonical_u64(self.degree)][..],
+            &self.epoch_scope[..],
+            &[F::from_canonical_u64(self.epoch)][..],
+        ]
+        .concat()
+    }
+
+    fn to_bytes(self) -> Vec<u8> {
+        field_elements_to_bytes(&self.message())
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let elements = field_elements_from_bytes(bytes)?;
+        if elements.len() != EDGE_LENGTH / 8 {
+            return Err("Revoked edge has the wrong length".into());
+        }
+        let key = |i: usize| -> PublicKey {
+            elements[i * PUBLIC_KEY_LENGTH..(i + 1) * PUBLIC_KEY_LENGTH]
+                .try_into()
+                .expect("slice has public key length")
+        };
+        let epoch_scope_start = 3 * PUBLIC_KEY_LENGTH + 1;
+        Ok(RevokedEdge {
+            origin: key(0),
+            issuer: key(1),
+            locus: key(2),
+            degree: elements[3 * PUBLIC_KEY_LENGTH].to_canonical_u64(),
+            epoch_scope: elements[epoch_scope_start..epoch_scope_start + DIGEST_LENGTH]
+                .try_into()
+                .expect("slice has digest length"),
+            epoch: elements[epoch_scope_start + DIGEST_LENGTH].to_canonical_u64(),
+        })
+    }
+
+    /// whether the path of `voucher` is this edge, runs through it, or may run through it
+    /// unseen, see the module documentation
+    fn refuses(&self, voucher: &VerifiedVoucher) -> bool {
+        if self.origin != voucher.origin
+            || self.epoch_scope != voucher.epoch_scope
+            || voucher.epoch > self.epoch
+        {
+            return false;
+        }
+        (self.issuer == voucher.issuer && self.locus == voucher.locus)
+            || self.locus == voucher.issuer
+            || voucher.degree >= self.degree.saturating_add(2)
+    }
+}
+
+/// A signed revocation of a voucher edge.
+pub struct RevocationNotice {
+    pub edge: RevokedEdge,
+    signature: Signature,
+    /// the revoked voucher, which an issuer attaches to show it issued the edge
+    voucher: Option<Vec<u8>>,
+}
+
+impl RevocationNotice {
+    /// sign the revocation of any `edge` of our tree with the private key of the origin; the
+    /// epoch of the edge ends for the whole tree below its degree
+    pub fn new(edge: RevokedEdge, origin_private_key: PrivateKey) -> Self {
+        RevocationNotice {
+            edge,
+            signature: Signature::sign(origin_private_key, &edge.message()),
+            voucher: None,
+        }
+    }
+
+    /// sign the revocation of the last edge of `voucher`, a voucher we issued, with our
+    /// private key, attaching the voucher as evidence
+    pub fn from_voucher(voucher: &Voucher, issuer_private_key: PrivateKey) -> Self {
+        let edge = RevokedEdge::of(voucher);
+        RevocationNotice {
+            edge,
+            signature: Signature::sign(issuer_private_key, &edge.message()),
+            voucher: Some(voucher.to_bytes()),
+        }
+    }
+
+    /// A notice is valid if it was signed over its edge by the origin, or by the issuer
+    /// with a valid voucher of that very edge attached.
+    pub fn verify(&self) -> bool {
+        let signer = self.signature.signer();
+        if signer != self.edge.origin && signer != self.edge.issuer {
+            return false;
+        }
+        if !self.signature.verify(&self.edge.message()) {
+            return false;
+        }
+        signer == self.edge.origin || self.voucher_shows_edge()
+    }
+
+    fn voucher_shows_edge(&self) -> bool {
+        let Some(voucher_bytes) = &self.voucher else {
+            return false;
+        };
+        Voucher::from_bytes(voucher_bytes).map_or(false, |voucher| {
+            !voucher.is_origin() && RevokedEdge::of(&voucher) == self.edge
+        })
+    }
+
+    /// the edge, the length of the signature (as a little-endian u32) and the signature,
+    /// followed by the revoked voucher if the issuer signed
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let signature = self.signature.to_bytes();
+        let mut bytes = self.edge.to_bytes();
+        bytes.extend((signature.len() as u32).to_le_bytes());
+        bytes.extend(signature);
+        if let Some(voucher) = &self.voucher {
+            bytes.extend(voucher);
+        }
+        bytes
+    }
+
+    /// decode a notice; it still has to be verified
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Box<dyn Error>> {
+        if bytes.len() > MAX_NOTICE_SIZE {
+            return Err("Revocation notice is too long".into());
+        }
+        if bytes.len() < EDGE_LENGTH + 4 {
+            return Err("Revocation notice is too short".into());
+        }
+        let (edge, rest) = bytes.split_at(EDGE_LENGTH);
+        let (signature_length, rest) = rest.split_at(4);
+        let signature_length = u32::from_le_bytes(signature_length.try_into()?) as usize;
+        if rest.len() < signature_length {
+            return Err("Revocation notice signature is truncated".into());
+        }
+        let (signature, voucher) = rest.split_at(signature_length);
+        Ok(RevocationNotice {
+            edge: RevokedEdge::from_bytes(edge)?,
+            signature: Signature::from_bytes(signature)?,
+            voucher: (!voucher.is_empty()).then(|| voucher.to_vec()),
+        })
+    }
+}
+
+/// The verified revocation notices known to this node.
+#[derive(Default)]
+pub struct RevocationSet {
+    // the encoded notices are kept to answer sync requests
+    notices: HashMap<RevokedEdge, Vec<u8>>,
+}
+
+impl RevocationSet {
+    pub fn contains(&self, edge: &RevokedEdge) -> bool {
+        self.notices.contains_key(edge)
+    }
+
+    /// add a verified notice, returning whether it was new and there was room for it
+    pub fn insert(&mut self, notice: &RevocationNotice) -> bool {
+        if self.contains(&notice.edge) || self.notices.len() >= MAX_REVOCATIONS {
+            return false;
+        }
+        self.notices.insert(notice.edge, notice.to_bytes());
+        true
+    }
+
+    /// Whether a revoked edge may be on the path of a voucher: its last edge, the edge into
+    /// its issuer, or, as the rest of the path is hidden in the proof, any edge above it
+    /// in the same epoch; see the module documentation.
+    pub fn revokes(&self, voucher: &VerifiedVoucher) -> bool {
+        self.notices.keys().any(|edge| edge.refuses(voucher))
+    }
+
+    /// at most [`MAX_SYNC_NOTICES`] encoded notices, to answer a sync request
+    pub fn encoded_notices(&self) -> Vec<Vec<u8>> {
+        self.notices
+            .values()
+            .take(MAX_SYNC_NOTICES)
+            .cloned()
+            .collect()
+    }
+}
+
+/// Limits how often a peer is synced with, or how many of its notices are verified, so
+/// that a peer cannot make us send (or verify) notices over and over by reconnecting,
+/// repeating its requests or signing new notices.
+pub struct RateLimit {
+    limit: usize,
+    // the start of the current interval of every peer, and what it used up since
+    intervals: HashMap<PeerId, (Instant, usize)>,
+}
+
+impl RateLimit {
+    /// one sync per peer per [`SYNC_INTERVAL`]
+    pub fn syncs() -> Self {
+        RateLimit {
+            limit: 1,
+            intervals: HashMap::new(),
+        }
+    }
+
+    /// [`MAX_PEER_NOTICES`] verified notices per peer per [`SYNC_INTERVAL`]
+    pub fn notices() -> Self {
+        RateLimit {
+            limit: MAX_PEER_NOTICES,
+            intervals: HashMap::new(),
+        }
+    }
+
+    /// whether `peer_id` may be synced with (or have a notice verified) at `now`,
+    /// recording it if so
+    pub fn allow(&mut self, peer_id: PeerId, now: Instant) -> bool {
+        self.intervals
+            .retain(|_, (start, _)| now.duration_since(*start) < SYNC_INTERVAL);
+        let (_, used) = self.intervals.entry(peer_id).or_insert((now, 0));
+        if *used >= self.limit {
+            return false;
+        }
+        *used += 1;
+        true
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RevocationSyncProtocol;
+
+impl ProtocolName for RevocationSyncProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        b"/circle/revocations/1.0.0"
+    }
+}
+
+/// Asks a peer for all revocation notices it knows.
+#[derive(Debug, Clone)]
+pub struct RevocationSyncRequest;
+
+/// The encoded (unverified) notices known to the peer.
+#[derive(Debug, Clone)]
+pub struct RevocationSyncResponse(pub Vec<Vec<u8>>);
+
+#[derive(Debug, Clone)]
+pub struct RevocationSyncCodec;
+
+#[async_trait]
+impl request_response::Codec for RevocationSyncCodec {
+    type Protocol = RevocationSyncProtocol;
+    type Request = RevocationSyncRequest;
+    type Response = RevocationSyncResponse;
+
+    async fn read_request<T>(
+        &mut self,
+        _: &RevocationSyncProtocol,
+        io: &mut T,
+    ) -> io::Result<Self::Request>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_length_prefixed(io, 0).await?;
+        Ok(RevocationSyncRequest)
+    }
+
+    async fn read_response<T>(
+        &mut self,
+        _: &RevocationSyncProtocol,
+        io: &mut T,
+    ) -> io::Result<Self::Response>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let bytes = read_length_prefixed(io, MAX_SYNC_SIZE).await?;
+        let notices: Vec<ByteBuf> = ciborium::de::from_reader(bytes.as_slice())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        if notices.len() > MAX_SYNC_NOTICES {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "too many revocation notices",
+            ));
+        }
+        Ok(RevocationSyncResponse(
+            notices.into_iter().map(ByteBuf::into_vec).collect(),
+        ))
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &RevocationSyncProtocol,
+        io: &mut T,
+        _: RevocationSyncRequest,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_length_prefixed(io, []).await?;
+        io.close().await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &RevocationSyncProtocol,
+        io: &mut T,
+        RevocationSyncResponse(notices): RevocationSyncResponse,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        let notices: Vec<ByteBuf> = notices.into_iter().map(ByteBuf::from).collect();
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(&notices, &mut bytes)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+        write_length_prefixed(io, bytes).await?;
+        io.close().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simple_crypto::KeyPair;
+
+    #[test]
+    fn it_works_revocation_notice() {
+        let origin = KeyPair::generate_key_pair();
+        let issuer = KeyPair::generate_key_pair();
+        let locus = KeyPair::generate_key_pair().public_key;
+        let issuer_voucher = Voucher::new_origin(origin.public_key, origin.private_key)
+            .extend_voucher(origin.private_key, issuer.public_key);
+        let revoked_voucher = issuer_voucher.extend_voucher(issuer.private_key, locus);
+        let edge = RevokedEdge::of(&revoked_voucher);
+        assert_eq!(
+            (edge.origin, edge.issuer, edge.locus, edge.degree),
+            (origin.public_key, issuer.public_key, locus, 2)
+        );
+
+        // the origin may revoke the edge, and so may the issuer with the voucher it issued
+        for notice in [
+            RevocationNotice::new(edge, origin.private_key),
+            RevocationNotice::from_voucher(&revoked_voucher, issuer.private_key),
+        ] {
+            let decoded = RevocationNotice::from_bytes(&notice.to_bytes()).unwrap();
+            assert_eq!(decoded.edge, edge);
+            assert!(decoded.verify());
+        }
+
+        // the issuer may not without the voucher
+        let unproven = RevocationNotice {
+            edge,
+            signature: Signature::sign(issuer.private_key, &edge.message()),
+            voucher: None,
+        };
+        assert!(!unproven.verify());
+
+        // nor for another epoch than the one of the voucher it attaches
+        let other_epoch = RevokedEdge {
+            epoch: edge.epoch + 1,
+            ..edge
+        };
+        let misdated = RevocationNotice {
+            edge: other_epoch,
+            signature: Signature::sign(issuer.private_key, &other_epoch.message()),
+            voucher: Some(revoked_voucher.to_bytes()),
+        };
+        assert!(!misdated.verify());
+
+        // a stranger may not, not even with the revoked voucher
+        let stranger = KeyPair::generate_key_pair();
+        assert!(!RevocationNotice::new(edge, stranger.private_key).verify());
+        assert!(!RevocationNotice::from_voucher(&revoked_voucher, stranger.private_key).verify());
+    }
+
+    #[test]
+    fn it_refuses_edges_forged_by_strangers() {
+        let origin = KeyPair::generate_key_pair();
+        let member = KeyPair::generate_key_pair();
+        let stranger = KeyPair::generate_key_pair();
+        let member_voucher = Voucher::new_origin(origin.public_key, origin.private_key)
+            .extend_voucher(origin.private_key, member.public_key);
+
+        // a stranger claiming to have issued edges into the member, or into the origin
+        // (which would revoke every degree one voucher), is refused
+        for locus in [member.public_key, origin.public_key] {
+            let forged_edge = RevokedEdge {
+                issuer: stranger.public_key,
+                locus,
+                ..RevokedEdge::of(&member_voucher)
+            };
+            let forged = RevocationNotice {
+                edge: forged_edge,
+                signature: Signature::sign(stranger.private_key, &forged_edge.message()),
+                voucher: Some(member_voucher.to_bytes()),
+            };
+            let decoded = RevocationNotice::from_bytes(&forged.to_bytes()).unwrap();
+            assert!(!decoded.verify());
+        }
+
+        // and so is an edge the member did not issue, even with its own voucher attached
+        let forged_edge = RevokedEdge {
+            issuer: member.public_key,
+            locus: stranger.public_key,
+            ..RevokedEdge::of(&member_voucher)
+        };
+        let forged = RevocationNotice {
+            edge: forged_edge,
+            signature: Signature::sign(member.private_key, &forged_edge.message()),
+            voucher: Some(member_voucher.to_bytes()),
+        };
+        assert!(!forged.verify());
+    }
+
+    #[test]
+    fn it_works_rate_limit() {
+        let mut rate_limit = RateLimit::syncs();
+        let peer_id = PeerId::random();
+        let now = Instant::now();
+        assert!(rate_limit.allow(peer_id, now));
+        assert!(!rate_limit.allow(peer_id, now + SYNC_INTERVAL / 2));
+        assert!(rate_limit.allow(PeerId::random(), now));
+        assert!(rate_limit.allow(peer_id, now + SYNC_INTERVAL));
+
+        let mut rate_limit = RateLimit::notices();
+        for _ in 0..MAX_PEER_NOTICES {
+            assert!(rate_limit.allow(peer_id, now));
+        }
+        assert!(!rate_limit.allow(peer_id, now + SYNC_INTERVAL / 2));
+        assert!(rate_limit.allow(peer_id, now + SYNC_INTERVAL));
+    }
+
+    #[test]
+    fn it_works_revocation_set_revokes_paths() {
+        let keys: Vec<PublicKey> = (0..5)
+            .map(|_| KeyPair::generate_key_pair().public_key)
+            .collect();
+        let (origin, a, b, c, d) = (keys[0], keys[1], keys[2], keys[3], keys[4]);
+        let scope = [F::ONE; 4];
+        let voucher = |issuer, locus, degree| VerifiedVoucher {
+            origin,
+            issuer,
+            locus,
+            degree,
+            epoch_scope: scope,
+            epoch: 3,
+        };
+
+        // the edge a -> b, b at degree 2
+        let mut revocations = RevocationSet::default();
+        revocations.notices.insert(
+            RevokedEdge {
+                origin,
+                issuer: a,
+                locus: b,
+                degree: 2,
+                epoch_scope: scope,
+                epoch: 3,
+            },
+            Vec::new(),
+        );
+
+        // the revoked edge itself, everyone vouched by its locus, and every path of the
+        // epoch long enough to run through the edge unseen
+        assert!(revocations.revokes(&voucher(a, b, 2)));
+        assert!(revocations.revokes(&voucher(b, c, 3)));
+        assert!(revocations.revokes(&voucher(c, d, 4)));
+        assert!(revocations.revokes(&VerifiedVoucher {
+            epoch: 2,
+            ..voucher(c, d, 4)
+        }));
+        // shorter paths of the tree, the same paths once reissued in a later epoch or in
+        // another scope, and the same edge in another tree
+        assert!(!revocations.revokes(&voucher(a, c, 2)));
+        assert!(!revocations.revokes(&voucher(c, d, 3)));
+        assert!(!revocations.revokes(&VerifiedVoucher {
+            epoch: 4,
+            ..voucher(c, d, 4)
+        }));
+        assert!(!revocations.revokes(&VerifiedVoucher {
+            epoch_scope: [F::ZERO; 4],
+            ..voucher(a, b, 2)
+        }));
+        assert!(!revocations.revokes(&VerifiedVoucher {
+            origin: c,
+            ..voucher(a, b, 2)
+        }));
+    }
+}
@@ -0,0 +1,317 @@
+use libp2p::{gossipsub, PeerId};
+use simple_crypto::{Digest, PublicKey};
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use crate::config::{GatingPolicy, ScoringConfig};
+use crate::revocation::RevocationSet;
+
+/// The public part of a voucher whose proof has been verified.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VerifiedVoucher {
+    pub origin: PublicKey,
+    /// the locus that vouched for `locus`
+    pub issuer: PublicKey,
+    pub locus: PublicKey,
+    pub degree: u64,
+    /// the scope and epoch the origin issued the path in
+    pub epoch_scope: Digest,
+    pub epoch: u64,
+}
+
+/// What we know about the voucher of a peer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PeerStatus {
+    /// seen, but no voucher received yet; scored neutrally until the grace period ends
+    Pending { since: Instant },
+    /// presented a valid voucher
+    Vouched(VerifiedVoucher),
+    /// presented no voucher, or an invalid, too distant or already claimed one, or one from
+    /// an origin we do not trust
+    Unvouched,
+    /// presented a voucher whose path may run through a revoked edge
+    Revoked,
+}
+
+/// Keeps the voucher status of every peer and turns it into gossipsub application scores.
+pub struct PeerVouchers {
+    scoring: ScoringConfig,
+    policy: GatingPolicy,
+    trusted_origins: HashSet<PublicKey>,
+    peers: HashMap<PeerId, PeerStatus>,
+}
+
+impl PeerVouchers {
+    /// `trusted_origins` are the decoded [`GatingPolicy::trusted_origins`]
+    pub fn new(
+        scoring: ScoringConfig,
+        policy: GatingPolicy,
+        trusted_origins: HashSet<PublicKey>,
+    ) -> Self {
+        PeerVouchers {
+            scoring,
+            policy,
+            trusted_origins,
+            peers: HashMap::new(),
+        }
+    }
+
+    pub fn is_trusted_origin(&self, origin: &PublicKey) -> bool {
+        self.trusted_origins.contains(origin)
+    }
+
+    pub fn status(&self, peer_id: &PeerId) -> Option<PeerStatus> {
+        self.peers.get(peer_id).copied()
+    }
+
+    pub fn is_vouched(&self, peer_id: &PeerId) -> bool {
+        matches!(self.status(peer_id), Some(PeerStatus::Vouched(_)))
+    }
+
+    /// the locus of the voucher the peer presented, if it is vouched
+    pub fn vouched_locus(&self, peer_id: &PeerId) -> Option<PublicKey> {
+        match self.status(peer_id) {
+            Some(PeerStatus::Vouched(voucher)) => Some(voucher.locus),
+            _ => None,
+        }
+    }
+
+    pub fn is_revoked(&self, peer_id: &PeerId) -> bool {
+        matches!(self.status(peer_id), Some(PeerStatus::Revoked))
+    }
+
+    /// the peer that presented a valid voucher for `locus`, bound to its peer id; there is
+    /// at most one, see [`PeerVouchers::voucher_verified`]
+    pub fn peer_for_locus(&self, locus: &PublicKey) -> Option<PeerId> {
+        self.peers
+            .iter()
+            .find_map(|(peer_id, status)| match status {
+                PeerStatus::Vouched(voucher) if voucher.locus == *locus => Some(*peer_id),
+                _ => None,
+            })
+    }
+
+    /// start the grace period for a newly seen peer
+    pub fn peer_seen(&mut self, peer_id: PeerId) {
+        self.peers.entry(peer_id).or_insert(PeerStatus::Pending {
+            since: Instant::now(),
+        });
+    }
+
+    /// Record the outcome of verifying the voucher of a peer and return its new score.
+    /// Only vouchers from a trusted origin count, anyone can mint an origin voucher of its
+    /// own. A locus is claimed by the first peer that presents it: a second peer presenting
+    /// a voucher for the same locus is unvouched, so that direct messages for the locus
+    /// never go to whichever peer claimed it last.
+    pub fn voucher_verified(&mut self, peer_id: PeerId, voucher: Option<VerifiedVoucher>) -> f64 {
+        let status = match voucher {
+            Some(voucher)
+                if voucher.degree <= self.policy.max_degree
+                    && self.is_trusted_origin(&voucher.origin)
+                    && self
+                        .peer_for_locus(&voucher.locus)
+                        .map_or(true, |claimant| claimant == peer_id) =>
+            {
+                PeerStatus::Vouched(voucher)
+            }
+            _ => PeerStatus::Unvouched,
+        };
+        self.peers.insert(peer_id, status);
+        self.score(status)
+    }
+
+    /// mark peers whose grace period ended without a valid voucher as unvouched,
+    /// returning them with their new score
+    pub fn expire_pending(&mut self, now: Instant) -> Vec<(PeerId, f64)> {
+        let grace_period = Duration::from_secs(self.scoring.voucher_grace_period_secs);
+        let mut expired = Vec::new();
+        for (peer_id, status) in self.peers.iter_mut() {
+            if let PeerStatus::Pending { since } = status {
+                if now.duration_since(*since) >= grace_period {
+                    *status = PeerStatus::Unvouched;
+                    expired.push(*peer_id);
+                }
+            }
+        }
+        let unvouched_score = self.score(PeerStatus::Unvouched);
+        expired
+            .into_iter()
+            .map(|peer_id| (peer_id, unvouched_score))
+            .collect()
+    }
+
+    /// mark vouched peers whose voucher a revoked edge refuses as revoked,
+    /// returning them with their new score
+    pub fn revoke(&mut self, revocations: &RevocationSet) -> Vec<(PeerId, f64)> {
+        let mut revoked = Vec::new();
+        for (peer_id, status) in self.peers.iter_mut() {
+            if let PeerStatus::Vouched(voucher) = status {
+                if revocations.revokes(voucher) {
+                    *status = PeerStatus::Revoked;
+                    revoked.push(*peer_id);
+                }
+            }
+        }
+        let revoked_score = self.score(PeerStatus::Revoked);
+        revoked
+            .into_iter()
+            .map(|peer_id| (peer_id, revoked_score))
+            .collect()
+    }
+
+    /// The application specific score of a peer. Origins get `origin_score`, every hop
+    /// further away from the origin lowers it linearly down to a single step at
+    /// `max_degree`. Unvouched and revoked peers get `unvouched_score`, which (weighted)
+    /// should be below the graylist threshold.
+    pub fn score(&self, status: PeerStatus) -> f64 {
+        match status {
+            PeerStatus::Pending { .. } => 0.0,
+            PeerStatus::Vouched(voucher) => {
+                let steps = (self.policy.max_degree + 1) as f64;
+                self.scoring.origin_score * (steps - voucher.degree as f64) / steps
+            }
+            PeerStatus::Unvouched | PeerStatus::Revoked => self.scoring.unvouched_score,
+        }
+    }
+}
+
+/// Peer scoring is driven by the application score only: topic scores are left out
+/// and IP colocation is not penalised, the social graph replaces both.
+pub fn peer_score_params(scoring: &ScoringConfig) -> gossipsub::PeerScoreParams {
+    gossipsub::PeerScoreParams {
+        app_specific_weight: scoring.app_specific_weight,
+        ip_colocation_factor_weight: scoring.ip_colocation_factor_weight,
+        ..Default::default()
+    }
+}
+
+pub fn peer_score_thresholds(scoring: &ScoringConfig) -> gossipsub::PeerScoreThresholds {
+    gossipsub::PeerScoreThresholds {
+        gossip_threshold: scoring.gossip_threshold,
+        publish_threshold: scoring.publish_threshold,
+        graylist_threshold: scoring.graylist_threshold,
+        accept_px_threshold: scoring.accept_px_threshold,
+        opportunistic_graft_threshold: scoring.opportunistic_graft_threshold,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use plonky2::field::types::Field;
+    use simple_crypto::{KeyPair, F};
+
+    fn weighted(peer_vouchers: &PeerVouchers, status: PeerStatus) -> f64 {
+        peer_vouchers.score(status) * peer_vouchers.scoring.app_specific_weight
+    }
+
+    fn peer_vouchers() -> PeerVouchers {
+        PeerVouchers::new(
+            ScoringConfig::default(),
+            GatingPolicy::default(),
+            HashSet::from([[F::ZERO; 4]]),
+        )
+    }
+
+    fn verified_voucher(degree: u64, locus: PublicKey) -> VerifiedVoucher {
+        VerifiedVoucher {
+            origin: [F::ZERO; 4],
+            issuer: [F::ONE; 4],
+            locus,
+            degree,
+            epoch_scope: [F::ZERO; 4],
+            epoch: 0,
+        }
+    }
+
+    #[test]
+    fn it_works_lower_degree_scores_higher() {
+        let peer_vouchers = peer_vouchers();
+        let thresholds = peer_score_thresholds(&peer_vouchers.scoring);
+
+        let mut previous = f64::INFINITY;
+        for degree in 0..=peer_vouchers.policy.max_degree {
+            let status = PeerStatus::Vouched(verified_voucher(degree, [F::ZERO; 4]));
+            let score = weighted(&peer_vouchers, status);
+            assert!(score < previous);
+            assert!(score > 0.0);
+            previous = score;
+        }
+
+        // origins are preferred for opportunistic grafting, unvouched peers are graylisted
+        let origin_status = PeerStatus::Vouched(verified_voucher(0, [F::ZERO; 4]));
+        let origin = weighted(&peer_vouchers, origin_status);
+        assert!(origin >= thresholds.opportunistic_graft_threshold);
+        let unvouched = weighted(&peer_vouchers, PeerStatus::Unvouched);
+        assert!(unvouched < thresholds.graylist_threshold);
+    }
+
+    #[test]
+    fn it_works_voucher_status_transitions() {
+        let mut peer_vouchers = peer_vouchers();
+        let vouched_peer = PeerId::random();
+        let distant_peer = PeerId::random();
+        let silent_peer = PeerId::random();
+
+        let vouched_locus = KeyPair::generate_key_pair().public_key;
+        let distant_locus = KeyPair::generate_key_pair().public_key;
+
+        peer_vouchers.peer_seen(vouched_peer);
+        peer_vouchers.peer_seen(silent_peer);
+        let vouched = verified_voucher(1, vouched_locus);
+        let distant = verified_voucher(100, distant_locus);
+        assert!(peer_vouchers.voucher_verified(vouched_peer, Some(vouched)) > 0.0);
+        assert!(peer_vouchers.voucher_verified(distant_peer, Some(distant)) < 0.0);
+        assert!(peer_vouchers.is_vouched(&vouched_peer));
+        assert!(!peer_vouchers.is_vouched(&distant_peer));
+        assert_eq!(
+            peer_vouchers.peer_for_locus(&vouched_locus),
+            Some(vouched_peer)
+        );
+        assert_eq!(peer_vouchers.peer_for_locus(&distant_locus), None);
+        assert_eq!(
+            peer_vouchers.vouched_locus(&vouched_peer),
+            Some(vouched_locus)
+        );
+        assert_eq!(peer_vouchers.vouched_locus(&distant_peer), None);
+
+        // the locus stays with the peer that claimed it first, even if it announces again
+        let duplicate_peer = PeerId::random();
+        assert!(peer_vouchers.voucher_verified(duplicate_peer, Some(vouched)) < 0.0);
+        assert!(!peer_vouchers.is_vouched(&duplicate_peer));
+        assert!(peer_vouchers.voucher_verified(vouched_peer, Some(vouched)) > 0.0);
+        assert_eq!(
+            peer_vouchers.peer_for_locus(&vouched_locus),
+            Some(vouched_peer)
+        );
+
+        let later = Instant::now() + Duration::from_secs(3600);
+        let expired = peer_vouchers.expire_pending(later);
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].0, silent_peer);
+        assert_eq!(
+            peer_vouchers.status(&silent_peer),
+            Some(PeerStatus::Unvouched)
+        );
+    }
+
+    #[test]
+    fn it_refuses_self_minted_origin_vouchers() {
+        let mut peer_vouchers = peer_vouchers();
+        let peer_id = PeerId::random();
+
+        // anyone can mint a degree zero voucher for a key of its own
+        let self_minted = KeyPair::generate_key_pair().public_key;
+        let score = peer_vouchers.voucher_verified(
+            peer_id,
+            Some(VerifiedVoucher {
+                origin: self_minted,
+                issuer: self_minted,
+                ..verified_voucher(0, self_minted)
+            }),
+        );
+        assert_eq!(score, peer_vouchers.scoring.unvouched_score);
+        assert!(!peer_vouchers.is_vouched(&peer_id));
+        assert_eq!(peer_vouchers.peer_for_locus(&self_minted), None);
+    }
+}
@@ -0,0 +1,113 @@
+use dos::voucher::Voucher;
+use simple_crypto::{
+    field_elements_from_bytes, field_elements_to_bytes, KeyPair, PrivateKey, PublicKey, F,
+};
+use std::error::Error;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+const LOCUS_KEY_FILE: &str = "locus.key";
+const VOUCHER_FILE: &str = "voucher.bin";
+const ISSUED_DIRECTORY: &str = "issued";
+
+/// Directory holding the (Poseidon) locus key of this node, the voucher that was issued
+/// to that locus, and the vouchers this node issued to others.
+pub struct Wallet {
+    path: PathBuf,
+}
+
+impl Wallet {
+    pub fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+        fs::create_dir_all(path)?;
+        Ok(Wallet {
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// the locus key pair of this node, generated on first use
+    pub fn locus_key_pair(&self) -> Result<KeyPair, Box<dyn Error>> {
+        let key_path = self.path.join(LOCUS_KEY_FILE);
+        if key_path.exists() {
+            let private_key: PrivateKey = decode_key(fs::read_to_string(&key_path)?.trim())?;
+            return Ok(KeyPair::from_private_key(private_key));
+        }
+
+        let key_pair = KeyPair::generate_key_pair();
+        write_private_file(&key_path, encode_key(&key_pair.private_key).as_bytes())?;
+        Ok(key_pair)
+    }
+
+    /// the voucher stored for our locus, if any
+    pub fn voucher(&self) -> Result<Option<Voucher>, Box<dyn Error>> {
+        let voucher_path = self.path.join(VOUCHER_FILE);
+        if !voucher_path.exists() {
+            return Ok(None);
+        }
+        let voucher = Voucher::from_bytes(&fs::read(voucher_path)?)?;
+        Ok(Some(voucher))
+    }
+
+    pub fn store_voucher(&self, voucher: &Voucher) -> Result<(), Box<dyn Error>> {
+        if voucher.locus() != self.locus_key_pair()?.public_key {
+            return Err("Voucher was not issued to the locus of this wallet".into());
+        }
+        fs::write(self.path.join(VOUCHER_FILE), voucher.to_bytes())?;
+        Ok(())
+    }
+
+    /// keep a copy of a voucher we issued, which we need to revoke it later
+    pub fn store_issued_voucher(&self, voucher: &Voucher) -> Result<(), Box<dyn Error>> {
+        let issued_path = self.path.join(ISSUED_DIRECTORY);
+        fs::create_dir_all(&issued_path)?;
+        fs::write(
+            issued_path.join(issued_file_name(&voucher.locus())),
+            voucher.to_bytes(),
+        )?;
+        Ok(())
+    }
+
+    /// the voucher we issued to `locus`, if we kept one
+    pub fn issued_voucher(&self, locus: &PublicKey) -> Result<Option<Voucher>, Box<dyn Error>> {
+        let voucher_path = self
+            .path
+            .join(ISSUED_DIRECTORY)
+            .join(issued_file_name(locus));
+        if !voucher_path.exists() {
+            return Ok(None);
+        }
+        let voucher = Voucher::from_bytes(&fs::read(voucher_path)?)?;
+        Ok(Some(voucher))
+    }
+}
+
+fn issued_file_name(locus: &PublicKey) -> String {
+    format!("{}.bin", encode_key(locus))
+}
+
+/// Write a file holding a private key, readable (and writable) by its owner only. The mode
+/// only applies to new files, so an existing file is restricted as well.
+pub fn write_private_file(path: &Path, contents: &[u8]) -> Result<(), Box<dyn Error>> {
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    file.write_all(contents)?;
+    Ok(())
+}
+
+pub fn encode_key(key: &[F]) -> String {
+    hex::encode(field_elements_to_bytes(key))
+}
+
+pub fn decode_key(encoded: &str) -> Result<PublicKey, Box<dyn Error>> {
+    let elements = field_elements_from_bytes(&hex::decode(encoded)?)?;
+    let key: PublicKey = elements
+        .try_into()
+        .map_err(|_| format!("Key {encoded} must be exactly four field elements"))?;
+    Ok(key)
+}
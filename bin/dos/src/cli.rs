@@ -0,0 +1,147 @@
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "dos",
+    about = "Create, extend and check degrees of separation vouchers"
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// generate a key pair, write the private key to a file and print the public key
+    Keygen {
+        /// file to write the hex encoded private key to, readable by its owner only
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// create an origin voucher for the key in a private key file
+    Vouch {
+        /// private key file of the origin
+        #[arg(long)]
+        key: PathBuf,
+
+        /// capability bitmask to grant, e.g. 0x3 (every capability if absent)
+        #[arg(long)]
+        capabilities: Option<String>,
+
+        /// longest path the voucher can be extended to (6 if absent)
+        #[arg(long)]
+        max_degree: Option<u64>,
+
+        /// hex encoded epoch scope to issue the voucher in (the zero scope if absent)
+        #[arg(long)]
+        epoch_scope: Option<String>,
+
+        /// epoch of the scope to issue the voucher in
+        #[arg(long, default_value_t = 0)]
+        epoch: u64,
+
+        /// file to write the voucher to
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// extend a voucher to another locus, signed with the key of its current locus
+    Extend {
+        /// voucher file to extend
+        voucher: PathBuf,
+
+        /// private key file of the locus of the voucher
+        #[arg(long)]
+        key: PathBuf,
+
+        /// hex encoded locus (public key) to vouch for
+        #[arg(long)]
+        locus: String,
+
+        /// capability bitmask to delegate, a subset of the voucher's (all of them if absent)
+        #[arg(long)]
+        capabilities: Option<String>,
+
+        /// how much the locus is trusted, from 1 to 100
+        #[arg(long, default_value_t = 100)]
+        weight: u64,
+
+        /// file to write the extended voucher to
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// verify a voucher file, exiting with an error if it is invalid
+    Verify {
+        /// voucher file to verify
+        voucher: PathBuf,
+
+        /// hex encoded origin the voucher must be rooted in
+        #[arg(long)]
+        origin: Option<String>,
+
+        /// largest accepted degree
+        #[arg(long)]
+        max_degree: Option<u64>,
+
+        /// capability bitmask the voucher must hold
+        #[arg(long)]
+        capabilities: Option<String>,
+
+        /// lowest accepted score, the lowest trust weight along the path
+        #[arg(long)]
+        min_score: Option<u64>,
+
+        /// epoch announcement file of the origin the voucher must be current in
+        #[arg(long)]
+        announcement: Option<PathBuf>,
+
+        /// how many epochs the voucher may lag behind the announcement
+        #[arg(long, default_value_t = 0)]
+        grace: u64,
+    },
+    /// sign the current epoch of a scope, revoking the vouchers issued in earlier epochs
+    Announce {
+        /// private key file of the origin
+        #[arg(long)]
+        key: PathBuf,
+
+        /// hex encoded epoch scope (the zero scope if absent)
+        #[arg(long)]
+        epoch_scope: Option<String>,
+
+        /// the new current epoch of the scope
+        #[arg(long)]
+        epoch: u64,
+
+        /// file to write the announcement to
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// print the decoded public inputs and the circuit digest of a voucher file
+    Inspect {
+        /// voucher file to inspect
+        voucher: PathBuf,
+    },
+    /// record the edge a voucher file proves in a local graph
+    Record {
+        /// voucher file to record
+        voucher: PathBuf,
+
+        /// directory of the graph
+        #[arg(long)]
+        graph: PathBuf,
+    },
+    /// print the extensions that would get a voucher of one of the origins to a locus
+    Plan {
+        /// hex encoded locus (public key) to plan for
+        locus: String,
+
+        /// hex encoded origin to start from (repeatable)
+        #[arg(long, required = true)]
+        origin: Vec<String>,
+
+        /// directory of the graph
+        #[arg(long)]
+        graph: PathBuf,
+    },
+}
@@ -0,0 +1,343 @@
+//! Command line tool for degrees of separation vouchers.
+//!
+//! Everything works on local files, so onboarding flows can be scripted:
+//!
+//! ```sh
+//! dos keygen --out origin.key
+//! dos vouch --key origin.key --capabilities 0x3 --max-degree 4 --epoch 1 --out origin.voucher
+//! dos keygen --out alice.key    # prints the public key of alice
+//! dos extend origin.voucher --key origin.key --locus <alice> --capabilities 0x1 --weight 80 --out alice.voucher
+//! dos inspect alice.voucher
+//! dos verify alice.voucher --origin <origin> --max-degree 3 --capabilities 0x1 --min-score 50
+//! dos announce --key origin.key --epoch 2 --out epoch.announcement
+//! dos verify alice.voucher --announcement epoch.announcement --grace 1
+//! dos record alice.voucher --graph graph
+//! dos plan <bob> --origin <origin> --graph graph
+//! ```
+//!
+//! Keys are hex encoded little-endian field elements, private keys are written readable by
+//! their owner only. `verify` rebuilds the circuit for the degree in the voucher and checks
+//! the proof against it, so `--origin` is what ties the voucher to a tree. Capabilities
+//! are bitmasks, given in decimal or as `0x` prefixed hex. Announcing a later epoch for a
+//! scope revokes every voucher the origin issued in it before, once `--grace` has passed.
+
+use clap::Parser;
+use dos::capabilities::Capabilities;
+use dos::circuit_builder::DEFAULT_MAX_DEGREE;
+use dos::epoch::{EpochAnnouncement, DEFAULT_EPOCH_SCOPE};
+use dos::graph::SocialGraph;
+use dos::voucher::Voucher;
+use plonky2::field::types::PrimeField64;
+use simple_crypto::{
+    field_elements_from_bytes, field_elements_to_bytes, Digest, KeyPair, PrivateKey, PublicKey, F,
+};
+use std::error::Error;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+mod cli;
+
+use cli::{Cli, Command};
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let cli = Cli::parse();
+
+    match cli.command {
+        Command::Keygen { out } => {
+            let key_pair = KeyPair::generate_key_pair();
+            write_private_key(&out, &key_pair.private_key)?;
+            println!("{}", encode_key(&key_pair.public_key));
+            Ok(())
+        }
+        Command::Vouch {
+            key,
+            capabilities,
+            max_degree,
+            epoch_scope,
+            epoch,
+            out,
+        } => {
+            let key_pair = read_key_pair(&key)?;
+            let capabilities = match capabilities {
+                Some(capabilities) => parse_capabilities(&capabilities)?,
+                None => Capabilities::ALL,
+            };
+            let voucher = Voucher::new_origin_in_epoch(
+                key_pair.public_key,
+                key_pair.private_key,
+                capabilities,
+                max_degree.unwrap_or(DEFAULT_MAX_DEGREE),
+                parse_epoch_scope(epoch_scope.as_deref())?,
+                epoch,
+            )?;
+            fs::write(&out, voucher.to_bytes())?;
+            println!(
+                "Wrote origin voucher for {} to {}",
+                encode_key(&key_pair.public_key),
+                out.display()
+            );
+            Ok(())
+        }
+        Command::Extend {
+            voucher,
+            key,
+            locus,
+            capabilities,
+            weight,
+            out,
+        } => {
+            let voucher = read_voucher(&voucher)?;
+            let key_pair = read_key_pair(&key)?;
+            if key_pair.public_key != voucher.locus() {
+                return Err("Only the locus of a voucher can extend it".into());
+            }
+            let locus = decode_key(&locus)?;
+            let capabilities = match capabilities {
+                Some(capabilities) => parse_capabilities(&capabilities)?,
+                None => voucher.capabilities(),
+            };
+
+            let extended_voucher = voucher.extend_voucher_weighted(
+                key_pair.private_key,
+                locus,
+                capabilities,
+                weight,
+            )?;
+            fs::write(&out, extended_voucher.to_bytes())?;
+            println!(
+                "Wrote degree {} voucher for {} to {}",
+                extended_voucher.degree(),
+                encode_key(&locus),
+                out.display()
+            );
+            Ok(())
+        }
+        Command::Verify {
+            voucher,
+            origin,
+            max_degree,
+            capabilities,
+            min_score,
+            announcement,
+            grace,
+        } => {
+            // decoding already verifies the proof
+            let voucher = read_voucher(&voucher)?;
+            if let Some(origin) = origin {
+                if decode_key(&origin)? != voucher.origin() {
+                    return Err("Voucher is rooted in another origin".into());
+                }
+            }
+            if let Some(max_degree) = max_degree {
+                if voucher.degree().to_canonical_u64() > max_degree {
+                    return Err(format!("Voucher degree is above {max_degree}").into());
+                }
+            }
+            if let Some(capabilities) = capabilities {
+                let capabilities = parse_capabilities(&capabilities)?;
+                if !voucher.capabilities().contains(capabilities) {
+                    return Err(format!("Voucher does not hold capabilities {capabilities}").into());
+                }
+            }
+            if let Some(min_score) = min_score {
+                if voucher.score() < min_score {
+                    return Err(format!("Voucher score is below {min_score}").into());
+                }
+            }
+            if let Some(announcement) = announcement {
+                let announcement = read_announcement(&announcement)?;
+                if announcement.origin() != voucher.origin() {
+                    return Err("Epoch was announced by another origin".into());
+                }
+                if !announcement
+                    .window(grace)
+                    .admits(voucher.epoch_scope(), voucher.epoch())
+                {
+                    return Err(format!(
+                        "Voucher was issued in epoch {}, outside the window",
+                        voucher.epoch()
+                    )
+                    .into());
+                }
+            }
+            println!("Valid");
+            Ok(())
+        }
+        Command::Announce {
+            key,
+            epoch_scope,
+            epoch,
+            out,
+        } => {
+            let key_pair = read_key_pair(&key)?;
+            let announcement = EpochAnnouncement::new(
+                key_pair.private_key,
+                parse_epoch_scope(epoch_scope.as_deref())?,
+                epoch,
+            )?;
+            fs::write(&out, announcement.to_bytes())?;
+            println!("Wrote epoch {epoch} announcement to {}", out.display());
+            Ok(())
+        }
+        Command::Inspect { voucher } => inspect(&read_voucher(&voucher)?),
+        Command::Record { voucher, graph } => {
+            let voucher = read_voucher(&voucher)?;
+            let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+            SocialGraph::open(&graph)?.record_voucher(&voucher, now)?;
+            println!(
+                "Recorded {} -> {}",
+                encode_key(&voucher.issuer()),
+                encode_key(&voucher.locus())
+            );
+            Ok(())
+        }
+        Command::Plan {
+            locus,
+            origin,
+            graph,
+        } => {
+            let origins = origin
+                .iter()
+                .map(|origin| decode_key(origin))
+                .collect::<Result<Vec<_>, _>>()?;
+            let plan = SocialGraph::open(&graph)?
+                .plan(&origins, decode_key(&locus)?)
+                .ok_or("No known path from the origins to the locus")?;
+            println!(
+                "origin {} reaches degree {}",
+                encode_key(&plan.origin),
+                plan.degree()
+            );
+            for step in plan.steps {
+                println!(
+                    "{} extends to {} (degree {})",
+                    encode_key(&step.issuer),
+                    encode_key(&step.locus),
+                    step.degree
+                );
+            }
+            Ok(())
+        }
+    }
+}
+
+fn inspect(voucher: &Voucher) -> Result<(), Box<dyn Error>> {
+    let public_inputs = voucher.proof_public_inputs()?;
+    println!("origin:         {}", encode_key(&public_inputs.origin));
+    println!("locus:          {}", encode_key(&public_inputs.locus));
+    println!("signature:      {}", encode_key(&public_inputs.signature));
+    println!("degree:         {}", public_inputs.degree);
+    println!("capabilities:   {}", public_inputs.capabilities);
+    println!("score:          {}", public_inputs.score);
+    println!("max degree:     {}", public_inputs.max_degree);
+    println!("epoch scope:    {}", encode_key(&public_inputs.epoch_scope));
+    println!("epoch:          {}", public_inputs.epoch);
+    if let Some(inner) = public_inputs.inner {
+        println!("inner origin:   {}", encode_key(&inner.origin));
+        println!("inner locus:    {}", encode_key(&inner.locus));
+        println!("inner degree:   {}", inner.degree);
+        println!("inner caps:     {}", inner.capabilities);
+        println!("inner score:    {}", inner.score);
+        println!("inner max deg:  {}", inner.max_degree);
+    }
+    println!("circuit digest: {}", encode_key(&voucher.circuit_digest()));
+    Ok(())
+}
+
+fn read_voucher(path: &Path) -> Result<Voucher, Box<dyn Error>> {
+    let bytes = fs::read(path).map_err(|e| format!("Cannot read {}: {e}", path.display()))?;
+    Voucher::from_bytes(&bytes)
+        .map_err(|e| format!("Invalid voucher {}: {e}", path.display()).into())
+}
+
+/// read an epoch announcement, verifying the signature of the origin
+fn read_announcement(path: &Path) -> Result<EpochAnnouncement, Box<dyn Error>> {
+    let bytes = fs::read(path).map_err(|e| format!("Cannot read {}: {e}", path.display()))?;
+    let announcement = EpochAnnouncement::from_bytes(&bytes)
+        .map_err(|e| format!("Invalid announcement {}: {e}", path.display()))?;
+    if !announcement.verify() {
+        return Err(format!("Announcement {} does not verify", path.display()).into());
+    }
+    Ok(announcement)
+}
+
+/// write a private key readable (and writable) by its owner only; the mode only applies to
+/// new files, so an existing file is restricted as well
+fn write_private_key(path: &Path, private_key: &PrivateKey) -> Result<(), Box<dyn Error>> {
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .map_err(|e| format!("Cannot write {}: {e}", path.display()))?;
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    file.write_all(encode_key(private_key).as_bytes())?;
+    Ok(())
+}
+
+fn read_key_pair(path: &Path) -> Result<KeyPair, Box<dyn Error>> {
+    let encoded =
+        fs::read_to_string(path).map_err(|e| format!("Cannot read {}: {e}", path.display()))?;
+    let private_key: PrivateKey = decode_key(encoded.trim())?;
+    Ok(KeyPair::from_private_key(private_key))
+}
+
+/// parse a capability bitmask, in decimal or as `0x` prefixed hex
+fn parse_capabilities(encoded: &str) -> Result<Capabilities, Box<dyn Error>> {
+    let bits = match encoded.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16)?,
+        None => encoded.parse()?,
+    };
+    Ok(Capabilities::from_bits(bits))
+}
+
+/// parse a hex encoded epoch scope, the zero scope if absent
+fn parse_epoch_scope(encoded: Option<&str>) -> Result<Digest, Box<dyn Error>> {
+    match encoded {
+        Some(encoded) => decode_key(encoded),
+        None => Ok(DEFAULT_EPOCH_SCOPE),
+    }
+}
+
+fn encode_key(key: &[F]) -> String {
+    hex::encode(field_elements_to_bytes(key))
+}
+
+/// decode four hex encoded field elements: a key or a digest
+fn decode_key(encoded: &str) -> Result<PublicKey, Box<dyn Error>> {
+    let elements = field_elements_from_bytes(&hex::decode(encoded)?)?;
+    let key: PublicKey = elements
+        .try_into()
+        .map_err(|_| format!("Key {encoded} must be exactly four field elements"))?;
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_works_key_encoding() {
+        let key_pair = KeyPair::generate_key_pair();
+        let encoded = encode_key(&key_pair.private_key);
+        assert_eq!(decode_key(&encoded).unwrap(), key_pair.private_key);
+
+        assert!(decode_key("00").is_err());
+        assert!(decode_key("not hex").is_err());
+    }
+
+    #[test]
+    fn it_works_capability_parsing() {
+        assert_eq!(
+            parse_capabilities("0x3").unwrap(),
+            Capabilities::from_bits(3)
+        );
+        assert_eq!(parse_capabilities("1").unwrap(), Capabilities::SSH);
+        assert!(parse_capabilities("0x100000000").is_err());
+        assert!(parse_capabilities("ssh").is_err());
+    }
+}
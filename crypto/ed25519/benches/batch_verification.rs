@@ -0,0 +1,137 @@
+//! Compares the cost per signature of one batch circuit against separate circuits.
+//!
+//! Run with `cargo bench -p ed25519_proofs`. Set `BATCH_SIZE` to change the batch size.
+
+use std::time::{Duration, Instant};
+
+use ed25519_dalek::{Keypair, Signer};
+use ed25519_proofs::{
+    add_virtual_bool_targets, make_batch_ed25519_verification_circuit,
+    make_ed25519_verification_ciruit, BatchEntryTargets, BatchVerification, PUBLIC_KEY_LENGTH_BITS,
+    SIGNATURE_LENGTH_BITS,
+};
+use plonky2::iop::witness::PartialWitness;
+use plonky2::plonk::{
+    circuit_builder::CircuitBuilder,
+    circuit_data::CircuitConfig,
+    config::{GenericConfig, PoseidonGoldilocksConfig},
+};
+use plonky2_ed25519::gadgets::eddsa::fill_circuits;
+use rand::rngs::OsRng;
+
+const D: usize = 2;
+type C = PoseidonGoldilocksConfig;
+type F = <C as GenericConfig<D>>::F;
+
+const MESSAGE_LENGTH: usize = 32;
+
+struct Signed {
+    message: Vec<u8>,
+    signature: [u8; 64],
+    public_key: [u8; 32],
+}
+
+fn signatures(count: usize) -> Vec<Signed> {
+    let mut csprng = OsRng {};
+    (0..count)
+        .map(|i| {
+            let key_pair = Keypair::generate(&mut csprng);
+            let message = vec![i as u8; MESSAGE_LENGTH];
+            Signed {
+                signature: key_pair.sign(&message).to_bytes(),
+                public_key: key_pair.public.to_bytes(),
+                message,
+            }
+        })
+        .collect()
+}
+
+fn entry(builder: &mut CircuitBuilder<F, D>) -> BatchEntryTargets {
+    BatchEntryTargets {
+        message: add_virtual_bool_targets(builder, MESSAGE_LENGTH * 8),
+        signature: add_virtual_bool_targets(builder, SIGNATURE_LENGTH_BITS),
+        public_key: add_virtual_bool_targets(builder, PUBLIC_KEY_LENGTH_BITS),
+    }
+}
+
+/// build and prove one circuit, returning its gate count and proving time
+fn prove(builder: CircuitBuilder<F, D>, partial_witness: PartialWitness<F>) -> (usize, Duration) {
+    let gates = builder.num_gates();
+    let circuit_data = builder.build::<C>();
+    let start = Instant::now();
+    let proof_with_pis = circuit_data.prove(partial_witness).unwrap();
+    let elapsed = start.elapsed();
+    circuit_data.verify(proof_with_pis).unwrap();
+    (gates, elapsed)
+}
+
+fn separate(signed: &[Signed]) -> (usize, Duration) {
+    let mut total = (0, Duration::ZERO);
+    for signed in signed {
+        let mut builder = CircuitBuilder::<F, D>::new(CircuitConfig::wide_ecc_config());
+        let entry = entry(&mut builder);
+        let targets = make_ed25519_verification_ciruit(
+            &mut builder,
+            &entry.message,
+            &entry.signature,
+            &entry.public_key,
+            true,
+        );
+        let mut partial_witness = PartialWitness::<F>::new();
+        fill_circuits(
+            &mut partial_witness,
+            &signed.message,
+            &signed.signature,
+            &signed.public_key,
+            &targets,
+        );
+        let (gates, elapsed) = prove(builder, partial_witness);
+        total = (total.0 + gates, total.1 + elapsed);
+    }
+    total
+}
+
+fn batch(signed: &[Signed], verification: BatchVerification) -> (usize, Duration) {
+    let mut builder = CircuitBuilder::<F, D>::new(CircuitConfig::wide_ecc_config());
+    let entries: Vec<_> = signed.iter().map(|_| entry(&mut builder)).collect();
+    let targets =
+        make_batch_ed25519_verification_circuit(&mut builder, &entries, verification, true);
+    let mut partial_witness = PartialWitness::<F>::new();
+    for (signed, targets) in signed.iter().zip(&targets) {
+        fill_circuits(
+            &mut partial_witness,
+            &signed.message,
+            &signed.signature,
+            &signed.public_key,
+            targets,
+        );
+    }
+    prove(builder, partial_witness)
+}
+
+fn main() {
+    let batch_size: usize = std::env::var("BATCH_SIZE")
+        .ok()
+        .and_then(|size| size.parse().ok())
+        .unwrap_or(4);
+    let signed = signatures(batch_size);
+
+    println!("{batch_size} signatures of {MESSAGE_LENGTH} bytes");
+    for (name, (gates, elapsed)) in [
+        ("separate proofs", separate(&signed)),
+        (
+            "batch, individual",
+            batch(&signed, BatchVerification::Individual),
+        ),
+        (
+            "batch, randomized",
+            batch(&signed, BatchVerification::Randomized),
+        ),
+    ] {
+        println!(
+            "{name:>20}: {:>8} gates per signature, {:>8.2?} proving per signature",
+            gates / batch_size,
+            elapsed / batch_size as u32,
+        );
+    }
+}
@@ -0,0 +1,247 @@
+//! Verification of many ed25519 signatures in one circuit.
+//!
+//! Every signature still needs its own SHA-512 and its own `h * A`, and in
+//! [`BatchVerification::Individual`] its own fixed-base `s * B` as well, so the saving over
+//! separate proofs comes from proving once and recursing once. Only
+//! [`BatchVerification::Randomized`] does a single fixed-base multiplication for the batch.
+
+use plonky2::field::extension::Extendable;
+use plonky2::field::types::Field;
+use plonky2::hash::hash_types::RichField;
+use plonky2::hash::poseidon::PoseidonHash;
+use plonky2::iop::target::BoolTarget;
+use plonky2::plonk::circuit_builder::CircuitBuilder;
+use plonky2_ed25519::curve::curve_types::Curve;
+use plonky2_ed25519::curve::ed25519::Ed25519;
+use plonky2_ed25519::gadgets::curve::{AffinePointTarget, CircuitBuilderCurve};
+use plonky2_ed25519::gadgets::curve_fixed_base::fixed_base_curve_mul_circuit;
+use plonky2_ed25519::gadgets::curve_windowed_mul::CircuitBuilderWindowedMul;
+use plonky2_ed25519::gadgets::nonnative::CircuitBuilderNonNative;
+use plonky2_sha512::circuit::bits_to_biguint_target;
+
+use crate::{signature_components, Ed25519Targets, SignatureComponents};
+
+// bits of the random coefficients of a randomized batch
+const BATCH_COEFFICIENT_BITS: usize = 128;
+
+/// The caller-owned bits of one signature in a batch.
+pub struct BatchEntryTargets {
+    pub message: Vec<BoolTarget>,
+    pub signature: Vec<BoolTarget>,
+    pub public_key: Vec<BoolTarget>,
+}
+
+/// How the signatures of a batch are checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BatchVerification {
+    /// One equation `s_i * B == R_i + h_i * A_i` per signature.
+    #[default]
+    Individual,
+    /// The single equation `8 (sum z_i s_i) * B == 8 (sum z_i R_i + sum (z_i h_i) A_i)`,
+    /// with 128-bit coefficients `z_i` hashed from the whole batch. Trades a fixed-base
+    /// multiplication per signature for a variable-base one on `R_i`. Multiplying by the
+    /// cofactor clears any small-order component of a public key or `R`, which the
+    /// coefficients could otherwise cancel out, so this checks the cofactored equation:
+    /// it also accepts signatures whose `R` or public key is off by a small-order point,
+    /// which [`BatchVerification::Individual`] refuses.
+    Randomized,
+}
+
+/// Constrain every entry to be a valid signature, like [`crate::make_ed25519_verification_ciruit`]
+/// does for one. If `register_public_inputs` is set, the message and public key of each
+/// entry are registered in order. The returned targets can be filled with `fill_circuits`.
+pub fn make_batch_ed25519_verification_circuit<F: RichField + Extendable<D>, const D: usize>(
+    builder: &mut CircuitBuilder<F, D>,
+    entries: &[BatchEntryTargets],
+    verification: BatchVerification,
+    register_public_inputs: bool,
+) -> Vec<Ed25519Targets> {
+    assert!(!entries.is_empty(), "a batch needs at least one signature");
+
+    if register_public_inputs {
+        for entry in entries {
+            for target in entry.message.iter().chain(&entry.public_key) {
+                builder.register_public_input(target.target);
+            }
+        }
+    }
+
+    let components: Vec<SignatureComponents> = entries
+        .iter()
+        .map(|entry| {
+            signature_components(builder, &entry.message, &entry.signature, &entry.public_key)
+        })
+        .collect();
+
+    match verification {
+        BatchVerification::Individual => {
+            for SignatureComponents { h, s, a, r } in &components {
+                let ha = builder.curve_scalar_mul_windowed(a, h);
+                let sb = fixed_base_curve_mul_circuit(builder, Ed25519::GENERATOR_AFFINE, s);
+                let rhs = builder.curve_add(r, &ha);
+                builder.connect_affine_point(&sb, &rhs);
+            }
+        }
+        BatchVerification::Randomized => {
+            // the coefficients are derived from every bit of the batch, so they are fixed
+            // only once all signatures are
+            let batch_bits: Vec<_> = entries
+                .iter()
+                .flat_map(|entry| {
+                    entry
+                        .message
+                        .iter()
+                        .chain(&entry.signature)
+                        .chain(&entry.public_key)
+                })
+                .map(|bit| bit.target)
+                .collect();
+            let seed = builder.hash_n_to_hash_no_pad::<PoseidonHash>(batch_bits);
+
+            let mut s_sum = None;
+            let mut rhs = None;
+            for (i, SignatureComponents { h, s, a, r }) in components.iter().enumerate() {
+                let index = builder.constant(F::from_canonical_usize(i));
+                let coefficient_hash = builder.hash_n_to_hash_no_pad::<PoseidonHash>(
+                    seed.elements.iter().copied().chain([index]).collect(),
+                );
+                let mut coefficient_bits: Vec<BoolTarget> = coefficient_hash.elements
+                    [..BATCH_COEFFICIENT_BITS / 64]
+                    .iter()
+                    .flat_map(|element| builder.split_le(*element, 64))
+                    .collect();
+                // bits_to_biguint_target takes the most significant bit first
+                coefficient_bits.reverse();
+                let coefficient_biguint = bits_to_biguint_target(builder, coefficient_bits);
+                let z = builder.biguint_to_nonnative(&coefficient_biguint);
+
+                let zs = builder.mul_nonnative(&z, s);
+                let zh = builder.mul_nonnative(&z, h);
+                let zr = builder.curve_scalar_mul_windowed(r, &z);
+                let zha = builder.curve_scalar_mul_windowed(a, &zh);
+                let term = builder.curve_add(&zr, &zha);
+
+                s_sum = Some(match s_sum {
+                    None => zs,
+                    Some(sum) => builder.add_nonnative(&sum, &zs),
+                });
+                rhs = Some(match rhs {
+                    None => term,
+                    Some(sum) => builder.curve_add(&sum, &term),
+                });
+            }
+
+            let s_sum = s_sum.expect("batch is not empty");
+            let rhs = rhs.expect("batch is not empty");
+            let sb = fixed_base_curve_mul_circuit(builder, Ed25519::GENERATOR_AFFINE, &s_sum);
+            let sb = clear_cofactor(builder, &sb);
+            let rhs = clear_cofactor(builder, &rhs);
+            builder.connect_affine_point(&sb, &rhs);
+        }
+    }
+
+    entries
+        .iter()
+        .map(|entry| Ed25519Targets {
+            msg: entry.message.clone(),
+            sig: entry.signature.clone(),
+            pk: entry.public_key.clone(),
+        })
+        .collect()
+}
+
+/// multiply `point` by the cofactor 8, with three doublings
+fn clear_cofactor<F: RichField + Extendable<D>, const D: usize>(
+    builder: &mut CircuitBuilder<F, D>,
+    point: &AffinePointTarget<Ed25519>,
+) -> AffinePointTarget<Ed25519> {
+    let twice = builder.curve_double(point);
+    let four_times = builder.curve_double(&twice);
+    builder.curve_double(&four_times)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        add_virtual_bool_targets, verify_ed25519_natively, VerificationMode,
+        PUBLIC_KEY_LENGTH_BITS, SIGNATURE_LENGTH_BITS,
+    };
+    use ed25519_dalek::{Keypair, Signer};
+    use plonky2::iop::witness::PartialWitness;
+    use plonky2::plonk::{
+        circuit_data::CircuitConfig,
+        config::{GenericConfig, PoseidonGoldilocksConfig},
+    };
+    use plonky2_ed25519::gadgets::eddsa::fill_circuits;
+    use rand::rngs::OsRng;
+
+    const D: usize = 2;
+    type C = PoseidonGoldilocksConfig;
+    type F = <C as GenericConfig<D>>::F;
+
+    fn prove_batch(verification: BatchVerification, forge_last: bool) -> bool {
+        let mut csprng = OsRng {};
+        let messages: Vec<Vec<u8>> = vec![b"dos".to_vec(), vec![1u8; 32], vec![2u8; 40]];
+
+        let mut builder = CircuitBuilder::<F, D>::new(CircuitConfig::wide_ecc_config());
+        let entries: Vec<BatchEntryTargets> = messages
+            .iter()
+            .map(|message| BatchEntryTargets {
+                message: add_virtual_bool_targets(&mut builder, message.len() * 8),
+                signature: add_virtual_bool_targets(&mut builder, SIGNATURE_LENGTH_BITS),
+                public_key: add_virtual_bool_targets(&mut builder, PUBLIC_KEY_LENGTH_BITS),
+            })
+            .collect();
+        let targets =
+            make_batch_ed25519_verification_circuit(&mut builder, &entries, verification, true);
+
+        let mut partial_witness = PartialWitness::<F>::new();
+        for (i, (message, targets)) in messages.iter().zip(&targets).enumerate() {
+            let key_pair = Keypair::generate(&mut csprng);
+            let signed: &[u8] = if forge_last && i == messages.len() - 1 {
+                b"something else"
+            } else {
+                message
+            };
+            let signature = key_pair.sign(signed).to_bytes();
+            let public_key = key_pair.public.to_bytes();
+            assert_eq!(
+                verify_ed25519_natively(message, &signature, &public_key, VerificationMode::Strict)
+                    .is_ok(),
+                signed == message.as_slice()
+            );
+            fill_circuits(
+                &mut partial_witness,
+                message,
+                &signature,
+                &public_key,
+                targets,
+            );
+        }
+
+        let circuit_data = builder.build::<C>();
+        // an invalid signature makes witness generation fail, which panics
+        let proved = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            circuit_data.prove(partial_witness)
+        }));
+        match proved {
+            Ok(Ok(proof_with_pis)) => circuit_data.verify(proof_with_pis).is_ok(),
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn it_works_batch_verification() {
+        for verification in [BatchVerification::Individual, BatchVerification::Randomized] {
+            assert!(prove_batch(verification, false));
+        }
+    }
+
+    #[test]
+    fn it_rejects_batch_with_one_invalid_signature() {
+        for verification in [BatchVerification::Individual, BatchVerification::Randomized] {
+            assert!(!prove_batch(verification, true));
+        }
+    }
+}
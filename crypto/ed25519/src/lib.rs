@@ -1,17 +1,26 @@
+use plonky2::field::types::{Field, PrimeField};
 use plonky2::iop::target::BoolTarget;
 use plonky2::{
     field::extension::Extendable, hash::hash_types::RichField, iop::witness::PartialWitness,
     plonk::circuit_builder::CircuitBuilder,
 };
-use plonky2_ed25519::gadgets::eddsa::{EDDSATargets, fill_circuits, make_verify_circuits};
 use plonky2_ed25519::curve::curve_types::Curve;
 use plonky2_ed25519::curve::ed25519::Ed25519;
-use plonky2_ed25519::gadgets::curve::CircuitBuilderCurve;
+use plonky2_ed25519::field::ed25519_scalar::Ed25519Scalar;
+use plonky2_ed25519::gadgets::biguint::CircuitBuilderBiguint;
+use plonky2_ed25519::gadgets::curve::{AffinePointTarget, CircuitBuilderCurve};
 use plonky2_ed25519::gadgets::curve_fixed_base::fixed_base_curve_mul_circuit;
 use plonky2_ed25519::gadgets::curve_windowed_mul::CircuitBuilderWindowedMul;
-use plonky2_ed25519::gadgets::nonnative::CircuitBuilderNonNative;
+use plonky2_ed25519::gadgets::eddsa::{fill_circuits, EDDSATargets};
+use plonky2_ed25519::gadgets::nonnative::{CircuitBuilderNonNative, NonNativeTarget};
 use plonky2_sha512::circuit::{bits_to_biguint_target, make_circuits};
 
+mod batch;
+mod native;
+
+pub use batch::{make_batch_ed25519_verification_circuit, BatchEntryTargets, BatchVerification};
+pub use native::{verify_ed25519_natively, Ed25519Error, VerificationMode};
+
 // we can hash messages to a 256bit hash.
 pub type MessageDigest = [u8; 32];
 // ed25519 signatures are 512bits long.
@@ -19,6 +28,9 @@ pub type Signature = [u8; 64];
 // ed25519 public key is 256 bits long.
 pub type PublicKey = [u8; 32];
 
+pub const SIGNATURE_LENGTH_BITS: usize = 512;
+pub const PUBLIC_KEY_LENGTH_BITS: usize = 256;
+
 pub type Ed25519Targets = EDDSATargets;
 
 // todo: I dont know if Jorge depends on the above,
@@ -29,19 +41,31 @@ pub struct Ed25519TargetsHomeGrown {
     pub(crate) public_key: Vec<BoolTarget>,
 }
 
+/// Add a circuit proving `signature` of `message` by `public_key`, and fill its witness.
+/// The signature is verified natively in `mode` first, so a bad signature is rejected
+/// before any constraint gets built.
 pub fn add_eddsa_targets<F: RichField + Extendable<D>, const D: usize>(
     builder: &mut CircuitBuilder<F, D>,
     partial_witness: &mut PartialWitness<F>,
     message: MessageDigest,
     signature: Signature,
     public_key: PublicKey,
-) {
+    mode: VerificationMode,
+) -> Result<Ed25519Targets, Ed25519Error> {
     // first verify the signature before attempting to prove it
-    // todo:
+    verify_ed25519_natively(&message, &signature, &public_key, mode)?;
 
-    // build verification circuit
-    let msg_len = message.len();
-    let eddsa_targets = make_verify_circuits(builder, msg_len);
+    // build verification circuit, with the message and public key as public inputs
+    let message_targets = add_virtual_bool_targets(builder, message.len() * 8);
+    let signature_targets = add_virtual_bool_targets(builder, SIGNATURE_LENGTH_BITS);
+    let public_key_targets = add_virtual_bool_targets(builder, PUBLIC_KEY_LENGTH_BITS);
+    let eddsa_targets = make_ed25519_verification_ciruit(
+        builder,
+        &message_targets,
+        &signature_targets,
+        &public_key_targets,
+        true,
+    );
 
     fill_circuits(
         partial_witness,
@@ -50,66 +74,112 @@ pub fn add_eddsa_targets<F: RichField + Extendable<D>, const D: usize>(
         public_key.as_ref(),
         &eddsa_targets,
     );
+
+    Ok(eddsa_targets)
+}
+
+/// allocate `count` boolean targets, constrained to be 0 or 1
+pub fn add_virtual_bool_targets<F: RichField + Extendable<D>, const D: usize>(
+    builder: &mut CircuitBuilder<F, D>,
+    count: usize,
+) -> Vec<BoolTarget> {
+    (0..count)
+        .map(|_| builder.add_virtual_bool_target_safe())
+        .collect()
 }
 
+/// Constrain `signature_targets` to be a valid ed25519 signature of `message_targets` by
+/// `public_key_targets`. All targets are bits (in the byte order of `array_to_bits`) owned
+/// by the caller, who must have constrained them to be boolean; the message can have any
+/// whole number of bytes. The message and public key are registered as public inputs only
+/// if `register_public_inputs` is set, so the caller controls its public input layout.
+/// The returned targets can be filled with `fill_circuits`.
 pub fn make_ed25519_verification_ciruit<F: RichField + Extendable<D>, const D: usize>(
     builder: &mut CircuitBuilder<F, D>,
-    message_targets: &Vec<BoolTarget>,
-    signature_targets: &Vec<BoolTarget>,
-    public_key_targets: &Vec<BoolTarget>,
-    message_length: usize,
+    message_targets: &[BoolTarget],
+    signature_targets: &[BoolTarget],
+    public_key_targets: &[BoolTarget],
+    register_public_inputs: bool,
 ) -> Ed25519Targets {
     // based on work from gh.com/polymerdao/plonky2-ed25519
+    if register_public_inputs {
+        for target in message_targets.iter().chain(public_key_targets) {
+            builder.register_public_input(target.target);
+        }
+    }
 
-    let message_length_in_bits: usize = message_length * 8;
-    let sha512_msg_len = msg_len_in_bits + 512;
-    let sha512 = make_circuits(builder, sha512_msg_len as u128);
+    let SignatureComponents { h, s, a, r } = signature_components(
+        builder,
+        message_targets,
+        signature_targets,
+        public_key_targets,
+    );
 
-    // let mut msg = Vec::new();
-    // let mut sig = Vec::new();
-    // let mut pk = Vec::new();
-    for i in 0..msg_len_in_bits {
-        builder.register_public_input(sha512.message[512 + i].target);
-        message_targets.push(sha512.message[512 + i]);
-    }
-    for _ in 0..512 {
-        signature_targets.push(builder.add_virtual_bool_target_unsafe());
-    }
-    for _ in 0..256 {
-        let t = builder.add_virtual_bool_target_unsafe();
-        builder.register_public_input(t.target);
-        pk.push(t);
+    // s * B == R + h * A
+    let ha = builder.curve_scalar_mul_windowed(&a, &h);
+    let sb = fixed_base_curve_mul_circuit(builder, Ed25519::GENERATOR_AFFINE, &s);
+    let rhs = builder.curve_add(&r, &ha);
+    builder.connect_affine_point(&sb, &rhs);
+
+    Ed25519Targets {
+        msg: message_targets.to_vec(),
+        sig: signature_targets.to_vec(),
+        pk: public_key_targets.to_vec(),
     }
+}
+
+/// The parts of the verification equation `s * B == R + h * A` of one signature.
+pub(crate) struct SignatureComponents {
+    pub(crate) h: NonNativeTarget<Ed25519Scalar>,
+    pub(crate) s: NonNativeTarget<Ed25519Scalar>,
+    pub(crate) a: AffinePointTarget<Ed25519>,
+    pub(crate) r: AffinePointTarget<Ed25519>,
+}
+
+pub(crate) fn signature_components<F: RichField + Extendable<D>, const D: usize>(
+    builder: &mut CircuitBuilder<F, D>,
+    message_targets: &[BoolTarget],
+    signature_targets: &[BoolTarget],
+    public_key_targets: &[BoolTarget],
+) -> SignatureComponents {
+    assert_eq!(message_targets.len() % 8, 0, "message must be whole bytes");
+    assert_eq!(signature_targets.len(), SIGNATURE_LENGTH_BITS);
+    assert_eq!(public_key_targets.len(), PUBLIC_KEY_LENGTH_BITS);
+
+    // h = sha512(R || A || M)
+    let message_length_in_bits = message_targets.len();
+    let sha512_msg_len = message_length_in_bits + 512;
+    let sha512 = make_circuits(builder, sha512_msg_len as u128);
+
     for i in 0..256 {
-        builder.connect(sha512.message[i].target, sig[i].target);
+        builder.connect(sha512.message[i].target, signature_targets[i].target);
     }
     for i in 0..256 {
-        builder.connect(sha512.message[256 + i].target, pk[i].target);
+        builder.connect(sha512.message[256 + i].target, public_key_targets[i].target);
+    }
+    for i in 0..message_length_in_bits {
+        builder.connect(sha512.message[512 + i].target, message_targets[i].target);
     }
 
     let digest_bits = bits_in_le(sha512.digest.clone());
     let hash = bits_to_biguint_target(builder, digest_bits);
     let h = builder.reduce(&hash);
 
-    let s_bits = bits_in_le(sig[256..512].to_vec());
+    let s_bits = bits_in_le(signature_targets[256..512].to_vec());
     let s_biguint = bits_to_biguint_target(builder, s_bits);
+    // s must be reduced, s < L, otherwise s and s + L would both verify
+    let largest_scalar = builder.constant_biguint(&Ed25519Scalar::NEG_ONE.to_canonical_biguint());
+    let s_is_reduced = builder.cmp_biguint(&s_biguint, &largest_scalar);
+    builder.assert_one(s_is_reduced.target);
     let s = builder.biguint_to_nonnative(&s_biguint);
 
-    let pk_bits = bits_in_le(pk.clone());
+    let pk_bits = bits_in_le(public_key_targets.to_vec());
     let a = builder.point_decompress(&pk_bits);
 
-    let ha = builder.curve_scalar_mul_windowed(&a, &h);
-
-    let r_bits = bits_in_le(sig[..256].to_vec());
+    let r_bits = bits_in_le(signature_targets[..256].to_vec());
     let r = builder.point_decompress(&r_bits);
 
-    let sb = fixed_base_curve_mul_circuit(builder, Ed25519::GENERATOR_AFFINE, &s);
-    let rhs = builder.curve_add(&r, &ha);
-    builder.connect_affine_point(&sb, &rhs);
-
-    return Ed25519Targets { msg, sig, pk };
-
-    // make_verify_circuits(builder, message_length)
+    SignatureComponents { h, s, a, r }
 }
 
 // thanks, taken from gh.com/polymerdao/plonky2-ed25519
@@ -122,4 +192,133 @@ fn bits_in_le(input_vec: Vec<BoolTarget>) -> Vec<BoolTarget> {
     }
     bits.reverse();
     bits
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use curve25519_dalek::constants::BASEPOINT_ORDER;
+    use ed25519_dalek::{Keypair, Signer};
+    use plonky2::plonk::{
+        circuit_data::CircuitConfig,
+        config::{GenericConfig, PoseidonGoldilocksConfig},
+    };
+    use plonky2_sha512::circuit::array_to_bits;
+    use rand::rngs::OsRng;
+
+    const D: usize = 2;
+    type C = PoseidonGoldilocksConfig;
+    type F = <C as GenericConfig<D>>::F;
+
+    /// prove a signature with the gadget, and check the result against the native verifier
+    fn prove_signature(message: &[u8], signature: Signature, public_key: PublicKey) -> bool {
+        let mut builder = CircuitBuilder::<F, D>::new(CircuitConfig::wide_ecc_config());
+        let message_targets = add_virtual_bool_targets(&mut builder, message.len() * 8);
+        let signature_targets = add_virtual_bool_targets(&mut builder, SIGNATURE_LENGTH_BITS);
+        let public_key_targets = add_virtual_bool_targets(&mut builder, PUBLIC_KEY_LENGTH_BITS);
+        let targets = make_ed25519_verification_ciruit(
+            &mut builder,
+            &message_targets,
+            &signature_targets,
+            &public_key_targets,
+            true,
+        );
+
+        let mut partial_witness = PartialWitness::<F>::new();
+        fill_circuits(
+            &mut partial_witness,
+            message,
+            &signature,
+            &public_key,
+            &targets,
+        );
+
+        let circuit_data = builder.build::<PoseidonGoldilocksConfig>();
+        // an invalid signature makes witness generation fail, which panics
+        let proved = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            circuit_data.prove(partial_witness)
+        }));
+        match proved {
+            Ok(Ok(proof_with_pis)) => {
+                // the message and public key bits are the public inputs
+                let expected: Vec<F> = array_to_bits(message)
+                    .into_iter()
+                    .chain(array_to_bits(&public_key))
+                    .map(F::from_bool)
+                    .collect();
+                assert_eq!(proof_with_pis.public_inputs, expected);
+                circuit_data.verify(proof_with_pis).is_ok()
+            }
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn it_works_gadget_matches_native_verification() {
+        let mut csprng = OsRng {};
+        let key_pair = Keypair::generate(&mut csprng);
+        let public_key = key_pair.public.to_bytes();
+
+        // messages of arbitrary length
+        for message in [b"dos".to_vec(), vec![7u8; 32], vec![42u8; 100]] {
+            let signature = key_pair.sign(&message).to_bytes();
+            assert!(verify_ed25519_natively(
+                &message,
+                &signature,
+                &public_key,
+                VerificationMode::Legacy
+            )
+            .is_ok());
+            assert!(prove_signature(&message, signature, public_key));
+        }
+
+        // a signature over another message
+        let signature = key_pair.sign(b"something else").to_bytes();
+        assert!(
+            verify_ed25519_natively(b"dos", &signature, &public_key, VerificationMode::Legacy)
+                .is_err()
+        );
+        assert!(!prove_signature(b"dos", signature, public_key));
+    }
+
+    #[test]
+    fn it_refuses_unreduced_s() {
+        let key_pair = Keypair::generate(&mut OsRng {});
+        let public_key = key_pair.public.to_bytes();
+        let mut signature = key_pair.sign(b"dos").to_bytes();
+
+        // s + L satisfies the equation as well, but is not the signature that was made
+        let mut carry = 0u16;
+        for (s_byte, order_byte) in signature[32..].iter_mut().zip(BASEPOINT_ORDER.as_bytes()) {
+            let sum = *s_byte as u16 + *order_byte as u16 + carry;
+            *s_byte = sum as u8;
+            carry = sum >> 8;
+        }
+        assert_eq!(
+            verify_ed25519_natively(b"dos", &signature, &public_key, VerificationMode::Legacy),
+            Err(Ed25519Error::NonCanonicalS)
+        );
+        assert!(!prove_signature(b"dos", signature, public_key));
+    }
+
+    #[test]
+    fn it_rejects_invalid_signature_before_building_circuit() {
+        let key_pair = Keypair::generate(&mut OsRng {});
+        let message: MessageDigest = [7u8; 32];
+        let signature = key_pair.sign(b"something else").to_bytes();
+
+        let mut builder = CircuitBuilder::<F, D>::new(CircuitConfig::wide_ecc_config());
+        let mut partial_witness = PartialWitness::<F>::new();
+        let result = add_eddsa_targets(
+            &mut builder,
+            &mut partial_witness,
+            message,
+            signature,
+            key_pair.public.to_bytes(),
+            VerificationMode::Strict,
+        );
+
+        assert_eq!(result.err(), Some(Ed25519Error::InvalidSignature));
+        assert_eq!(builder.num_gates(), 0);
+    }
+}
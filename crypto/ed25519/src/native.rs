@@ -0,0 +1,169 @@
+//! Native ed25519 verification, run before anything gets proven.
+//!
+//! The circuit checks the cofactorless equation `s * B == R + h * A` and fails only during
+//! witness generation, after the whole circuit has been built. Checking the signature here
+//! first is cheap and gives a typed error instead.
+
+use curve25519_dalek::edwards::{CompressedEdwardsY, EdwardsPoint};
+use curve25519_dalek::scalar::Scalar;
+use ed25519_dalek::Verifier;
+use thiserror::Error;
+
+use crate::{PublicKey, Signature};
+
+/// How strictly to check a signature before proving it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VerificationMode {
+    /// Only canonical encodings of the public key, `R` and `s`, and no small-order public
+    /// key or `R`. A signature accepted in this mode verifies under every implementation.
+    #[default]
+    Strict,
+    /// Whatever the cofactorless equation accepts with a reduced `s`, which is what the
+    /// circuit checks: it accepts non-canonical and small-order points like this mode
+    /// does. Needed for signatures made by older signers.
+    Legacy,
+}
+
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+pub enum Ed25519Error {
+    #[error("public key is not a point on the curve")]
+    InvalidPublicKey,
+    #[error("public key is not a canonical point encoding")]
+    NonCanonicalPublicKey,
+    #[error("public key has small order")]
+    SmallOrderPublicKey,
+    #[error("signature R is not a point on the curve")]
+    InvalidR,
+    #[error("signature R is not a canonical point encoding")]
+    NonCanonicalR,
+    #[error("signature R has small order")]
+    SmallOrderR,
+    #[error("signature s is not a canonical scalar")]
+    NonCanonicalS,
+    #[error("signature does not verify")]
+    InvalidSignature,
+}
+
+/// Verify `signature` of `message` by `public_key` outside of the circuit.
+pub fn verify_ed25519_natively(
+    message: &[u8],
+    signature: &Signature,
+    public_key: &PublicKey,
+    mode: VerificationMode,
+) -> Result<(), Ed25519Error> {
+    if mode == VerificationMode::Strict {
+        let a = decompress_canonical(public_key).map_err(|e| match e {
+            PointError::Invalid => Ed25519Error::InvalidPublicKey,
+            PointError::NonCanonical => Ed25519Error::NonCanonicalPublicKey,
+        })?;
+        if a.is_small_order() {
+            return Err(Ed25519Error::SmallOrderPublicKey);
+        }
+
+        let r_bytes: [u8; 32] = signature[..32].try_into().expect("signature has 64 bytes");
+        let r = decompress_canonical(&r_bytes).map_err(|e| match e {
+            PointError::Invalid => Ed25519Error::InvalidR,
+            PointError::NonCanonical => Ed25519Error::NonCanonicalR,
+        })?;
+        if r.is_small_order() {
+            return Err(Ed25519Error::SmallOrderR);
+        }
+    }
+
+    // the circuit range checks s < L as well, the dalek verifier lets some larger s through
+    let s_bytes: [u8; 32] = signature[32..].try_into().expect("signature has 64 bytes");
+    if Scalar::from_canonical_bytes(s_bytes).is_none() {
+        return Err(Ed25519Error::NonCanonicalS);
+    }
+
+    let public_key = ed25519_dalek::PublicKey::from_bytes(public_key)
+        .map_err(|_| Ed25519Error::InvalidPublicKey)?;
+    let signature =
+        ed25519_dalek::Signature::from_bytes(signature).map_err(|_| Ed25519Error::NonCanonicalS)?;
+    // both modes check the cofactorless equation, like the circuit does
+    public_key
+        .verify(message, &signature)
+        .map_err(|_| Ed25519Error::InvalidSignature)
+}
+
+enum PointError {
+    Invalid,
+    NonCanonical,
+}
+
+// decompression reduces the y coordinate mod p, so a point is canonical only if it
+// compresses back to the same bytes
+fn decompress_canonical(bytes: &[u8; 32]) -> Result<EdwardsPoint, PointError> {
+    let compressed = CompressedEdwardsY(*bytes);
+    let point = compressed.decompress().ok_or(PointError::Invalid)?;
+    if point.compress() != compressed {
+        return Err(PointError::NonCanonical);
+    }
+    Ok(point)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use curve25519_dalek::constants::BASEPOINT_ORDER;
+    use ed25519_dalek::{Keypair, Signer};
+    use rand::rngs::OsRng;
+
+    #[test]
+    fn it_works_native_verification() {
+        let key_pair = Keypair::generate(&mut OsRng {});
+        let public_key = key_pair.public.to_bytes();
+        let signature = key_pair.sign(b"dos").to_bytes();
+
+        for mode in [VerificationMode::Strict, VerificationMode::Legacy] {
+            assert_eq!(
+                verify_ed25519_natively(b"dos", &signature, &public_key, mode),
+                Ok(())
+            );
+            assert_eq!(
+                verify_ed25519_natively(b"sod", &signature, &public_key, mode),
+                Err(Ed25519Error::InvalidSignature)
+            );
+        }
+    }
+
+    #[test]
+    fn it_rejects_non_canonical_and_small_order_encodings() {
+        let key_pair = Keypair::generate(&mut OsRng {});
+        let public_key = key_pair.public.to_bytes();
+        let signature = key_pair.sign(b"dos").to_bytes();
+        let strict = VerificationMode::Strict;
+
+        // the identity has small order
+        let mut identity = [0u8; 32];
+        identity[0] = 1;
+        assert_eq!(
+            verify_ed25519_natively(b"dos", &signature, &identity, strict),
+            Err(Ed25519Error::SmallOrderPublicKey)
+        );
+
+        // y = p is a non-canonical encoding of y = 0
+        let mut y_is_p = [0xffu8; 32];
+        y_is_p[0] = 0xed;
+        y_is_p[31] = 0x7f;
+        assert_eq!(
+            verify_ed25519_natively(b"dos", &signature, &y_is_p, strict),
+            Err(Ed25519Error::NonCanonicalPublicKey)
+        );
+
+        let mut small_order_r = signature;
+        small_order_r[..32].copy_from_slice(&identity);
+        assert_eq!(
+            verify_ed25519_natively(b"dos", &small_order_r, &public_key, strict),
+            Err(Ed25519Error::SmallOrderR)
+        );
+
+        // s must be reduced mod the group order
+        let mut unreduced_s = signature;
+        unreduced_s[32..].copy_from_slice(BASEPOINT_ORDER.as_bytes());
+        assert_eq!(
+            verify_ed25519_natively(b"dos", &unreduced_s, &public_key, strict),
+            Err(Ed25519Error::NonCanonicalS)
+        );
+    }
+}
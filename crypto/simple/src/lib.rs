@@ -1,12 +1,11 @@
+use anyhow::{anyhow, Result};
 use plonky2::{
-    field::types::{Field, Sample},
+    field::types::{Field, Field64, PrimeField64, Sample},
+    hash::poseidon::PoseidonHash,
     plonk::{
-        circuit_builder::CircuitBuilder,
-        circuit_data::CircuitData,
-        config::{Hasher, GenericConfig, PoseidonGoldilocksConfig},
+        config::{GenericConfig, Hasher, PoseidonGoldilocksConfig},
         proof::ProofWithPublicInputs,
     },
-    hash::{poseidon::PoseidonHash},
 };
 
 pub const D: usize = 2;
@@ -29,15 +28,53 @@ pub struct KeyPair {
 }
 
 impl KeyPair {
-    pub fn generate_key_pair() -> Self {
-        let private_key: PrivateKey = [F::rand(); 4];
-        let public_key: PublicKey = 
-            PoseidonHash::hash_no_pad(
-                &[private_key, PUBLIC_KEY_POSTFIX].concat())
-                .elements;
+    pub fn from_private_key(private_key: PrivateKey) -> Self {
+        let public_key: PublicKey =
+            PoseidonHash::hash_no_pad(&[private_key, PUBLIC_KEY_POSTFIX].concat()).elements;
         KeyPair {
             public_key,
             private_key,
         }
     }
+
+    pub fn generate_key_pair() -> Self {
+        // four independent draws, not one draw repeated
+        let private_key: PrivateKey = F::rand_array();
+        Self::from_private_key(private_key)
+    }
+}
+
+/// The pseudonym of a private key towards a verifier: the public key derivation with the
+/// verifier id in place of [`PUBLIC_KEY_POSTFIX`]. Every verifier sees a stable pseudonym,
+/// and the pseudonyms seen by distinct verifiers cannot be linked without the private key.
+pub fn derive_pseudonym(private_key: PrivateKey, verifier_id: Digest) -> Digest {
+    PoseidonHash::hash_no_pad(&[private_key, verifier_id].concat()).elements
+}
+
+/// encode field elements as concatenated little-endian u64s
+pub fn field_elements_to_bytes(elements: &[F]) -> Vec<u8> {
+    elements
+        .iter()
+        .flat_map(|element| element.to_canonical_u64().to_le_bytes())
+        .collect()
+}
+
+/// decode concatenated little-endian u64s into field elements,
+/// rejecting non-canonical encodings
+pub fn field_elements_from_bytes(bytes: &[u8]) -> Result<Vec<F>> {
+    if bytes.len() % 8 != 0 {
+        return Err(anyhow!(
+            "field element encoding must be a multiple of 8 bytes"
+        ));
+    }
+    bytes
+        .chunks_exact(8)
+        .map(|chunk| {
+            let value = u64::from_le_bytes(chunk.try_into().expect("chunk is 8 bytes"));
+            if value >= F::ORDER {
+                return Err(anyhow!("non-canonical field element {value}"));
+            }
+            Ok(F::from_canonical_u64(value))
+        })
+        .collect()
 }
@@ -0,0 +1,44 @@
+//! Backend independent voucher interface.
+//!
+//! Both the Poseidon vouchers of `dos` and the ed25519 vouchers of `dos-ed25519` implement
+//! [`Voucher`], so applications can be written once, against `Box<dyn Voucher>`. Keys are
+//! passed around in the encoding of their backend.
+
+use anyhow::Result;
+
+/// What a voucher proof attests, decoded from its public inputs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VoucherPublicInputs {
+    pub origin: Vec<u8>,
+    pub locus: Vec<u8>,
+    pub degree: u64,
+}
+
+/// A proof that `locus` is `degree` hops away from `origin`.
+pub trait Voucher: Send + Sync {
+    /// the encoded public key of the origin
+    fn origin(&self) -> Vec<u8>;
+
+    /// the encoded public key of the locus
+    fn locus(&self) -> Vec<u8>;
+
+    fn degree(&self) -> u64;
+
+    fn is_origin(&self) -> bool {
+        self.degree() == 0
+    }
+
+    /// decode the origin, locus and degree the proof attests
+    fn public_inputs(&self) -> Result<VoucherPublicInputs>;
+
+    fn verify(&self) -> bool;
+
+    /// Vouch for `locus` one hop further, with the encoded secret key of the current locus.
+    fn extend(&self, locus_secret_key: &[u8], locus: &[u8]) -> Result<Box<dyn Voucher>>;
+
+    /// Encode the voucher, to be decoded by the `from_bytes` of its backend. Encodings start
+    /// with the origin, locus and degree, but that header is only a convenience: the proof
+    /// is what binds them, so decoders check the header against the public inputs of the
+    /// proof and refuse the voucher if they differ.
+    fn to_bytes(&self) -> Vec<u8>;
+}
@@ -0,0 +1,396 @@
+//! Bridge from an ed25519 origin voucher into the Poseidon vouchers of the `dos` crate.
+//!
+//! The ed25519 origin signs the Poseidon public key of the first locus, prefixed with
+//! [`BRIDGE_LOCUS_CONTEXT`] so that the signature cannot be mistaken for a signature over
+//! an ed25519 locus or any other message of the origin key. The bridge circuit
+//! verifies the origin voucher recursively and that signature once, and exposes public
+//! inputs in the `dos` layout (origin, locus, signature, degree, capabilities, score,
+//! maximum degree, epoch scope, epoch), so every later hop is a cheap `dos` extension. The
+//! origin of a bridged path is the Poseidon digest of the ed25519 origin bits, see
+//! [`ed25519_origin_digest`].
+//!
+//! The circuits of a bridged path differ from those of a path from a `dos` origin voucher,
+//! so a bridged path decodes with [`bridged_voucher_from_bytes`] rather than
+//! [`dos::voucher::Voucher::from_bytes`].
+
+use plonky2::{
+    field::types::PrimeField64,
+    hash::poseidon::PoseidonHash,
+    iop::target::Target,
+    plonk::{
+        circuit_data::VerifierCircuitTarget, config::Hasher, proof::ProofWithPublicInputsTarget,
+    },
+};
+
+use anyhow::{anyhow, Result};
+use dos::{
+    capabilities::Capabilities,
+    circuit_builder::{DEFAULT_MAX_DEGREE, MAX_TRUST_WEIGHT},
+    epoch::DEFAULT_EPOCH_SCOPE,
+};
+use ed25519_proofs::{
+    add_virtual_bool_targets, make_ed25519_verification_ciruit, verify_ed25519_natively,
+    VerificationMode,
+};
+
+use crate::{
+    utils::get_circuit_builder_and_partial_witness,
+    voucher::{voucher_circuit_data, Ed25519Voucher, ORIGIN_PUBLIC_INPUTS},
+    *,
+};
+
+/// Length of a Poseidon public key of the `dos` crate, in field elements.
+pub use simple_crypto::PUBLIC_KEY_LENGTH as POSEIDON_KEY_LENGTH;
+
+pub type PoseidonPublicKey = simple_crypto::PublicKey;
+
+/// The capabilities a bridged voucher grants: all of them, like a `dos` origin voucher,
+/// extensions narrow them down from there.
+pub const BRIDGE_CAPABILITIES: Capabilities = Capabilities::ALL;
+
+/// The score a bridged voucher starts with, the highest trust weight of `dos`: the ed25519
+/// origin signed the locus itself.
+pub const BRIDGE_SCORE: u64 = MAX_TRUST_WEIGHT;
+
+/// The maximum degree of a bridged path, the default of `dos` origin vouchers.
+pub const BRIDGE_MAX_DEGREE: u64 = DEFAULT_MAX_DEGREE;
+
+/// The epoch of a bridged path, in the default epoch scope: the ed25519 origin does not
+/// announce epochs, so verifiers that follow it admit the bridged paths it issued.
+pub const BRIDGE_EPOCH: u64 = 0;
+
+/// The context an ed25519 origin prefixes the Poseidon locus with before signing it.
+pub const BRIDGE_LOCUS_CONTEXT: &[u8; 24] = b"dos-ed25519 bridge locus";
+
+/// The length of the message an ed25519 origin signs to vouch for a Poseidon locus.
+pub const BRIDGE_MESSAGE_LENGTH: usize = BRIDGE_LOCUS_CONTEXT.len() + 8 * POSEIDON_KEY_LENGTH;
+
+pub struct BridgeTargets {
+    pub(crate) inner_proof: ProofWithPublicInputsTarget<D>,
+    pub(crate) locus: Vec<Target>,
+    pub(crate) signature: Vec<BoolTarget>,
+}
+
+/// A degree 1 voucher rooted in an ed25519 key, for a Poseidon locus.
+pub struct BridgeVoucher {
+    pub(crate) origin: PublicKey,
+    pub(crate) locus: PoseidonPublicKey,
+    pub(crate) circuit_data: CircuitData<F, C, D>,
+    pub(crate) proof_data: ProofWithPublicInputs<F, C, D>,
+}
+
+/// The Poseidon identity of an ed25519 origin: the hash of its bits.
+pub fn ed25519_origin_digest(origin: &PublicKey) -> PoseidonPublicKey {
+    let origin_bits: Vec<F> = array_to_bits(origin)
+        .into_iter()
+        .map(F::from_bool)
+        .collect();
+    PoseidonHash::hash_no_pad(&origin_bits).elements
+}
+
+/// The message an ed25519 origin signs to vouch for a Poseidon locus:
+/// [`BRIDGE_LOCUS_CONTEXT`] followed by the locus elements as little-endian u64s.
+pub fn poseidon_locus_message(locus: &PoseidonPublicKey) -> [u8; BRIDGE_MESSAGE_LENGTH] {
+    let mut message = [0u8; BRIDGE_MESSAGE_LENGTH];
+    let (context, locus_bytes) = message.split_at_mut(BRIDGE_LOCUS_CONTEXT.len());
+    context.copy_from_slice(BRIDGE_LOCUS_CONTEXT);
+    for (chunk, element) in locus_bytes.chunks_mut(8).zip(locus) {
+        chunk.copy_from_slice(&element.to_canonical_u64().to_le_bytes());
+    }
+    message
+}
+
+/// Build the bridge circuit over origin vouchers proven with `origin_circuit_data`. The
+/// public inputs are the origin digest, the locus, Poseidon(origin digest || locus), the
+/// degree, which is one, [`BRIDGE_CAPABILITIES`], [`BRIDGE_SCORE`], [`BRIDGE_MAX_DEGREE`],
+/// the default epoch scope and [`BRIDGE_EPOCH`].
+pub fn make_bridge_circuit(
+    circuit_builder: &mut CircuitBuilder<F, D>,
+    origin_circuit_data: &CircuitData<F, C, D>,
+) -> BridgeTargets {
+    // verify the origin voucher, with constant verifier data like the path circuit
+    let inner_proof = circuit_builder.add_virtual_proof_with_pis(&origin_circuit_data.common);
+    let inner_verifier_data = VerifierCircuitTarget {
+        constants_sigmas_cap: circuit_builder
+            .constant_merkle_cap(&origin_circuit_data.verifier_only.constants_sigmas_cap),
+        circuit_digest: circuit_builder
+            .constant_hash(origin_circuit_data.verifier_only.circuit_digest),
+    };
+    circuit_builder.verify_proof::<C>(
+        &inner_proof,
+        &inner_verifier_data,
+        &origin_circuit_data.common,
+    );
+
+    // the origin bits are boolean, the origin voucher circuit constrains them
+    let origin_bits: Vec<BoolTarget> = inner_proof.public_inputs[ORIGIN_PUBLIC_INPUTS]
+        .iter()
+        .map(|target| BoolTarget::new_unsafe(*target))
+        .collect();
+    let origin_digest = circuit_builder
+        .hash_n_to_hash_no_pad::<PoseidonHash>(origin_bits.iter().map(|bit| bit.target).collect());
+    let locus = circuit_builder.add_virtual_targets(POSEIDON_KEY_LENGTH);
+
+    // the origin signs the context and then the locus elements as little-endian u64s, and
+    // array_to_bits puts the most significant bit of every byte first
+    let mut message: Vec<BoolTarget> = array_to_bits(BRIDGE_LOCUS_CONTEXT)
+        .into_iter()
+        .map(|bit| circuit_builder.constant_bool(bit))
+        .collect();
+    for element in &locus {
+        let element_bits = split_le_canonical(circuit_builder, *element);
+        for byte in 0..8 {
+            for bit in 0..8 {
+                message.push(element_bits[byte * 8 + 7 - bit]);
+            }
+        }
+    }
+    let signature = add_virtual_bool_targets(circuit_builder, SIGNATURE_LENGTH_BITS);
+    make_ed25519_verification_ciruit(circuit_builder, &message, &signature, &origin_bits, false);
+
+    let signature_digest = circuit_builder.hash_n_to_hash_no_pad::<PoseidonHash>(
+        [origin_digest.elements.to_vec(), locus.clone()].concat(),
+    );
+    let degree = circuit_builder.one();
+    let capabilities = circuit_builder.constant(BRIDGE_CAPABILITIES.to_field());
+    let score = circuit_builder.constant(F::from_canonical_u64(BRIDGE_SCORE));
+    let max_degree = circuit_builder.constant(F::from_canonical_u64(BRIDGE_MAX_DEGREE));
+    let epoch_scope = circuit_builder.constants(&DEFAULT_EPOCH_SCOPE);
+    let epoch = circuit_builder.constant(F::from_canonical_u64(BRIDGE_EPOCH));
+
+    circuit_builder.register_public_inputs(&origin_digest.elements);
+    circuit_builder.register_public_inputs(&locus);
+    circuit_builder.register_public_inputs(&signature_digest.elements);
+    circuit_builder.register_public_input(degree);
+    circuit_builder.register_public_input(capabilities);
+    circuit_builder.register_public_input(score);
+    circuit_builder.register_public_input(max_degree);
+    circuit_builder.register_public_inputs(&epoch_scope);
+    circuit_builder.register_public_input(epoch);
+
+    BridgeTargets {
+        inner_proof,
+        locus,
+        signature,
+    }
+}
+
+/// The 64 little-endian bits of `element`, constrained to its canonical value: on their
+/// own, the bits of `element + p` also sum to `element` whenever they fit in 64 bits.
+fn split_le_canonical(
+    circuit_builder: &mut CircuitBuilder<F, D>,
+    element: Target,
+) -> Vec<BoolTarget> {
+    let bits = circuit_builder.split_le(element, 64);
+    // p = 2^64 - 2^32 + 1, so 64 bits are at least p exactly when the high 32 bits are all
+    // set and the low 32 bits are not all clear
+    let all_set = circuit_builder._true();
+    let high_bits_set = bits[32..]
+        .iter()
+        .fold(all_set, |all_set, bit| circuit_builder.and(all_set, *bit));
+    let low = circuit_builder.le_sum(bits[..32].iter());
+    let overflow = circuit_builder.mul(high_bits_set.target, low);
+    circuit_builder.assert_zero(overflow);
+    bits
+}
+
+/// the (witness independent) circuit data of bridge vouchers
+fn bridge_circuit_data() -> CircuitData<F, C, D> {
+    let origin_circuit_data = voucher_circuit_data(0);
+    let (mut circuit_builder, _) = get_circuit_builder_and_partial_witness();
+    make_bridge_circuit(&mut circuit_builder, &origin_circuit_data);
+    circuit_builder.build::<C>()
+}
+
+/// Deserialize a `dos` voucher whose path starts with a bridge voucher, adopted with
+/// [`dos::voucher::Voucher::from_proof`] and extended with Poseidon hops. The proof is
+/// verified before returning.
+pub fn bridged_voucher_from_bytes(bytes: &[u8]) -> Result<dos::voucher::Voucher> {
+    dos::voucher::Voucher::from_bytes_with_root(bytes, bridge_circuit_data(), 1)
+}
+
+/// Fill the witness of a bridge circuit: `origin_voucher` vouching for `locus`, with the
+/// ed25519 `signature` of [`poseidon_locus_message`] by the origin.
+pub fn fill_bridge_circuit(
+    partial_witness: &mut PartialWitness<F>,
+    bridge_targets: &BridgeTargets,
+    origin_voucher: &impl Ed25519Voucher,
+    locus: PoseidonPublicKey,
+    signature: Signature,
+) {
+    partial_witness
+        .set_proof_with_pis_target(&bridge_targets.inner_proof, origin_voucher.proof_data());
+
+    for i in 0..POSEIDON_KEY_LENGTH {
+        partial_witness.set_target(bridge_targets.locus[i], locus[i]);
+    }
+
+    let signature_bits = array_to_bits(&signature);
+    for i in 0..SIGNATURE_LENGTH_BITS {
+        partial_witness.set_bool_target(bridge_targets.signature[i], signature_bits[i]);
+    }
+}
+
+impl BridgeVoucher {
+    /// Have the origin of `origin_voucher` vouch for a Poseidon `locus`. The signature is
+    /// checked before anything gets proven.
+    pub fn new(
+        origin_voucher: &impl Ed25519Voucher,
+        locus: PoseidonPublicKey,
+        signature: Signature,
+    ) -> Result<Self> {
+        if !origin_voucher.is_origin() {
+            return Err(anyhow!("only origin vouchers can be bridged"));
+        }
+        verify_ed25519_natively(
+            &poseidon_locus_message(&locus),
+            &signature,
+            &origin_voucher.origin_key(),
+            VerificationMode::Strict,
+        )?;
+
+        let (mut circuit_builder, mut partial_witness) = get_circuit_builder_and_partial_witness();
+
+        let bridge_targets =
+            make_bridge_circuit(&mut circuit_builder, origin_voucher.circuit_data());
+        fill_bridge_circuit(
+            &mut partial_witness,
+            &bridge_targets,
+            origin_voucher,
+            locus,
+            signature,
+        );
+
+        let circuit_data = circuit_builder.build::<C>();
+        let proof_data = circuit_data.prove(partial_witness)?;
+
+        Ok(BridgeVoucher {
+            origin: origin_voucher.origin_key(),
+            locus,
+            circuit_data,
+            proof_data,
+        })
+    }
+
+    /// the ed25519 key the path is rooted in
+    pub fn ed25519_origin(&self) -> PublicKey {
+        self.origin
+    }
+
+    /// the origin as seen by the Poseidon extensions
+    pub fn origin(&self) -> PoseidonPublicKey {
+        ed25519_origin_digest(&self.origin)
+    }
+
+    pub fn locus(&self) -> PoseidonPublicKey {
+        self.locus
+    }
+
+    pub fn degree(&self) -> F {
+        F::ONE
+    }
+
+    /// The circuit and proof to hand over to the `dos` extension circuit, which verifies
+    /// any inner proof with the origin, locus, signature, degree, capabilities, score,
+    /// maximum degree, epoch scope and epoch public inputs.
+    pub fn circuit_data(&self) -> &CircuitData<F, C, D> {
+        &self.circuit_data
+    }
+
+    pub fn proof_data(&self) -> &ProofWithPublicInputs<F, C, D> {
+        &self.proof_data
+    }
+
+    pub fn verify(&self) -> bool {
+        let public_inputs = &self.proof_data.public_inputs;
+        public_inputs[..POSEIDON_KEY_LENGTH] == self.origin()
+            && public_inputs[POSEIDON_KEY_LENGTH..2 * POSEIDON_KEY_LENGTH] == self.locus
+            && self.circuit_data.verify(self.proof_data.clone()).is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::original_voucher::make_origin_voucher;
+    use ed25519_dalek::{Keypair, Signer};
+    use rand::rngs::OsRng;
+    use simple_crypto::KeyPair;
+
+    #[test]
+    fn it_works_bridge_voucher() {
+        let mut csprng = OsRng {};
+        let origin_key_pair = Keypair::generate(&mut csprng);
+        let origin = origin_key_pair.public.to_bytes();
+        let origin_voucher =
+            make_origin_voucher(origin, origin_key_pair.sign(&origin).to_bytes()).unwrap();
+
+        let locus: PoseidonPublicKey = [F::ONE, F::TWO, F::ZERO, F::NEG_ONE];
+        let signature = origin_key_pair
+            .sign(&poseidon_locus_message(&locus))
+            .to_bytes();
+        let bridge_voucher = BridgeVoucher::new(&origin_voucher, locus, signature).unwrap();
+
+        assert!(bridge_voucher.verify());
+        let public_inputs = &bridge_voucher.proof_data().public_inputs;
+        assert_eq!(public_inputs[..4], ed25519_origin_digest(&origin));
+        assert_eq!(public_inputs[4..8], locus);
+        assert_eq!(
+            public_inputs[8..12],
+            PoseidonHash::hash_no_pad(&[ed25519_origin_digest(&origin), locus].concat()).elements
+        );
+        assert_eq!(public_inputs[12], F::ONE);
+        assert_eq!(public_inputs[13], BRIDGE_CAPABILITIES.to_field());
+        assert_eq!(public_inputs[14], F::from_canonical_u64(BRIDGE_SCORE));
+        assert_eq!(public_inputs[15], F::from_canonical_u64(BRIDGE_MAX_DEGREE));
+        assert_eq!(public_inputs[16..20], DEFAULT_EPOCH_SCOPE);
+        assert_eq!(public_inputs[20], F::from_canonical_u64(BRIDGE_EPOCH));
+    }
+
+    #[test]
+    fn it_works_bridged_voucher_serialization() {
+        let mut csprng = OsRng {};
+        let origin_key_pair = Keypair::generate(&mut csprng);
+        let origin = origin_key_pair.public.to_bytes();
+        let origin_voucher =
+            make_origin_voucher(origin, origin_key_pair.sign(&origin).to_bytes()).unwrap();
+
+        let locus_key_pair = KeyPair::generate_key_pair();
+        let signature = origin_key_pair
+            .sign(&poseidon_locus_message(&locus_key_pair.public_key))
+            .to_bytes();
+        let bridge_voucher =
+            BridgeVoucher::new(&origin_voucher, locus_key_pair.public_key, signature).unwrap();
+        let adopted_voucher = dos::voucher::Voucher::from_proof(
+            bridge_voucher.circuit_data,
+            bridge_voucher.proof_data,
+        )
+        .unwrap();
+        let outer_locus = KeyPair::generate_key_pair().public_key;
+        let bytes = adopted_voucher
+            .extend_voucher(locus_key_pair.private_key, outer_locus)
+            .to_bytes();
+
+        // the circuits of a path from a dos origin voucher do not verify a bridged path
+        assert!(dos::voucher::Voucher::from_bytes(&bytes).is_err());
+        let decoded_voucher = bridged_voucher_from_bytes(&bytes).unwrap();
+        assert_eq!(decoded_voucher.origin(), ed25519_origin_digest(&origin));
+        assert_eq!(decoded_voucher.locus(), outer_locus);
+    }
+
+    #[test]
+    fn it_rejects_bridge_to_unsigned_locus() {
+        let mut csprng = OsRng {};
+        let origin_key_pair = Keypair::generate(&mut csprng);
+        let origin = origin_key_pair.public.to_bytes();
+        let origin_voucher =
+            make_origin_voucher(origin, origin_key_pair.sign(&origin).to_bytes()).unwrap();
+
+        // the origin signed another locus
+        let signed_locus: PoseidonPublicKey = [F::ONE; 4];
+        let signature = origin_key_pair
+            .sign(&poseidon_locus_message(&signed_locus))
+            .to_bytes();
+        assert!(BridgeVoucher::new(&origin_voucher, [F::TWO; 4], signature).is_err());
+    }
+}
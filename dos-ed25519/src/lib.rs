@@ -1,13 +1,13 @@
 //! Degrees of Separation (DOS)
 //!
-//! This is a simple implementation of the Degrees of Separation algorithm
-use ed25519_proofs::{add_eddsa_targets, Ed25519Targets, PublicKey, Signature};
-use hex_literal::hex;
+//! This is a simple implementation of the Degrees of Separation algorithm, with vouchers
+//! signed by ed25519 keys
+pub use ed25519_proofs::{PublicKey, Signature};
 use plonky2::{
     self,
     field::types::Field,
     iop::{
-        target::{BoolTarget, Target},
+        target::BoolTarget,
         witness::{PartialWitness, WitnessWrite},
     },
     plonk::{
@@ -19,19 +19,26 @@ use plonky2::{
 };
 use plonky2_sha512::circuit::array_to_bits;
 
-mod original_voucher;
-mod path_voucher;
+pub mod bridge;
+pub mod original_voucher;
+pub mod path_voucher;
 mod utils;
-mod voucher;
+pub mod voucher;
 
-pub(crate) const D: usize = 2;
-// first 4 bytes of keccak256("dos.voucher.locus");
-pub(crate) const LOCUS_DOMAIN_SEPARATOR: [u8; 4] = hex!("73afcf84");
-pub(crate) type C = PoseidonGoldilocksConfig;
-pub(crate) type F = <C as GenericConfig<D>>::F;
+pub const D: usize = 2;
+pub type C = PoseidonGoldilocksConfig;
+pub type F = <C as GenericConfig<D>>::F;
 pub(crate) const MESSAGE_LENGTH: usize = 32;
 pub(crate) const SIGNATURE_LENGTH: usize = 64;
 pub(crate) const PUBLIC_KEY_LENGTH: usize = 32;
 pub(crate) const MESSAGE_LENGTH_BITS: usize = MESSAGE_LENGTH * 8;
 pub(crate) const SIGNATURE_LENGTH_BITS: usize = SIGNATURE_LENGTH * 8;
-pub(crate) const PUBLIC_KEY_LENGTH_BITS: usize = PUBLIC_KEY_LENGTH * 8;
\ No newline at end of file
+pub(crate) const PUBLIC_KEY_LENGTH_BITS: usize = PUBLIC_KEY_LENGTH * 8;
+/// bits the circuits range check degrees in, so that they cannot wrap around the field
+pub(crate) const DEGREE_BITS: usize = 8;
+/// The longest path the path circuit extends a voucher to, the default maximum degree of
+/// `dos` origin vouchers. Unlike a `dos` origin, an ed25519 origin cannot choose its own:
+/// the origin voucher is the origin signing nothing but its own key, so there is no signed
+/// maximum degree for the path circuit to carry forward, and every ed25519 path shares this
+/// one.
+pub const MAX_DEGREE: u64 = dos::circuit_builder::DEFAULT_MAX_DEGREE;
@@ -1,15 +1,20 @@
-use plonky2::{iop::target::{Target, BoolTarget}, hash::hash_types::RichField, field::extension::Extendable};
+use plonky2::iop::target::{BoolTarget, Target};
+
+use anyhow::Result;
 
 use crate::{
-    add_eddsa_targets,
     utils::get_circuit_builder_and_partial_witness,
-    voucher::Voucher,
+    voucher::{
+        check_public_inputs, decode_public_inputs, extend_ed25519_voucher, voucher_to_bytes,
+        Ed25519Voucher, Voucher, VoucherPublicInputs,
+    },
     *,
 };
 
-use crate::path_voucher::PathVoucher;
-
-use ed25519_proofs::{Ed25519Targets, make_ed25519_verification_ciruit};
+use ed25519_proofs::{
+    add_virtual_bool_targets, make_ed25519_verification_ciruit, verify_ed25519_natively,
+    VerificationMode,
+};
 
 pub struct OriginVoucher {
     pub(crate) origin: PublicKey,
@@ -17,130 +22,150 @@ pub struct OriginVoucher {
     pub(crate) proof_data: ProofWithPublicInputs<F, C, D>,
 }
 
+/// Targets of the origin voucher circuit. The public inputs are the message bits and the
+/// origin bits (in that order, registered by the ed25519 gadget), followed by the degree.
+/// As the message is the origin, this is the origin, locus, degree layout of every voucher,
+/// and `message` holds the same targets as `origin`.
 pub struct OriginVoucherTargets {
     pub(crate) origin: Vec<BoolTarget>,
     pub(crate) signature: Vec<BoolTarget>,
     pub(crate) message: Vec<BoolTarget>,
     pub(crate) degree: Target,
-    pub(crate) eddsa: Ed25519Targets,
 }
 
-/// make a new origin voucher
-pub fn make_origin_voucher(origin: PublicKey, signature: Signature) -> OriginVoucher {
-    let (mut circuit_builder, mut partial_witness) = get_circuit_builder_and_partial_witness();
+/// Make a new origin voucher from the signature of the origin over itself. The signature
+/// is checked before anything gets proven.
+pub fn make_origin_voucher(origin: PublicKey, signature: Signature) -> Result<OriginVoucher> {
+    verify_ed25519_natively(&origin, &signature, &origin, VerificationMode::Strict)?;
 
-    // Steps:
-    //  1. have PI target for the degree to be zero (constant target == 0)
-    //  2. Verify signature of origin signing (message hash of) origin
-
-    let voucher_targets =
-        make_origin_voucher_circuit(&mut circuit_builder);
-    let zero_degree_target = circuit_builder.add_virtual_target();
-    let message: [u8; MESSAGE_LENGTH] = origin.clone();
-    add_eddsa_targets(
-        &mut circuit_builder,
-        &mut partial_witness,
-        message,
-        signature,
-        origin,
-    );
+    let (mut circuit_builder, mut partial_witness) = get_circuit_builder_and_partial_witness();
 
-    circuit_builder.register_public_input(zero_degree_target);
-    partial_witness.set_target(zero_degree_target, F::ZERO);
+    let voucher_targets = make_origin_voucher_circuit(&mut circuit_builder);
+    fill_origin_voucher_circuit(&mut partial_witness, &voucher_targets, origin, signature);
 
     let circuit_data = circuit_builder.build::<C>();
-    let proof_with_pis = circuit_data.prove(partial_witness).unwrap();
+    let proof_with_pis = circuit_data.prove(partial_witness)?;
 
-    OriginVoucher {
+    Ok(OriginVoucher {
         origin,
         circuit_data,
         proof_data: proof_with_pis,
-    }
+    })
 }
 
 impl Voucher for OriginVoucher {
-    fn degree(&self) -> F {
-        F::ZERO
+    fn origin(&self) -> Vec<u8> {
+        self.origin.to_vec()
     }
 
-    fn is_origin(&self) -> bool {
-        true
+    /// an origin vouches for itself
+    fn locus(&self) -> Vec<u8> {
+        self.origin.to_vec()
     }
 
-    fn verify(&self) -> bool {
-        // TODO: can we make it without cloning the proof ?
-        self.circuit_data.verify(self.proof_data.clone()).is_ok()
+    fn degree(&self) -> u64 {
+        0
     }
 
-    fn incremental_vouch(
-        existing_voucher: impl Voucher,
-        origin: PublicKey,
-        locus: PublicKey,
-        signature: Signature,
-        input_degree: F,
-    ) -> PathVoucher {
-        unimplemented!("Implement me");
+    fn public_inputs(&self) -> Result<VoucherPublicInputs> {
+        decode_public_inputs(&self.proof_data)
     }
 
-    fn proof_data(&self) -> &ProofWithPublicInputs<F, C, D>{
-        &self.proof_data
+    /// verify the proof, and that it attests what the voucher claims
+    fn verify(&self) -> bool {
+        check_public_inputs(self).is_ok()
+            && self.circuit_data.verify(self.proof_data.clone()).is_ok()
     }
 
-    fn origin(&self) -> PublicKey {
-        self.origin
+    fn extend(&self, locus_secret_key: &[u8], locus: &[u8]) -> Result<Box<dyn Voucher>> {
+        extend_ed25519_voucher(self, locus_secret_key, locus)
     }
-}
-
 
-/// make all the targets for the origin voucher
-fn make_origin_voucher_circuit<F: RichField + Extendable<D>, const D: usize>(
-    circuit_builder: &mut CircuitBuilder<F, D>,
-) -> OriginVoucherTargets {
-    // allocate a vector of binary (bool) targets 
-    // for each of the origin, signature and message
-    let origin_targets: Vec<BoolTarget> = Vec::with_capacity(PUBLIC_KEY_LENGTH_BITS);
-    let signature_targets: Vec<BoolTarget> = Vec::with_capacity(SIGNATURE_LENGTH_BITS);
-    let message_targets: Vec<BoolTarget> = Vec::with_capacity(MESSAGE_LENGTH_BITS);
-    
-    // add a single target for the degree
-    let degree_target: Target = circuit_builder.add_virtual_target();
+    fn to_bytes(&self) -> Vec<u8> {
+        voucher_to_bytes(self)
+    }
+}
 
-    // // create targets for the origin address
-    // for i in 0..PUBLIC_KEY_LENGTH_BITS {
-    //     origin_targets.push(circuit_builder.add_virtual_bool_target_safe());
-    // }
+impl Ed25519Voucher for OriginVoucher {
+    fn origin_key(&self) -> PublicKey {
+        self.origin
+    }
 
-    // create targets for the signature
-    for i in 0..SIGNATURE_LENGTH_BITS {
-        signature_targets.push(circuit_builder.add_virtual_bool_target_safe());
+    fn locus_key(&self) -> PublicKey {
+        self.origin
     }
 
-    // create targets for the message
-    for i in 0..MESSAGE_LENGTH_BITS {
-        message_targets.push(circuit_builder.add_virtual_bool_target_safe());
+    fn circuit_data(&self) -> &CircuitData<F, C, D> {
+        &self.circuit_data
     }
 
-    // create targets for the eddsa
-    let ed25519_targets: Ed25519Targets =
-        make_ed25519_verification_ciruit(circuit_builder, MESSAGE_LENGTH_BITS);
+    fn proof_data(&self) -> &ProofWithPublicInputs<F, C, D> {
+        &self.proof_data
+    }
+}
 
+/// Build the origin voucher circuit: the origin signs its own public key, and the degree
+/// is zero. The circuit does not depend on the witness, so one built circuit proves
+/// the voucher of any origin.
+pub fn make_origin_voucher_circuit(
+    circuit_builder: &mut CircuitBuilder<F, D>,
+) -> OriginVoucherTargets {
+    // the message that gets signed is the origin itself, so the same bits are passed as
+    // message and public key, and the gadget registers both as public inputs
+    let origin_targets = add_virtual_bool_targets(circuit_builder, PUBLIC_KEY_LENGTH_BITS);
+    let signature_targets = add_virtual_bool_targets(circuit_builder, SIGNATURE_LENGTH_BITS);
+    let eddsa = make_ed25519_verification_ciruit(
+        circuit_builder,
+        &origin_targets,
+        &signature_targets,
+        &origin_targets,
+        true,
+    );
 
+    // the degree must be zero
+    let degree_target: Target = circuit_builder.add_virtual_target();
+    let zero_target = circuit_builder.zero();
+    circuit_builder.connect(degree_target, zero_target);
+    circuit_builder.register_public_input(degree_target);
 
     OriginVoucherTargets {
-        origin,
-        signature,
-        message,
-        degree,
-        eddsa,
+        origin: eddsa.pk,
+        signature: eddsa.sig,
+        message: eddsa.msg,
+        degree: degree_target,
     }
 }
 
-fn make_ed25519
+/// Fill the witness of an origin voucher circuit for `origin` and its self-signature.
+pub fn fill_origin_voucher_circuit(
+    partial_witness: &mut PartialWitness<F>,
+    voucher_targets: &OriginVoucherTargets,
+    origin: PublicKey,
+    signature: Signature,
+) {
+    let origin_bits = array_to_bits(&origin);
+    let signature_bits = array_to_bits(&signature);
+
+    // the origin targets are also the message targets
+    for i in 0..PUBLIC_KEY_LENGTH_BITS {
+        partial_witness.set_bool_target(voucher_targets.origin[i], origin_bits[i]);
+    }
+
+    // fill signature targets with the signature bits
+    for i in 0..SIGNATURE_LENGTH_BITS {
+        partial_witness.set_bool_target(voucher_targets.signature[i], signature_bits[i]);
+    }
+
+    // the circuit already connects the degree to zero, this only makes the witness explicit
+    partial_witness.set_target(voucher_targets.degree, F::ZERO);
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::voucher::voucher_from_bytes;
     use ed25519_dalek::{Keypair, PublicKey, Signer, Verifier};
+    use ed25519_proofs::Ed25519Error;
     use hex_literal::hex;
     use rand::rngs::OsRng;
 
@@ -152,8 +177,59 @@ mod tests {
         let message = origin.clone();
         let signature = key_pair.sign(&message).to_bytes();
 
-        let origin_voucher: OriginVoucher = make_origin_voucher(origin, signature);
+        let origin_voucher: OriginVoucher = make_origin_voucher(origin, signature).unwrap();
         assert!(origin_voucher.verify());
+        assert!(origin_voucher.is_origin());
+        assert_eq!(
+            origin_voucher.public_inputs().unwrap(),
+            VoucherPublicInputs {
+                origin: origin.to_vec(),
+                locus: origin.to_vec(),
+                degree: 0,
+            }
+        );
+
+        // only the origin can extend its voucher
+        let stranger_key_pair = Keypair::generate(&mut csprng);
+        assert!(origin_voucher
+            .extend(
+                &stranger_key_pair.secret.to_bytes(),
+                &stranger_key_pair.public.to_bytes()
+            )
+            .is_err());
+
+        let decoded_voucher = voucher_from_bytes(&origin_voucher.to_bytes()).unwrap();
+        assert_eq!(decoded_voucher.origin(), origin.to_vec());
+        assert_eq!(decoded_voucher.degree(), 0);
+
+        // nor can anyone else sign it in as an origin
+        let forged_signature = stranger_key_pair.sign(&origin).to_bytes();
+        let error = make_origin_voucher(origin, forged_signature)
+            .err()
+            .expect("a signature by a stranger is refused before proving");
+        assert_eq!(
+            error.downcast_ref::<Ed25519Error>(),
+            Some(&Ed25519Error::InvalidSignature)
+        );
+    }
+
+    #[test]
+    fn it_works_one_origin_circuit_for_any_origin() {
+        let (mut circuit_builder, _) = get_circuit_builder_and_partial_witness();
+        let voucher_targets = make_origin_voucher_circuit(&mut circuit_builder);
+        let circuit_data = circuit_builder.build::<C>();
+
+        let mut csprng = OsRng {};
+        for _ in 0..2 {
+            let key_pair = Keypair::generate(&mut csprng);
+            let origin = key_pair.public.to_bytes();
+            let signature = key_pair.sign(&origin).to_bytes();
+
+            let mut partial_witness = PartialWitness::<F>::new();
+            fill_origin_voucher_circuit(&mut partial_witness, &voucher_targets, origin, signature);
+            let proof_with_pis = circuit_data.prove(partial_witness).unwrap();
+            assert!(circuit_data.verify(proof_with_pis).is_ok());
+        }
     }
 
     #[test]
@@ -1,211 +1,325 @@
+use anyhow::{anyhow, Result};
+use plonky2::field::types::PrimeField64;
+
 use crate::{
-    utils::get_circuit_builder_and_partial_witness, voucher::Voucher, PublicKey,
-    Signature, C, D, F, *,
+    utils::get_circuit_builder_and_partial_witness,
+    voucher::{
+        check_public_inputs, decode_public_inputs, extend_ed25519_voucher, voucher_to_bytes,
+        Ed25519Voucher, Voucher, VoucherPublicInputs, LOCUS_PUBLIC_INPUTS, ORIGIN_PUBLIC_INPUTS,
+    },
+    PublicKey, Signature, C, D, F, *,
 };
 
-use ed25519_proofs::Ed25519Targets;
-
-/// Adds targets to circuit to verify increment degree logic.
-/// Additionally, it sets partial witness values to targets
-pub fn increment_degree_targets(
-    builder: &mut CircuitBuilder<F, D>,
-    partial_witness: &mut PartialWitness<F>,
-    input_degree: F,
-    output_degree: F,
-) {
-    let input_degree_target = builder.add_virtual_target();
-    let output_degree_target = builder.add_virtual_target();
+use ed25519_proofs::{
+    add_virtual_bool_targets, make_ed25519_verification_ciruit, verify_ed25519_natively,
+    VerificationMode,
+};
+use plonky2::plonk::{circuit_data::VerifierCircuitTarget, proof::ProofWithPublicInputsTarget};
 
-    builder.register_public_input(output_degree_target);
+pub struct PathVoucherTargets {
+    pub(crate) inner_proof: ProofWithPublicInputsTarget<D>,
+    pub(crate) locus: Vec<BoolTarget>,
+    pub(crate) signer: Vec<BoolTarget>,
+    pub(crate) signature: Vec<BoolTarget>,
+}
 
-    let one_target = builder.one();
-    let increment_target = builder.add(input_degree_target, one_target);
+// todo: add expiry timestamp to carry forward
+/// [`PathVoucher`] represents a degree > 0 [`Voucher`] instance
+pub struct PathVoucher {
+    pub(crate) origin: PublicKey,
+    pub(crate) locus: PublicKey,
+    pub(crate) degree: F,
+    pub(crate) circuit_data: CircuitData<F, C, D>,
+    pub(crate) proof_data: ProofWithPublicInputs<F, C, D>,
+}
 
-    builder.connect(output_degree_target, increment_target);
+/// Build the circuit extending a voucher proven with `inner_circuit_data` by one hop.
+///
+/// The inner proof is verified recursively, and the origin and degree of the new voucher
+/// are read from its public inputs, so the prover cannot choose them. The new locus must be
+/// signed by the locus of the inner voucher. The public inputs are the origin bits, the
+/// locus bits (the signed message), the signer bits and the degree.
+pub fn make_path_voucher_circuit(
+    circuit_builder: &mut CircuitBuilder<F, D>,
+    inner_circuit_data: &CircuitData<F, C, D>,
+) -> PathVoucherTargets {
+    // verify the inner voucher; its verifier data is a constant, otherwise the prover
+    // could pass off a proof of any circuit as the inner voucher
+    let inner_proof = circuit_builder.add_virtual_proof_with_pis(&inner_circuit_data.common);
+    let inner_verifier_data = VerifierCircuitTarget {
+        constants_sigmas_cap: circuit_builder
+            .constant_merkle_cap(&inner_circuit_data.verifier_only.constants_sigmas_cap),
+        circuit_digest: circuit_builder
+            .constant_hash(inner_circuit_data.verifier_only.circuit_digest),
+    };
+    circuit_builder.verify_proof::<C>(
+        &inner_proof,
+        &inner_verifier_data,
+        &inner_circuit_data.common,
+    );
+
+    // the origin is carried over from the inner voucher
+    let inner_public_inputs = inner_proof.public_inputs.clone();
+    circuit_builder.register_public_inputs(&inner_public_inputs[ORIGIN_PUBLIC_INPUTS]);
+
+    // the gadget registers the message (the new locus) and public key (the signer)
+    // as public inputs
+    let locus_targets = add_virtual_bool_targets(circuit_builder, MESSAGE_LENGTH_BITS);
+    let signer_targets = add_virtual_bool_targets(circuit_builder, PUBLIC_KEY_LENGTH_BITS);
+    let signature_targets = add_virtual_bool_targets(circuit_builder, SIGNATURE_LENGTH_BITS);
+    let eddsa = make_ed25519_verification_ciruit(
+        circuit_builder,
+        &locus_targets,
+        &signature_targets,
+        &signer_targets,
+        true,
+    );
+
+    // only the holder of the previous hop can extend: the signer is the inner locus
+    for (signer_target, inner_locus_target) in eddsa
+        .pk
+        .iter()
+        .zip(&inner_public_inputs[LOCUS_PUBLIC_INPUTS])
+    {
+        circuit_builder.connect(signer_target.target, *inner_locus_target);
+    }
 
-    partial_witness.set_target(input_degree_target, input_degree);
-    partial_witness.set_target(output_degree_target, output_degree);
+    // the degree is one more than the degree of the inner voucher, and at most MAX_DEGREE;
+    // range checks keep both from wrapping around the field
+    let inner_degree_target = *inner_public_inputs
+        .last()
+        .expect("voucher circuits have public inputs");
+    let one_target = circuit_builder.one();
+    let degree_target = circuit_builder.add(inner_degree_target, one_target);
+    circuit_builder.range_check(degree_target, DEGREE_BITS);
+    let max_degree_target = circuit_builder.constant(F::from_canonical_u64(MAX_DEGREE));
+    let degree_headroom_target = circuit_builder.sub(max_degree_target, degree_target);
+    circuit_builder.range_check(degree_headroom_target, DEGREE_BITS);
+    circuit_builder.register_public_input(degree_target);
+
+    PathVoucherTargets {
+        inner_proof,
+        locus: eddsa.msg,
+        signer: eddsa.pk,
+        signature: eddsa.sig,
+    }
 }
 
-/// Adds targets to circuit to verify origin invariance across vouchers logic.
-/// Additionally, it sets partial witness values to targets
-pub fn origin_check_targets(
-    builder: &mut CircuitBuilder<F, D>,
+/// Fill the witness of a path voucher circuit: `inner_voucher` extended to `locus`,
+/// signed by the locus of the inner voucher.
+pub fn fill_path_voucher_circuit(
     partial_witness: &mut PartialWitness<F>,
-    previous_origin: PublicKey,
-    current_origin: PublicKey,
+    voucher_targets: &PathVoucherTargets,
+    inner_voucher: &impl Ed25519Voucher,
+    locus: PublicKey,
+    signature: Signature,
 ) {
-    // todo: check that the len of both previous and current origin pubkeys is == 32
-    // this should already be done in the ed25519 plonky2 lib (to be confirmed)
-    let origin_len_in_bits = previous_origin.len() * 8;
-    let previous_origin_bits: Vec<bool> = array_to_bits(&previous_origin);
-    let current_origin_bits: Vec<bool> = array_to_bits(&current_origin);
-
-    for i in 0..origin_len_in_bits {
-        let previous_origin_target = builder.add_virtual_bool_target_safe();
-        let current_origin_target = builder.add_virtual_bool_target_safe();
+    partial_witness
+        .set_proof_with_pis_target(&voucher_targets.inner_proof, inner_voucher.proof_data());
 
-        builder.connect(previous_origin_target.target, current_origin_target.target);
+    let locus_bits = array_to_bits(&locus);
+    let signer_bits = array_to_bits(&inner_voucher.locus_key());
+    let signature_bits = array_to_bits(&signature);
 
-        partial_witness.set_bool_target(previous_origin_target, previous_origin_bits[i]);
-        partial_witness.set_bool_target(current_origin_target, current_origin_bits[i]);
+    // fill locus (message) targets with the new locus bits
+    for i in 0..PUBLIC_KEY_LENGTH_BITS {
+        partial_witness.set_bool_target(voucher_targets.locus[i], locus_bits[i]);
     }
-}
 
-pub struct PathVoucherTargets {
-    pub(crate) inner_voucher_origin: Vec<BoolTarget>,
-    pub(crate) inner_origin_targets: Vec<BoolTarget>,
-    pub(crate) current_origin_targets: Vec<BoolTarget>,
-    pub(crate) inner_voucher_degree: Target,
-    pub(crate) current_degree: Target,
-    pub(crate) one_target: Target,
-    pub(crate) eddsa: Ed25519Targets,
-}
+    // fill signer targets with the inner locus bits
+    for i in 0..PUBLIC_KEY_LENGTH_BITS {
+        partial_witness.set_bool_target(voucher_targets.signer[i], signer_bits[i]);
+    }
 
-// todo: add expiry timestamp to carry forward
-/// [`PathVoucher`] represents a degree > 0 [`Voucher`] instance
-pub struct PathVoucher {
-    pub(crate) origin: PublicKey,
-    pub(crate) locus: PublicKey,
-    pub(crate) degree: F,
-    pub(crate) circuit_data: CircuitData<F, C, D>,
-    pub(crate) proof_data: ProofWithPublicInputs<F, C, D>,
+    // fill signature targets with the signature bits
+    for i in 0..SIGNATURE_LENGTH_BITS {
+        partial_witness.set_bool_target(voucher_targets.signature[i], signature_bits[i]);
+    }
 }
 
-impl Voucher for PathVoucher {
-    fn incremental_vouch(
-        inner_voucher: impl Voucher,
-        origin: PublicKey,
+impl PathVoucher {
+    /// Make a new voucher by having the locus of `inner_voucher` sign a new locus. The
+    /// signature and degree are checked before anything gets proven.
+    pub fn incremental_vouch(
+        inner_voucher: &impl Ed25519Voucher,
         locus: PublicKey,
         signature: Signature,
-        input_degree: F,
-    ) -> Self {
-        // verify inner voucher
-        if !inner_voucher.verify() {
-            panic!("Inner voucher proof is invalid!!")
+    ) -> Result<Self> {
+        if inner_voucher.degree() >= MAX_DEGREE {
+            return Err(anyhow!("paths are limited to degree {MAX_DEGREE}"));
         }
-        // TODO: distinguish between cases path and origin voucher cases
-        let (mut circuit_builder, mut partial_witness) = get_circuit_builder_and_partial_witness();
-
-        let inner_voucher_degree = inner_voucher.degree();
-        let degree = inner_voucher_degree + F::ONE;
-        let inner_voucher_origin = inner_voucher.origin();
-
-        increment_degree_targets(
-            &mut circuit_builder,
-            &mut partial_witness,
-            inner_voucher_degree,
-            degree,
-        );
+        verify_ed25519_natively(
+            &locus,
+            &signature,
+            &inner_voucher.locus_key(),
+            VerificationMode::Strict,
+        )?;
 
-        origin_check_targets(
-            &mut circuit_builder,
-            &mut partial_witness,
-            inner_voucher_origin,
-            origin,
-        );
+        let (mut circuit_builder, mut partial_witness) = get_circuit_builder_and_partial_witness();
 
-        add_eddsa_targets(
-            &mut circuit_builder,
+        let voucher_targets =
+            make_path_voucher_circuit(&mut circuit_builder, inner_voucher.circuit_data());
+        fill_path_voucher_circuit(
             &mut partial_witness,
-            origin.clone(),
-            signature,
+            &voucher_targets,
+            inner_voucher,
             locus,
+            signature,
         );
 
         let circuit_data = circuit_builder.build::<C>();
-        let proof_with_pis = circuit_data
-            .prove(partial_witness)
-            .unwrap_or_else(|e| panic!("Invalid proof for current voucher, with error {e} !"));
+        let proof_with_pis = circuit_data.prove(partial_witness)?;
 
-        Self {
-            origin,
-            degree,
+        Ok(Self {
+            origin: inner_voucher.origin_key(),
             locus,
+            degree: F::from_canonical_u64(inner_voucher.degree() + 1),
             circuit_data,
             proof_data: proof_with_pis,
-        }
+        })
+    }
+}
+
+impl Voucher for PathVoucher {
+    fn origin(&self) -> Vec<u8> {
+        self.origin.to_vec()
     }
 
-    fn degree(&self) -> F {
-        self.degree
+    fn locus(&self) -> Vec<u8> {
+        self.locus.to_vec()
     }
 
+    fn degree(&self) -> u64 {
+        self.degree.to_canonical_u64()
+    }
+
+    fn public_inputs(&self) -> Result<VoucherPublicInputs> {
+        decode_public_inputs(&self.proof_data)
+    }
+
+    /// verify the proof, and that it attests what the voucher claims
     fn verify(&self) -> bool {
-        // TODO: can we make it without cloning the proof ?
-        self.circuit_data.verify(self.proof_data.clone()).is_ok()
+        check_public_inputs(self).is_ok()
+            && self.circuit_data.verify(self.proof_data.clone()).is_ok()
     }
 
-    fn is_origin(&self) -> bool {
-        false
+    fn extend(&self, locus_secret_key: &[u8], locus: &[u8]) -> Result<Box<dyn Voucher>> {
+        extend_ed25519_voucher(self, locus_secret_key, locus)
     }
 
-    fn proof_data(&self) -> &ProofWithPublicInputs<F, C, D> {
-        &self.proof_data
+    fn to_bytes(&self) -> Vec<u8> {
+        voucher_to_bytes(self)
     }
+}
 
-    fn origin(&self) -> PublicKey {
+impl Ed25519Voucher for PathVoucher {
+    fn origin_key(&self) -> PublicKey {
         self.origin
     }
+
+    fn locus_key(&self) -> PublicKey {
+        self.locus
+    }
+
+    fn circuit_data(&self) -> &CircuitData<F, C, D> {
+        &self.circuit_data
+    }
+
+    fn proof_data(&self) -> &ProofWithPublicInputs<F, C, D> {
+        &self.proof_data
+    }
 }
 
-// impl PathVoucher {
-//     // pub fn origin_vouch(
-//     //     origin: PublicKey,
-//     //     signature: Signature,
-//     // )
-
-//     /// make a new voucher by signing a new locus from a previous voucher
-//     pub fn incremental_vouch(
-//         inner_voucher: impl GenericVoucher,
-//         origin: PublicKey,
-//         locus: PublicKey,
-//         signature: Signature,
-//         input_degree: F,
-//         // expiry: F,
-//     ) -> Result<Self, anyhow::Error> {
-//         let config = CircuitConfig::standard_recursion_zk_config();
-//         let mut circuit_builder = CircuitBuilder::<F, D>::new(config);
-
-//         let mut partial_witness = PartialWitness::<F>::new();
-//         let output_degree = input_degree + F::ONE;
-
-//         increment_degree_targets(
-//             &mut circuit_builder,
-//             &mut partial_witness,
-//             input_degree,
-//             output_degree,
-//         );
-
-//         // message
-//         // TODO: hash the locus with a domain separator to a message
-//         // let mut domain_separated_locus = LOCUS_DOMAIN_SEPARATOR.to_vec();
-//         // domain_separated_locus.extend(locus);
-//         let message = locus;
-
-//         // build circuit for signature verification
-//         add_eddsa_targets(
-//             &mut circuit_builder,
-//             &mut partial_witness,
-//             message,
-//             signature,
-//             origin,
-//         );
-
-//         // verify the previous voucher's proof data
-
-//         let circuit_data = circuit_builder.build::<C>();
-//         let proof_with_pis = circuit_data.prove(partial_witness)?;
-
-//         Ok(Self {
-//             origin,
-//             locus,
-//             degree: F::ONE,
-//             proof_data: proof_with_pis,
-//         })
-//     }
-
-//     pub fn verify(self) -> Result<(), anyhow::Error> {
-//         Ok(())
-//     }
-// }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::original_voucher::make_origin_voucher;
+    use crate::voucher::Ed25519PublicInputs;
+    use ed25519_dalek::{Keypair, Signer};
+    use rand::rngs::OsRng;
+
+    #[test]
+    fn it_works_path_voucher_proof_and_verify() {
+        let mut csprng = OsRng {};
+        let origin_key_pair = Keypair::generate(&mut csprng);
+        let origin = origin_key_pair.public.to_bytes();
+        let origin_voucher =
+            make_origin_voucher(origin, origin_key_pair.sign(&origin).to_bytes()).unwrap();
+
+        let locus = Keypair::generate(&mut csprng).public.to_bytes();
+        let signature = origin_key_pair.sign(&locus).to_bytes();
+        let path_voucher =
+            PathVoucher::incremental_vouch(&origin_voucher, locus, signature).unwrap();
+
+        assert!(path_voucher.verify());
+        assert_eq!(path_voucher.degree(), 1);
+
+        // a signature over another locus is refused before proving
+        let other_signature = origin_key_pair.sign(&origin).to_bytes();
+        assert!(PathVoucher::incremental_vouch(&origin_voucher, locus, other_signature).is_err());
+
+        // origin and degree come from the inner proof, not from the prover
+        let public_inputs = &path_voucher.proof_data().public_inputs;
+        assert_eq!(
+            public_inputs[ORIGIN_PUBLIC_INPUTS],
+            origin_voucher.proof_data().public_inputs[ORIGIN_PUBLIC_INPUTS]
+        );
+        let locus_bits: Vec<F> = array_to_bits(&locus)
+            .into_iter()
+            .map(F::from_bool)
+            .collect();
+        assert_eq!(public_inputs[LOCUS_PUBLIC_INPUTS], locus_bits[..]);
+        assert_eq!(*public_inputs.last().unwrap(), F::ONE);
+        assert_eq!(
+            Ed25519PublicInputs::decode(public_inputs).unwrap(),
+            Ed25519PublicInputs::Path {
+                origin,
+                locus,
+                signer: origin,
+                degree: 1,
+            }
+        );
+
+        // the voucher must not claim more than its proof attests
+        let mut relabeled_voucher = path_voucher;
+        relabeled_voucher.locus = origin;
+        assert!(!relabeled_voucher.verify());
+    }
+
+    #[test]
+    // the signer targets are copies of the inner locus, which the inner proof sets to the
+    // origin, so witness generation finds the partition set to two different values
+    #[should_panic(expected = "was set twice with different values")]
+    fn it_rejects_path_voucher_signed_by_stranger() {
+        let mut csprng = OsRng {};
+        let origin_key_pair = Keypair::generate(&mut csprng);
+        let origin = origin_key_pair.public.to_bytes();
+        let origin_voucher =
+            make_origin_voucher(origin, origin_key_pair.sign(&origin).to_bytes()).unwrap();
+
+        // a stranger signs the new locus with their own key and claims to be the signer
+        let stranger_key_pair = Keypair::generate(&mut csprng);
+        let locus = Keypair::generate(&mut csprng).public.to_bytes();
+        let signature = stranger_key_pair.sign(&locus).to_bytes();
+
+        let (mut circuit_builder, mut partial_witness) = get_circuit_builder_and_partial_witness();
+        let voucher_targets =
+            make_path_voucher_circuit(&mut circuit_builder, origin_voucher.circuit_data());
+        partial_witness
+            .set_proof_with_pis_target(&voucher_targets.inner_proof, origin_voucher.proof_data());
+        let locus_bits = array_to_bits(&locus);
+        let stranger_bits = array_to_bits(&stranger_key_pair.public.to_bytes());
+        let signature_bits = array_to_bits(&signature);
+        for i in 0..PUBLIC_KEY_LENGTH_BITS {
+            partial_witness.set_bool_target(voucher_targets.locus[i], locus_bits[i]);
+            partial_witness.set_bool_target(voucher_targets.signer[i], stranger_bits[i]);
+        }
+        for i in 0..SIGNATURE_LENGTH_BITS {
+            partial_witness.set_bool_target(voucher_targets.signature[i], signature_bits[i]);
+        }
+
+        // the stranger signed validly, but is not the origin, so no proof can be generated
+        let circuit_data = circuit_builder.build::<C>();
+        circuit_data.prove(partial_witness).unwrap();
+    }
+}
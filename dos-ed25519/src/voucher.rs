@@ -1,18 +1,242 @@
-use crate::{ProofWithPublicInputs, PublicKey, Signature, C, D, F};
+use anyhow::{anyhow, Result};
+use ed25519_dalek::{ExpandedSecretKey, SecretKey};
+use plonky2::field::types::{Field, PrimeField64};
 
-use crate::path_voucher::PathVoucher;
+pub use dos_core::{Voucher, VoucherPublicInputs};
 
-pub(crate) trait Voucher {
-    fn incremental_vouch(
-        existing_voucher: impl Voucher,
+use crate::{
+    original_voucher::{make_origin_voucher_circuit, OriginVoucher},
+    path_voucher::{make_path_voucher_circuit, PathVoucher},
+    utils::get_circuit_builder_and_partial_witness,
+    CircuitData, ProofWithPublicInputs, PublicKey, C, D, F, PUBLIC_KEY_LENGTH,
+};
+
+/// Public inputs shared by every voucher circuit: the origin bits, then the locus bits;
+/// the degree is always the last public input.
+pub(crate) const ORIGIN_PUBLIC_INPUTS: std::ops::Range<usize> = 0..crate::PUBLIC_KEY_LENGTH_BITS;
+pub(crate) const LOCUS_PUBLIC_INPUTS: std::ops::Range<usize> =
+    crate::PUBLIC_KEY_LENGTH_BITS..2 * crate::PUBLIC_KEY_LENGTH_BITS;
+
+/// A [`Voucher`] between ed25519 keys, whose proof can be verified recursively.
+pub trait Ed25519Voucher: Voucher {
+    fn origin_key(&self) -> PublicKey;
+    fn locus_key(&self) -> PublicKey;
+    fn circuit_data(&self) -> &CircuitData<F, C, D>;
+    fn proof_data(&self) -> &ProofWithPublicInputs<F, C, D>;
+}
+
+const ORIGIN_PUBLIC_INPUTS_LENGTH: usize = 2 * crate::PUBLIC_KEY_LENGTH_BITS + 1;
+const PATH_PUBLIC_INPUTS_LENGTH: usize = 3 * crate::PUBLIC_KEY_LENGTH_BITS + 1;
+
+/// The public inputs of an ed25519 voucher proof, decoded from the positions the circuits
+/// register them at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ed25519PublicInputs {
+    /// the origin signed itself, so it is both the message and the public key
+    Origin { origin: PublicKey, degree: u64 },
+    /// `signer`, the locus of the inner voucher, signed `locus`
+    Path {
         origin: PublicKey,
         locus: PublicKey,
-        signature: Signature,
-        input_degree: F,
-    ) -> PathVoucher;
-    fn degree(&self) -> F;
-    fn is_origin(&self) -> bool;
-    fn proof_data(&self) -> &ProofWithPublicInputs<F, C, D>;
-    fn verify(&self) -> bool;
-    fn origin(&self) -> PublicKey;
+        signer: PublicKey,
+        degree: u64,
+    },
+}
+
+impl Ed25519PublicInputs {
+    /// decode the public inputs of an origin or a path circuit
+    pub fn decode(public_inputs: &[F]) -> Result<Self> {
+        let key = |start: usize| -> Result<PublicKey> {
+            let bytes =
+                bits_to_bytes(&public_inputs[start..start + crate::PUBLIC_KEY_LENGTH_BITS])?;
+            Ok(bytes.try_into().expect("bits have public key length"))
+        };
+        let degree = |length: usize| public_inputs[length - 1].to_canonical_u64();
+
+        match public_inputs.len() {
+            ORIGIN_PUBLIC_INPUTS_LENGTH => {
+                let origin = key(ORIGIN_PUBLIC_INPUTS.start)?;
+                if key(LOCUS_PUBLIC_INPUTS.start)? != origin {
+                    return Err(anyhow!("origin proof signs another message"));
+                }
+                Ok(Ed25519PublicInputs::Origin {
+                    origin,
+                    degree: degree(ORIGIN_PUBLIC_INPUTS_LENGTH),
+                })
+            }
+            PATH_PUBLIC_INPUTS_LENGTH => Ok(Ed25519PublicInputs::Path {
+                origin: key(ORIGIN_PUBLIC_INPUTS.start)?,
+                locus: key(LOCUS_PUBLIC_INPUTS.start)?,
+                signer: key(LOCUS_PUBLIC_INPUTS.end)?,
+                degree: degree(PATH_PUBLIC_INPUTS_LENGTH),
+            }),
+            length => Err(anyhow!("unexpected number of public inputs {length}")),
+        }
+    }
+
+    pub fn origin(&self) -> PublicKey {
+        match self {
+            Ed25519PublicInputs::Origin { origin, .. }
+            | Ed25519PublicInputs::Path { origin, .. } => *origin,
+        }
+    }
+
+    pub fn locus(&self) -> PublicKey {
+        match self {
+            Ed25519PublicInputs::Origin { origin, .. } => *origin,
+            Ed25519PublicInputs::Path { locus, .. } => *locus,
+        }
+    }
+
+    pub fn degree(&self) -> u64 {
+        match self {
+            Ed25519PublicInputs::Origin { degree, .. }
+            | Ed25519PublicInputs::Path { degree, .. } => *degree,
+        }
+    }
+}
+
+/// decode the origin, locus and degree from the public inputs
+pub(crate) fn decode_public_inputs(
+    proof_data: &ProofWithPublicInputs<F, C, D>,
+) -> Result<VoucherPublicInputs> {
+    let public_inputs = Ed25519PublicInputs::decode(&proof_data.public_inputs)?;
+    Ok(VoucherPublicInputs {
+        origin: public_inputs.origin().to_vec(),
+        locus: public_inputs.locus().to_vec(),
+        degree: public_inputs.degree(),
+    })
+}
+
+/// Check that the proof of `voucher` attests the origin, locus and degree it claims, with
+/// the layout of its kind of circuit.
+pub fn check_public_inputs(voucher: &impl Ed25519Voucher) -> Result<()> {
+    let public_inputs = Ed25519PublicInputs::decode(&voucher.proof_data().public_inputs)?;
+    if voucher.is_origin() != matches!(public_inputs, Ed25519PublicInputs::Origin { .. }) {
+        return Err(anyhow!("proof is not of the claimed kind of voucher"));
+    }
+    if public_inputs.origin() != voucher.origin_key() {
+        return Err(anyhow!("proof attests another origin"));
+    }
+    if public_inputs.locus() != voucher.locus_key() {
+        return Err(anyhow!("proof attests another locus"));
+    }
+    if public_inputs.degree() != voucher.degree() {
+        return Err(anyhow!("proof attests another degree"));
+    }
+    Ok(())
+}
+
+// the inverse of array_to_bits, which puts the most significant bit of every byte first
+fn bits_to_bytes(bits: &[F]) -> Result<Vec<u8>> {
+    bits.chunks(8)
+        .map(|byte_bits| {
+            byte_bits
+                .iter()
+                .try_fold(0u8, |byte, bit| match bit.to_canonical_u64() {
+                    bit @ (0 | 1) => Ok((byte << 1) | bit as u8),
+                    _ => Err(anyhow!("public input is not a bit")),
+                })
+        })
+        .collect()
+}
+
+/// sign `locus` with the secret key of the locus of `voucher`, and extend it
+pub(crate) fn extend_ed25519_voucher(
+    voucher: &impl Ed25519Voucher,
+    locus_secret_key: &[u8],
+    locus: &[u8],
+) -> Result<Box<dyn Voucher>> {
+    let secret_key =
+        SecretKey::from_bytes(locus_secret_key).map_err(|e| anyhow!("invalid secret key: {e}"))?;
+    let public_key = ed25519_dalek::PublicKey::from(&secret_key);
+    if public_key.to_bytes() != voucher.locus_key() {
+        return Err(anyhow!("only the locus can extend a voucher"));
+    }
+    let locus: PublicKey = locus
+        .try_into()
+        .map_err(|_| anyhow!("locus has the wrong length"))?;
+
+    let signature = ExpandedSecretKey::from(&secret_key).sign(&locus, &public_key);
+    Ok(Box::new(PathVoucher::incremental_vouch(
+        voucher,
+        locus,
+        signature.to_bytes(),
+    )?))
+}
+
+/// serialize a voucher as origin, locus and degree (as a little-endian u64) followed by
+/// the proof bytes
+pub(crate) fn voucher_to_bytes(voucher: &impl Ed25519Voucher) -> Vec<u8> {
+    let mut bytes = voucher.origin_key().to_vec();
+    bytes.extend(voucher.locus_key());
+    bytes.extend(voucher.degree().to_le_bytes());
+    bytes.extend(voucher.proof_data().to_bytes());
+    bytes
+}
+
+/// Deserialize a voucher. The circuit data is rebuilt for the encoded degree, and the
+/// proof is verified against it before returning.
+pub fn voucher_from_bytes(bytes: &[u8]) -> Result<Box<dyn Voucher>> {
+    const HEADER_LENGTH: usize = 2 * PUBLIC_KEY_LENGTH + 8;
+    if bytes.len() < HEADER_LENGTH {
+        return Err(anyhow!("voucher encoding is too short"));
+    }
+
+    let (header, proof_bytes) = bytes.split_at(HEADER_LENGTH);
+    let origin: PublicKey = header[..PUBLIC_KEY_LENGTH].try_into()?;
+    let locus: PublicKey = header[PUBLIC_KEY_LENGTH..2 * PUBLIC_KEY_LENGTH].try_into()?;
+    let degree = u64::from_le_bytes(header[2 * PUBLIC_KEY_LENGTH..].try_into()?);
+    // refuse before rebuilding a circuit per degree
+    if degree > crate::MAX_DEGREE {
+        return Err(anyhow!("voucher degree is beyond {}", crate::MAX_DEGREE));
+    }
+
+    let circuit_data = voucher_circuit_data(degree);
+    let proof_data = ProofWithPublicInputs::from_bytes(proof_bytes.to_vec(), &circuit_data.common)?;
+
+    let voucher: Box<dyn Voucher> = if degree == 0 {
+        Box::new(OriginVoucher {
+            origin,
+            circuit_data,
+            proof_data,
+        })
+    } else {
+        Box::new(PathVoucher {
+            origin,
+            locus,
+            degree: F::from_canonical_u64(degree),
+            circuit_data,
+            proof_data,
+        })
+    };
+
+    let public_inputs = voucher.public_inputs()?;
+    if public_inputs.origin != origin
+        || public_inputs.locus != locus
+        || public_inputs.degree != degree
+    {
+        return Err(anyhow!("voucher header does not match the proof"));
+    }
+    if !voucher.verify() {
+        return Err(anyhow!("voucher proof does not verify"));
+    }
+
+    Ok(voucher)
+}
+
+/// rebuild the (witness independent) circuit data for a voucher of the given degree,
+/// by building the origin circuit and wrapping it in `degree` path circuits
+pub(crate) fn voucher_circuit_data(degree: u64) -> CircuitData<F, C, D> {
+    let (mut circuit_builder, _) = get_circuit_builder_and_partial_witness();
+    make_origin_voucher_circuit(&mut circuit_builder);
+    let mut circuit_data = circuit_builder.build::<C>();
+
+    for _ in 0..degree {
+        let (mut circuit_builder, _) = get_circuit_builder_and_partial_witness();
+        make_path_voucher_circuit(&mut circuit_builder, &circuit_data);
+        circuit_data = circuit_builder.build::<C>();
+    }
+
+    circuit_data
 }
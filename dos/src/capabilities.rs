@@ -0,0 +1,95 @@
+//! What the holder of a voucher is allowed to do.
+//!
+//! Capabilities are a bitmask carried as a public input of every voucher. An origin grants
+//! any set, and every extension can only keep or drop bits, like the caveats of a macaroon:
+//! an intermediary can delegate narrower rights than it holds, never wider ones.
+
+use anyhow::{anyhow, Result};
+use plonky2::field::types::{Field, PrimeField64};
+use simple_crypto::F;
+use std::fmt;
+use std::ops::{BitAnd, BitOr};
+
+/// number of capability bits the circuits range check
+pub const CAPABILITY_BITS: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Capabilities(u32);
+
+impl Capabilities {
+    pub const NONE: Capabilities = Capabilities(0);
+    pub const ALL: Capabilities = Capabilities(u32::MAX);
+
+    /// log in to edge machines
+    pub const SSH: Capabilities = Capabilities(1 << 0);
+    /// publish on gossip topics
+    pub const GOSSIP: Capabilities = Capabilities(1 << 1);
+
+    pub const fn from_bits(bits: u32) -> Self {
+        Capabilities(bits)
+    }
+
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    pub const fn contains(self, other: Capabilities) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn is_subset_of(self, other: Capabilities) -> bool {
+        other.contains(self)
+    }
+
+    pub fn to_field(self) -> F {
+        F::from_canonical_u32(self.0)
+    }
+
+    /// decode a public input, rejecting elements wider than [`CAPABILITY_BITS`]
+    pub fn from_field(element: F) -> Result<Self> {
+        let bits = u32::try_from(element.to_canonical_u64())
+            .map_err(|_| anyhow!("capabilities {element} do not fit in {CAPABILITY_BITS} bits"))?;
+        Ok(Capabilities(bits))
+    }
+}
+
+impl BitAnd for Capabilities {
+    type Output = Capabilities;
+
+    fn bitand(self, other: Capabilities) -> Capabilities {
+        Capabilities(self.0 & other.0)
+    }
+}
+
+impl BitOr for Capabilities {
+    type Output = Capabilities;
+
+    fn bitor(self, other: Capabilities) -> Capabilities {
+        Capabilities(self.0 | other.0)
+    }
+}
+
+impl fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#010x}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_works_capabilities() {
+        let held = Capabilities::SSH | Capabilities::GOSSIP;
+        assert!(held.contains(Capabilities::SSH));
+        assert!(Capabilities::GOSSIP.is_subset_of(held));
+        assert!(!held.is_subset_of(Capabilities::SSH));
+        assert!(Capabilities::NONE.is_subset_of(Capabilities::NONE));
+        assert_eq!(held & Capabilities::SSH, Capabilities::SSH);
+
+        assert_eq!(Capabilities::from_field(held.to_field()).unwrap(), held);
+        assert!(Capabilities::from_field(F::from_canonical_u64(1 << 32)).is_err());
+        assert_eq!(Capabilities::ALL.to_string(), "0xffffffff");
+    }
+}
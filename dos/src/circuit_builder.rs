@@ -1,8 +1,8 @@
 use plonky2::{
-    field::types::Field,
+    field::types::{Field, PrimeField64},
     hash::poseidon::PoseidonHash,
     iop::{
-        target::Target,
+        target::{BoolTarget, Target},
         witness::{PartialWitness, WitnessWrite},
     },
     plonk::{
@@ -13,11 +13,56 @@ use plonky2::{
 };
 use simple_crypto::{C, D, DIGEST_LENGTH, F, PRIVATE_KEY_LENGTH, PUBLIC_KEY_LENGTH};
 
+use crate::capabilities::CAPABILITY_BITS;
+
+/// where every voucher circuit registers its degree, capabilities, score, maximum degree,
+/// epoch scope and epoch, after origin, locus and signature
+pub(crate) const DEGREE_PUBLIC_INPUT_INDEX: usize = 2 * PUBLIC_KEY_LENGTH + DIGEST_LENGTH;
+pub(crate) const CAPABILITIES_PUBLIC_INPUT_INDEX: usize = DEGREE_PUBLIC_INPUT_INDEX + 1;
+pub(crate) const SCORE_PUBLIC_INPUT_INDEX: usize = CAPABILITIES_PUBLIC_INPUT_INDEX + 1;
+pub(crate) const MAX_DEGREE_PUBLIC_INPUT_INDEX: usize = SCORE_PUBLIC_INPUT_INDEX + 1;
+pub(crate) const EPOCH_SCOPE_PUBLIC_INPUT_INDEX: usize = MAX_DEGREE_PUBLIC_INPUT_INDEX + 1;
+pub(crate) const EPOCH_PUBLIC_INPUT_INDEX: usize = EPOCH_SCOPE_PUBLIC_INPUT_INDEX + DIGEST_LENGTH;
+
+/// private path circuits only register origin, locus commitment, degree, capabilities,
+/// score, maximum degree, epoch scope and epoch
+pub(crate) const PRIVATE_DEGREE_PUBLIC_INPUT_INDEX: usize = PUBLIC_KEY_LENGTH + DIGEST_LENGTH;
+pub(crate) const PRIVATE_CAPABILITIES_PUBLIC_INPUT_INDEX: usize =
+    PRIVATE_DEGREE_PUBLIC_INPUT_INDEX + 1;
+pub(crate) const PRIVATE_SCORE_PUBLIC_INPUT_INDEX: usize =
+    PRIVATE_CAPABILITIES_PUBLIC_INPUT_INDEX + 1;
+pub(crate) const PRIVATE_MAX_DEGREE_PUBLIC_INPUT_INDEX: usize =
+    PRIVATE_SCORE_PUBLIC_INPUT_INDEX + 1;
+pub(crate) const PRIVATE_EPOCH_SCOPE_PUBLIC_INPUT_INDEX: usize =
+    PRIVATE_MAX_DEGREE_PUBLIC_INPUT_INDEX + 1;
+pub(crate) const PRIVATE_EPOCH_PUBLIC_INPUT_INDEX: usize =
+    PRIVATE_EPOCH_SCOPE_PUBLIC_INPUT_INDEX + DIGEST_LENGTH;
+
+/// bits the circuits range check degrees in, so that they cannot wrap around the field
+pub const DEGREE_BITS: usize = 8;
+/// the highest maximum degree an origin can choose
+pub const MAX_DEGREE: u64 = (1 << DEGREE_BITS) - 1;
+/// the maximum degree of origin vouchers that do not choose one
+pub const DEFAULT_MAX_DEGREE: u64 = 6;
+
+/// the highest trust weight an extension can carry, and the score of an origin voucher
+pub const MAX_TRUST_WEIGHT: u64 = 100;
+/// bits the circuits range check weights, scores and their differences in
+const TRUST_WEIGHT_BITS: usize = 7;
+
+/// bits the circuits range check epochs, grace windows and the age of a voucher in
+pub const EPOCH_BITS: usize = 32;
+
 pub struct VoucherTargets {
     pub(crate) origin_targets: Vec<Target>,
     pub(crate) locus_targets: Vec<Target>,
     pub(crate) signature_targets: Vec<Target>,
     pub(crate) degree_target: Target,
+    pub(crate) capabilities_target: Target,
+    pub(crate) score_target: Target,
+    pub(crate) max_degree_target: Target,
+    pub(crate) epoch_scope_targets: Vec<Target>,
+    pub(crate) epoch_target: Target,
     pub(crate) private_key_targets: Vec<Target>,
 }
 
@@ -25,13 +70,82 @@ pub struct ExtendedVoucherTargets {
     pub(crate) inner_origin_targets: Vec<Target>,
     pub(crate) inner_locus_targets: Vec<Target>,
     pub(crate) inner_degree_target: Target,
+    pub(crate) inner_capabilities_target: Target,
+    pub(crate) inner_score_target: Target,
+    pub(crate) inner_max_degree_target: Target,
+    pub(crate) inner_epoch_scope_targets: Vec<Target>,
+    pub(crate) inner_epoch_target: Target,
     pub(crate) outer_origin_targets: Vec<Target>,
     pub(crate) outer_locus_targets: Vec<Target>,
     pub(crate) outer_signature_targets: Vec<Target>,
     pub(crate) outer_degree_target: Target,
+    pub(crate) outer_capabilities_target: Target,
+    pub(crate) outer_score_target: Target,
+    pub(crate) outer_max_degree_target: Target,
+    pub(crate) outer_epoch_scope_targets: Vec<Target>,
+    pub(crate) outer_epoch_target: Target,
+    pub(crate) weight_target: Target,
+    pub(crate) weight_is_lower_target: BoolTarget,
+    pub(crate) private_key_targets: Vec<Target>,
+    pub(crate) inner_proof_targets: ProofWithPublicInputsTarget<D>,
+}
+
+pub struct PrivateVoucherTargets {
+    pub(crate) origin_targets: Vec<Target>,
+    pub(crate) locus_commitment_targets: Vec<Target>,
+    pub(crate) degree_target: Target,
+    pub(crate) capabilities_target: Target,
+    pub(crate) score_target: Target,
+    pub(crate) max_degree_target: Target,
+    pub(crate) epoch_scope_targets: Vec<Target>,
+    pub(crate) epoch_target: Target,
+    pub(crate) weight_target: Target,
+    pub(crate) weight_is_lower_target: BoolTarget,
+    pub(crate) private_key_targets: Vec<Target>,
+    pub(crate) inner_blinding_targets: Vec<Target>,
+    pub(crate) outer_locus_targets: Vec<Target>,
+    pub(crate) outer_blinding_targets: Vec<Target>,
+    pub(crate) inner_proof_targets: ProofWithPublicInputsTarget<D>,
+}
+
+pub struct PresentationTargets {
+    pub(crate) origin_targets: Vec<Target>,
+    pub(crate) degree_target: Target,
+    pub(crate) capabilities_target: Target,
+    pub(crate) score_target: Target,
+    pub(crate) epoch_scope_targets: Vec<Target>,
+    pub(crate) origin_epoch_target: Target,
+    pub(crate) epoch_grace_target: Target,
+    pub(crate) verifier_id_targets: Vec<Target>,
+    pub(crate) challenge_targets: Vec<Target>,
+    pub(crate) pseudonym_targets: Vec<Target>,
+    pub(crate) private_key_targets: Vec<Target>,
+    pub(crate) inner_blinding_targets: Vec<Target>,
+    pub(crate) inner_proof_targets: ProofWithPublicInputsTarget<D>,
+}
+
+pub struct RateLimitedPresentationTargets {
+    pub(crate) origin_targets: Vec<Target>,
+    pub(crate) degree_target: Target,
+    pub(crate) capabilities_target: Target,
+    pub(crate) score_target: Target,
+    pub(crate) epoch_scope_targets: Vec<Target>,
+    pub(crate) origin_epoch_target: Target,
+    pub(crate) epoch_grace_target: Target,
+    pub(crate) scope_targets: Vec<Target>,
+    pub(crate) epoch_target: Target,
+    pub(crate) challenge_targets: Vec<Target>,
+    pub(crate) nullifier_targets: Vec<Target>,
+    pub(crate) share_targets: Option<Vec<Target>>,
     pub(crate) private_key_targets: Vec<Target>,
+    pub(crate) inner_blinding_targets: Vec<Target>,
     pub(crate) inner_proof_targets: ProofWithPublicInputsTarget<D>,
-    pub(crate) inner_verify_data_targets: VerifierCircuitTarget,
+}
+
+pub struct SignatureTargets {
+    pub(crate) public_key_targets: Vec<Target>,
+    pub(crate) message_targets: Vec<Target>,
+    pub(crate) private_key_targets: Vec<Target>,
 }
 
 pub fn make_origin_voucher_circuit(builder: &mut CircuitBuilder<F, D>) -> VoucherTargets {
@@ -43,11 +157,21 @@ pub fn make_origin_voucher_circuit(builder: &mut CircuitBuilder<F, D>) -> Vouche
     // but to be sure we add it (but can be removed later)
     let signature_targets = builder.add_virtual_targets(DIGEST_LENGTH);
     let degree_target = builder.add_virtual_target();
+    let capabilities_target = builder.add_virtual_target();
+    let score_target = builder.add_virtual_target();
+    let max_degree_target = builder.add_virtual_target();
+    let epoch_scope_targets = builder.add_virtual_targets(DIGEST_LENGTH);
+    let epoch_target = builder.add_virtual_target();
 
     builder.register_public_inputs(&origin_targets);
     builder.register_public_inputs(&locus_targets);
     builder.register_public_inputs(&signature_targets);
     builder.register_public_input(degree_target);
+    builder.register_public_input(capabilities_target);
+    builder.register_public_input(score_target);
+    builder.register_public_input(max_degree_target);
+    builder.register_public_inputs(&epoch_scope_targets);
+    builder.register_public_input(epoch_target);
 
     // allocate remaining internal targets
     let private_key_targets = builder.add_virtual_targets(PRIVATE_KEY_LENGTH);
@@ -65,8 +189,20 @@ pub fn make_origin_voucher_circuit(builder: &mut CircuitBuilder<F, D>) -> Vouche
         builder.connect(origin_targets[i], locus_targets[i]);
     }
 
-    // the degree must be zero
+    // the degree must be zero, and the origin chooses how long paths can get
     builder.connect(degree_target, zero_target);
+    connect_degree_bound(builder, degree_target, max_degree_target);
+
+    // the origin grants any capabilities, as long as they fit in the bitmask
+    builder.range_check(capabilities_target, CAPABILITY_BITS);
+
+    // the origin trusts itself fully
+    let max_trust_weight_target = builder.constant(F::from_canonical_u64(MAX_TRUST_WEIGHT));
+    builder.connect(score_target, max_trust_weight_target);
+
+    // the origin issues the voucher in an epoch of any scope, which presentations compare
+    // with the epoch the origin announces for that scope
+    builder.range_check(epoch_target, EPOCH_BITS);
 
     // the prover must know the private key, so it must hash to public key
     let should_be_public_key_origin_targets = builder.hash_n_to_hash_no_pad::<PoseidonHash>(
@@ -96,6 +232,11 @@ pub fn make_origin_voucher_circuit(builder: &mut CircuitBuilder<F, D>) -> Vouche
         locus_targets,
         signature_targets,
         degree_target,
+        capabilities_target,
+        score_target,
+        max_degree_target,
+        epoch_scope_targets,
+        epoch_target,
         private_key_targets,
     }
 }
@@ -109,43 +250,85 @@ pub fn make_extended_voucher_circuit(
     let outer_locus_targets = builder.add_virtual_targets(PUBLIC_KEY_LENGTH);
     let outer_signature_targets = builder.add_virtual_targets(DIGEST_LENGTH);
     let outer_degree_target = builder.add_virtual_target();
+    let outer_capabilities_target = builder.add_virtual_target();
+    let outer_score_target = builder.add_virtual_target();
+    let outer_max_degree_target = builder.add_virtual_target();
+    let outer_epoch_scope_targets = builder.add_virtual_targets(DIGEST_LENGTH);
+    let outer_epoch_target = builder.add_virtual_target();
 
     builder.register_public_inputs(&outer_origin_targets);
     builder.register_public_inputs(&outer_locus_targets);
     builder.register_public_inputs(&outer_signature_targets);
     builder.register_public_input(outer_degree_target);
+    builder.register_public_input(outer_capabilities_target);
+    builder.register_public_input(outer_score_target);
+    builder.register_public_input(outer_max_degree_target);
+    builder.register_public_inputs(&outer_epoch_scope_targets);
+    builder.register_public_input(outer_epoch_target);
 
     // allocate remaining internal targets
     let private_key_targets = builder.add_virtual_targets(PRIVATE_KEY_LENGTH);
     let topic_public_key_targets = builder.add_virtual_targets(DIGEST_LENGTH);
+    let weight_target = builder.add_virtual_target();
+    let weight_is_lower_target = builder.add_virtual_bool_target_safe();
 
     let inner_origin_targets = builder.add_virtual_targets(PUBLIC_KEY_LENGTH);
     let inner_locus_targets = builder.add_virtual_targets(PUBLIC_KEY_LENGTH);
     let inner_degree_target = builder.add_virtual_target();
+    let inner_capabilities_target = builder.add_virtual_target();
+    let inner_score_target = builder.add_virtual_target();
+    let inner_max_degree_target = builder.add_virtual_target();
+    let inner_epoch_scope_targets = builder.add_virtual_targets(DIGEST_LENGTH);
+    let inner_epoch_target = builder.add_virtual_target();
 
     builder.register_public_inputs(&inner_origin_targets);
     builder.register_public_inputs(&inner_locus_targets);
     builder.register_public_input(inner_degree_target);
+    builder.register_public_input(inner_capabilities_target);
+    builder.register_public_input(inner_score_target);
+    builder.register_public_input(inner_max_degree_target);
+    builder.register_public_inputs(&inner_epoch_scope_targets);
+    builder.register_public_input(inner_epoch_target);
 
     // inner and outer origin must be the same
     for i in 0..PUBLIC_KEY_LENGTH {
         builder.connect(outer_origin_targets[i], inner_origin_targets[i]);
     }
 
-    // outer locus and origin should be distinct
-    let mut bool_cumulative_target =
-        builder.is_equal(outer_locus_targets[0], outer_origin_targets[0]);
-    for i in 1..PUBLIC_KEY_LENGTH {
-        let equality_element_target =
-            builder.is_equal(outer_locus_targets[i], outer_origin_targets[i]);
-        bool_cumulative_target = builder.and(bool_cumulative_target, equality_element_target);
+    // the whole path stays in the epoch the origin issued it in
+    for i in 0..DIGEST_LENGTH {
+        builder.connect(outer_epoch_scope_targets[i], inner_epoch_scope_targets[i]);
     }
-    let _origin_and_locus_must_not_be_equal_target = builder.not(bool_cumulative_target);
+    builder.connect(outer_epoch_target, inner_epoch_target);
+
+    // outer locus and origin should be distinct
+    connect_locus_is_not_origin(builder, &outer_locus_targets, &outer_origin_targets);
 
-    // the outer degree must be one more than the inner degree
+    // the outer degree must be one more than the inner degree, and stay within the maximum
+    // degree the origin chose
     let one_target = builder.constant(F::ONE);
     let should_be_inner_degree_plus_one_target = builder.add(inner_degree_target, one_target);
     builder.connect(should_be_inner_degree_plus_one_target, outer_degree_target);
+    builder.connect(outer_max_degree_target, inner_max_degree_target);
+    connect_degree_bound(builder, outer_degree_target, outer_max_degree_target);
+
+    // the outer capabilities must be a subset of the inner ones: every outer bit is set
+    // in the inner bitmask as well (splitting also range checks both bitmasks)
+    let inner_capability_bits = builder.split_le(inner_capabilities_target, CAPABILITY_BITS);
+    let outer_capability_bits = builder.split_le(outer_capabilities_target, CAPABILITY_BITS);
+    for i in 0..CAPABILITY_BITS {
+        let held_bit = builder.and(outer_capability_bits[i], inner_capability_bits[i]);
+        builder.connect(held_bit.target, outer_capability_bits[i].target);
+    }
+
+    // the outer score is the lower of the inner score and the weight of this extension
+    let should_be_outer_score_target = connect_path_score(
+        builder,
+        inner_score_target,
+        weight_target,
+        weight_is_lower_target,
+    );
+    builder.connect(should_be_outer_score_target, outer_score_target);
 
     // prove that we know the private key
     // topic for public key is [F::ZERO; 4]
@@ -164,10 +347,15 @@ pub fn make_extended_voucher_circuit(
         );
     }
 
-    // the prover must sign the outer locus correctly, so they should hash the public key
-    // (origin) with the message (outer locus)
+    // the prover must sign the outer locus and the weight correctly, so they should hash
+    // the public key (inner locus) with the message (outer locus and weight)
     let should_be_signature_targets = builder.hash_n_to_hash_no_pad::<PoseidonHash>(
-        [inner_locus_targets.clone(), outer_locus_targets.clone()].concat(),
+        [
+            inner_locus_targets.clone(),
+            outer_locus_targets.clone(),
+            vec![weight_target],
+        ]
+        .concat(),
     );
     for i in 0..DIGEST_LENGTH {
         builder.connect(
@@ -176,27 +364,71 @@ pub fn make_extended_voucher_circuit(
         );
     }
 
-    let inner_proof_targets = builder.add_virtual_proof_with_pis(&inner_circuit_data.common);
-    let inner_verify_data_targets =
-        builder.add_virtual_verifier_data(inner_circuit_data.common.config.fri_config.cap_height);
+    // the inner verifier data is constant, so that the inner proof can only come from the
+    // circuit this one was built over, and one fixed circuit stands for each degree
+    let (inner_proof_targets, _) = verify_inner_voucher_proof(builder, inner_circuit_data, false);
 
-    builder.verify_proof::<C>(
-        &inner_proof_targets,
-        &inner_verify_data_targets,
-        &inner_circuit_data.common,
+    // the inner origin, locus, degree, capabilities, score, maximum degree and epoch are the
+    // ones the verified inner proof attests
+    for i in 0..PUBLIC_KEY_LENGTH {
+        builder.connect(
+            inner_origin_targets[i],
+            inner_proof_targets.public_inputs[i],
+        );
+        builder.connect(
+            inner_locus_targets[i],
+            inner_proof_targets.public_inputs[PUBLIC_KEY_LENGTH + i],
+        );
+    }
+    builder.connect(
+        inner_degree_target,
+        inner_proof_targets.public_inputs[DEGREE_PUBLIC_INPUT_INDEX],
+    );
+    builder.connect(
+        inner_capabilities_target,
+        inner_proof_targets.public_inputs[CAPABILITIES_PUBLIC_INPUT_INDEX],
+    );
+    builder.connect(
+        inner_score_target,
+        inner_proof_targets.public_inputs[SCORE_PUBLIC_INPUT_INDEX],
+    );
+    builder.connect(
+        inner_max_degree_target,
+        inner_proof_targets.public_inputs[MAX_DEGREE_PUBLIC_INPUT_INDEX],
+    );
+    for i in 0..DIGEST_LENGTH {
+        builder.connect(
+            inner_epoch_scope_targets[i],
+            inner_proof_targets.public_inputs[EPOCH_SCOPE_PUBLIC_INPUT_INDEX + i],
+        );
+    }
+    builder.connect(
+        inner_epoch_target,
+        inner_proof_targets.public_inputs[EPOCH_PUBLIC_INPUT_INDEX],
     );
 
     ExtendedVoucherTargets {
         inner_origin_targets,
         inner_locus_targets,
         inner_degree_target,
+        inner_capabilities_target,
+        inner_score_target,
+        inner_max_degree_target,
+        inner_epoch_scope_targets,
+        inner_epoch_target,
         outer_origin_targets,
         outer_locus_targets,
         outer_signature_targets,
         outer_degree_target,
+        outer_capabilities_target,
+        outer_score_target,
+        outer_max_degree_target,
+        outer_epoch_scope_targets,
+        outer_epoch_target,
+        weight_target,
+        weight_is_lower_target,
         private_key_targets,
         inner_proof_targets,
-        inner_verify_data_targets,
     }
 }
 
@@ -207,12 +439,21 @@ pub fn fill_origin_voucher_circuit(
     locus: [F; PUBLIC_KEY_LENGTH],
     private_key: [F; PRIVATE_KEY_LENGTH],
     signature: [F; DIGEST_LENGTH],
+    capabilities: F,
+    max_degree: F,
+    epoch_scope: [F; DIGEST_LENGTH],
+    epoch: F,
 ) {
     let VoucherTargets {
         origin_targets,
         locus_targets,
         signature_targets,
         degree_target,
+        capabilities_target,
+        score_target,
+        max_degree_target,
+        epoch_scope_targets,
+        epoch_target,
         private_key_targets,
     } = voucher_targets;
 
@@ -231,8 +472,15 @@ pub fn fill_origin_voucher_circuit(
         partial_witness.set_target(signature_targets[i], signature[i]);
     }
 
-    // todo: we already set degree to zero in the circuit, so this is redundant
-    // partial_witness.set_target(degree_target, F::ZERO);
+    // the circuit already connects the degree to zero, this only makes the witness explicit
+    partial_witness.set_target(degree_target, F::ZERO);
+    partial_witness.set_target(capabilities_target, capabilities);
+    partial_witness.set_target(score_target, F::from_canonical_u64(MAX_TRUST_WEIGHT));
+    partial_witness.set_target(max_degree_target, max_degree);
+    for i in 0..DIGEST_LENGTH {
+        partial_witness.set_target(epoch_scope_targets[i], epoch_scope[i]);
+    }
+    partial_witness.set_target(epoch_target, epoch);
 
     // fill private key targets with private key entries
     for i in 0..PRIVATE_KEY_LENGTH {
@@ -247,23 +495,40 @@ pub fn fill_extend_voucher_circuit(
 
     inner_locus: [F; PUBLIC_KEY_LENGTH],
     inner_degree: F,
+    inner_capabilities: F,
+    inner_score: F,
+    max_degree: F,
+    epoch_scope: [F; DIGEST_LENGTH],
+    epoch: F,
     outer_locus: [F; PUBLIC_KEY_LENGTH],
+    outer_capabilities: F,
+    weight: F,
     private_key: [F; PRIVATE_KEY_LENGTH],
     signature: [F; DIGEST_LENGTH],
-    inner_circuit_data: &CircuitData<F, C, D>,
     inner_proof_with_pis: &ProofWithPublicInputs<F, C, D>,
 ) {
     let ExtendedVoucherTargets {
         inner_origin_targets,
         inner_locus_targets,
         inner_degree_target,
+        inner_capabilities_target,
+        inner_score_target,
+        inner_max_degree_target,
+        inner_epoch_scope_targets,
+        inner_epoch_target,
         outer_origin_targets,
         outer_locus_targets,
         outer_signature_targets,
         outer_degree_target,
+        outer_capabilities_target,
+        outer_score_target,
+        outer_max_degree_target,
+        outer_epoch_scope_targets,
+        outer_epoch_target,
+        weight_target,
+        weight_is_lower_target,
         private_key_targets,
         inner_proof_targets,
-        inner_verify_data_targets,
     } = voucher_targets;
 
     // fill both origin targets with origin entries
@@ -280,6 +545,25 @@ pub fn fill_extend_voucher_circuit(
 
     partial_witness.set_target(inner_degree_target, inner_degree);
     partial_witness.set_target(outer_degree_target, inner_degree + F::ONE);
+    partial_witness.set_target(inner_capabilities_target, inner_capabilities);
+    partial_witness.set_target(outer_capabilities_target, outer_capabilities);
+    partial_witness.set_target(inner_score_target, inner_score);
+    let outer_score = fill_path_score(
+        partial_witness,
+        weight_target,
+        weight_is_lower_target,
+        inner_score,
+        weight,
+    );
+    partial_witness.set_target(outer_score_target, outer_score);
+    partial_witness.set_target(inner_max_degree_target, max_degree);
+    partial_witness.set_target(outer_max_degree_target, max_degree);
+    for i in 0..DIGEST_LENGTH {
+        partial_witness.set_target(inner_epoch_scope_targets[i], epoch_scope[i]);
+        partial_witness.set_target(outer_epoch_scope_targets[i], epoch_scope[i]);
+    }
+    partial_witness.set_target(inner_epoch_target, epoch);
+    partial_witness.set_target(outer_epoch_target, epoch);
 
     // fill outer signature targets with signature entries
     for i in 0..DIGEST_LENGTH {
@@ -291,10 +575,848 @@ pub fn fill_extend_voucher_circuit(
         partial_witness.set_target(private_key_targets[i], private_key[i]);
     }
 
-    // fill targets for verified circuit data
+    // fill targets for the verified inner proof, the circuit holds its verifier data
     partial_witness.set_proof_with_pis_target(&inner_proof_targets, inner_proof_with_pis);
-    partial_witness.set_verifier_data_target(
+}
+
+/// A path circuit that keeps the loci of the path in the witness. Only the origin, a
+/// commitment Poseidon(locus || blinding) to the outer locus, the degree, capabilities,
+/// score, maximum degree and epoch are public. The inner proof is either an origin (or path) voucher, whose locus is public,
+/// or another private path voucher, whose locus the prover opens with `inner_blinding`.
+/// The inner verifier data is constant, so the circuit pins the whole chain of circuits.
+pub fn make_private_extended_voucher_circuit(
+    builder: &mut CircuitBuilder<F, D>,
+    inner_circuit_data: &CircuitData<F, C, D>,
+    inner_is_private: bool,
+) -> PrivateVoucherTargets {
+    // allocate targets for the public inputs
+    let origin_targets = builder.add_virtual_targets(PUBLIC_KEY_LENGTH);
+    let locus_commitment_targets = builder.add_virtual_targets(DIGEST_LENGTH);
+    let degree_target = builder.add_virtual_target();
+    let capabilities_target = builder.add_virtual_target();
+    let score_target = builder.add_virtual_target();
+    let max_degree_target = builder.add_virtual_target();
+    let epoch_scope_targets = builder.add_virtual_targets(DIGEST_LENGTH);
+    let epoch_target = builder.add_virtual_target();
+
+    builder.register_public_inputs(&origin_targets);
+    builder.register_public_inputs(&locus_commitment_targets);
+    builder.register_public_input(degree_target);
+    builder.register_public_input(capabilities_target);
+    builder.register_public_input(score_target);
+    builder.register_public_input(max_degree_target);
+    builder.register_public_inputs(&epoch_scope_targets);
+    builder.register_public_input(epoch_target);
+
+    // allocate remaining internal targets
+    let private_key_targets = builder.add_virtual_targets(PRIVATE_KEY_LENGTH);
+    let topic_public_key_targets = builder.add_virtual_targets(DIGEST_LENGTH);
+    let inner_blinding_targets = builder.add_virtual_targets(DIGEST_LENGTH);
+    let outer_locus_targets = builder.add_virtual_targets(PUBLIC_KEY_LENGTH);
+    let outer_blinding_targets = builder.add_virtual_targets(DIGEST_LENGTH);
+    let weight_target = builder.add_virtual_target();
+    let weight_is_lower_target = builder.add_virtual_bool_target_safe();
+
+    let (inner_proof_targets, inner_indices) =
+        verify_inner_voucher_proof(builder, inner_circuit_data, inner_is_private);
+    let inner_public_inputs = inner_proof_targets.public_inputs.clone();
+
+    // inner and outer origin must be the same
+    for i in 0..PUBLIC_KEY_LENGTH {
+        builder.connect(origin_targets[i], inner_public_inputs[i]);
+    }
+
+    // the whole path stays in the epoch the origin issued it in
+    for i in 0..DIGEST_LENGTH {
+        builder.connect(
+            epoch_scope_targets[i],
+            inner_public_inputs[inner_indices.epoch_scope + i],
+        );
+    }
+    builder.connect(epoch_target, inner_public_inputs[inner_indices.epoch]);
+
+    // the outer degree must be one more than the inner degree, and stay within the maximum
+    // degree the origin chose
+    let one_target = builder.one();
+    let should_be_inner_degree_plus_one_target =
+        builder.add(inner_public_inputs[inner_indices.degree], one_target);
+    builder.connect(should_be_inner_degree_plus_one_target, degree_target);
+    builder.connect(
+        max_degree_target,
+        inner_public_inputs[inner_indices.max_degree],
+    );
+    connect_degree_bound(builder, degree_target, max_degree_target);
+
+    // the outer capabilities must be a subset of the inner ones
+    let inner_capability_bits = builder.split_le(
+        inner_public_inputs[inner_indices.capabilities],
+        CAPABILITY_BITS,
+    );
+    let outer_capability_bits = builder.split_le(capabilities_target, CAPABILITY_BITS);
+    for i in 0..CAPABILITY_BITS {
+        let held_bit = builder.and(outer_capability_bits[i], inner_capability_bits[i]);
+        builder.connect(held_bit.target, outer_capability_bits[i].target);
+    }
+
+    // the outer score is the lower of the inner score and the weight of this extension
+    let should_be_score_target = connect_path_score(
+        builder,
+        inner_public_inputs[inner_indices.score],
+        weight_target,
+        weight_is_lower_target,
+    );
+    builder.connect(should_be_score_target, score_target);
+
+    // prove that we know the private key of the inner locus
+    // topic for public key is [F::ZERO; 4]
+    let zero_target = builder.zero();
+    for i in 0..DIGEST_LENGTH {
+        builder.connect(topic_public_key_targets[i], zero_target);
+    }
+    connect_inner_locus(
+        builder,
+        &private_key_targets,
+        topic_public_key_targets,
+        &inner_blinding_targets,
+        &inner_public_inputs,
+        inner_is_private,
+    );
+
+    // the outer locus stays in the witness, but must still be distinct from the origin
+    connect_locus_is_not_origin(builder, &outer_locus_targets, &origin_targets);
+
+    // commit to the outer locus
+    let should_be_locus_commitment_targets = builder.hash_n_to_hash_no_pad::<PoseidonHash>(
+        [outer_locus_targets.clone(), outer_blinding_targets.clone()].concat(),
+    );
+    for i in 0..DIGEST_LENGTH {
+        builder.connect(
+            locus_commitment_targets[i],
+            should_be_locus_commitment_targets.elements[i],
+        );
+    }
+
+    PrivateVoucherTargets {
+        origin_targets,
+        locus_commitment_targets,
+        degree_target,
+        capabilities_target,
+        score_target,
+        max_degree_target,
+        epoch_scope_targets,
+        epoch_target,
+        weight_target,
+        weight_is_lower_target,
+        private_key_targets,
+        inner_blinding_targets,
+        outer_locus_targets,
+        outer_blinding_targets,
+        inner_proof_targets,
+    }
+}
+
+/// Where the proof of a voucher (or private voucher) has its degree, capabilities, score,
+/// maximum degree, epoch scope and epoch.
+struct InnerIndices {
+    degree: usize,
+    capabilities: usize,
+    score: usize,
+    max_degree: usize,
+    epoch_scope: usize,
+    epoch: usize,
+}
+
+/// Verify the proof of a voucher (or private voucher) against the verifier data of the
+/// expected inner circuit, returning it with the indices of its public inputs.
+fn verify_inner_voucher_proof(
+    builder: &mut CircuitBuilder<F, D>,
+    inner_circuit_data: &CircuitData<F, C, D>,
+    inner_is_private: bool,
+) -> (ProofWithPublicInputsTarget<D>, InnerIndices) {
+    let inner_proof_targets = builder.add_virtual_proof_with_pis(&inner_circuit_data.common);
+    let inner_verify_data_targets = VerifierCircuitTarget {
+        constants_sigmas_cap: builder
+            .constant_merkle_cap(&inner_circuit_data.verifier_only.constants_sigmas_cap),
+        circuit_digest: builder.constant_hash(inner_circuit_data.verifier_only.circuit_digest),
+    };
+    builder.verify_proof::<C>(
+        &inner_proof_targets,
         &inner_verify_data_targets,
-        &inner_circuit_data.verifier_only,
+        &inner_circuit_data.common,
+    );
+
+    let inner_indices = if inner_is_private {
+        InnerIndices {
+            degree: PRIVATE_DEGREE_PUBLIC_INPUT_INDEX,
+            capabilities: PRIVATE_CAPABILITIES_PUBLIC_INPUT_INDEX,
+            score: PRIVATE_SCORE_PUBLIC_INPUT_INDEX,
+            max_degree: PRIVATE_MAX_DEGREE_PUBLIC_INPUT_INDEX,
+            epoch_scope: PRIVATE_EPOCH_SCOPE_PUBLIC_INPUT_INDEX,
+            epoch: PRIVATE_EPOCH_PUBLIC_INPUT_INDEX,
+        }
+    } else {
+        InnerIndices {
+            degree: DEGREE_PUBLIC_INPUT_INDEX,
+            capabilities: CAPABILITIES_PUBLIC_INPUT_INDEX,
+            score: SCORE_PUBLIC_INPUT_INDEX,
+            max_degree: MAX_DEGREE_PUBLIC_INPUT_INDEX,
+            epoch_scope: EPOCH_SCOPE_PUBLIC_INPUT_INDEX,
+            epoch: EPOCH_PUBLIC_INPUT_INDEX,
+        }
+    };
+    (inner_proof_targets, inner_indices)
+}
+
+/// Constrain `locus_targets` to differ from `origin_targets` in at least one element, so
+/// that no path leads back to its origin.
+fn connect_locus_is_not_origin(
+    builder: &mut CircuitBuilder<F, D>,
+    locus_targets: &[Target],
+    origin_targets: &[Target],
+) {
+    let mut bool_cumulative_target = builder.is_equal(locus_targets[0], origin_targets[0]);
+    for i in 1..PUBLIC_KEY_LENGTH {
+        let equality_element_target = builder.is_equal(locus_targets[i], origin_targets[i]);
+        bool_cumulative_target = builder.and(bool_cumulative_target, equality_element_target);
+    }
+    let zero_target = builder.zero();
+    builder.connect(bool_cumulative_target.target, zero_target);
+}
+
+/// Range check `degree_target` and `max_degree_target` to [`DEGREE_BITS`] and constrain the
+/// degree to at most the maximum degree.
+fn connect_degree_bound(
+    builder: &mut CircuitBuilder<F, D>,
+    degree_target: Target,
+    max_degree_target: Target,
+) {
+    builder.range_check(degree_target, DEGREE_BITS);
+    builder.range_check(max_degree_target, DEGREE_BITS);
+    let degree_headroom_target = builder.sub(max_degree_target, degree_target);
+    builder.range_check(degree_headroom_target, DEGREE_BITS);
+}
+
+/// Range check the epoch a voucher was issued in, the current epoch of its origin and the
+/// grace window to [`EPOCH_BITS`], and constrain the voucher to be at most `grace` epochs
+/// older than the current epoch, and not newer.
+fn connect_epoch_window(
+    builder: &mut CircuitBuilder<F, D>,
+    voucher_epoch_target: Target,
+    origin_epoch_target: Target,
+    epoch_grace_target: Target,
+) {
+    builder.range_check(voucher_epoch_target, EPOCH_BITS);
+    builder.range_check(origin_epoch_target, EPOCH_BITS);
+    builder.range_check(epoch_grace_target, EPOCH_BITS);
+    let age_target = builder.sub(origin_epoch_target, voucher_epoch_target);
+    builder.range_check(age_target, EPOCH_BITS);
+    let grace_headroom_target = builder.sub(epoch_grace_target, age_target);
+    builder.range_check(grace_headroom_target, EPOCH_BITS);
+}
+
+/// Range check the weight of an extension to [1, MAX_TRUST_WEIGHT] and return the lower of
+/// it and the inner score, which is the outer score. Which one is lower is a witness hint:
+/// the higher minus the lower only fits in the range check if the hint is right.
+fn connect_path_score(
+    builder: &mut CircuitBuilder<F, D>,
+    inner_score_target: Target,
+    weight_target: Target,
+    weight_is_lower_target: BoolTarget,
+) -> Target {
+    let one_target = builder.one();
+    let max_trust_weight_target = builder.constant(F::from_canonical_u64(MAX_TRUST_WEIGHT));
+    let weight_minus_one_target = builder.sub(weight_target, one_target);
+    builder.range_check(weight_minus_one_target, TRUST_WEIGHT_BITS);
+    let weight_headroom_target = builder.sub(max_trust_weight_target, weight_target);
+    builder.range_check(weight_headroom_target, TRUST_WEIGHT_BITS);
+    // the inner score is bounded by the inner circuit, but checking it here keeps the
+    // comparison sound for any inner proof
+    builder.range_check(inner_score_target, TRUST_WEIGHT_BITS);
+
+    let lower_target = builder.select(weight_is_lower_target, weight_target, inner_score_target);
+    let higher_target = builder.select(weight_is_lower_target, inner_score_target, weight_target);
+    let difference_target = builder.sub(higher_target, lower_target);
+    builder.range_check(difference_target, TRUST_WEIGHT_BITS);
+
+    lower_target
+}
+
+/// fill the weight of an extension and the hint of [`connect_path_score`], returning the
+/// outer score
+fn fill_path_score(
+    partial_witness: &mut PartialWitness<F>,
+    weight_target: Target,
+    weight_is_lower_target: BoolTarget,
+    inner_score: F,
+    weight: F,
+) -> F {
+    let weight_is_lower = weight.to_canonical_u64() < inner_score.to_canonical_u64();
+    partial_witness.set_target(weight_target, weight);
+    partial_witness.set_bool_target(weight_is_lower_target, weight_is_lower);
+    if weight_is_lower {
+        weight
+    } else {
+        inner_score
+    }
+}
+
+/// Prove that the private key hashes (with the public key topic) to the inner locus, which
+/// is public in a voucher and committed to with `inner_blinding_targets` in a private voucher.
+fn connect_inner_locus(
+    builder: &mut CircuitBuilder<F, D>,
+    private_key_targets: &[Target],
+    topic_public_key_targets: Vec<Target>,
+    inner_blinding_targets: &[Target],
+    inner_public_inputs: &[Target],
+    inner_is_private: bool,
+) {
+    let inner_locus_targets = builder.hash_n_to_hash_no_pad::<PoseidonHash>(
+        [private_key_targets.to_vec(), topic_public_key_targets].concat(),
+    );
+    let should_be_inner_locus_targets = if inner_is_private {
+        builder
+            .hash_n_to_hash_no_pad::<PoseidonHash>(
+                [
+                    inner_locus_targets.elements.to_vec(),
+                    inner_blinding_targets.to_vec(),
+                ]
+                .concat(),
+            )
+            .elements
+    } else {
+        inner_locus_targets.elements
+    };
+    for i in 0..PUBLIC_KEY_LENGTH {
+        builder.connect(
+            should_be_inner_locus_targets[i],
+            inner_public_inputs[PUBLIC_KEY_LENGTH + i],
+        );
+    }
+}
+
+pub fn fill_private_extended_voucher_circuit(
+    partial_witness: &mut PartialWitness<F>,
+    voucher_targets: PrivateVoucherTargets,
+    origin: [F; PUBLIC_KEY_LENGTH],
+    locus_commitment: [F; DIGEST_LENGTH],
+    degree: F,
+    capabilities: F,
+    inner_score: F,
+    weight: F,
+    max_degree: F,
+    epoch_scope: [F; DIGEST_LENGTH],
+    epoch: F,
+    private_key: [F; PRIVATE_KEY_LENGTH],
+    inner_blinding: [F; DIGEST_LENGTH],
+    outer_locus: [F; PUBLIC_KEY_LENGTH],
+    outer_blinding: [F; DIGEST_LENGTH],
+    inner_proof_with_pis: &ProofWithPublicInputs<F, C, D>,
+) {
+    let PrivateVoucherTargets {
+        origin_targets,
+        locus_commitment_targets,
+        degree_target,
+        capabilities_target,
+        score_target,
+        max_degree_target,
+        epoch_scope_targets,
+        epoch_target,
+        weight_target,
+        weight_is_lower_target,
+        private_key_targets,
+        inner_blinding_targets,
+        outer_locus_targets,
+        outer_blinding_targets,
+        inner_proof_targets,
+    } = voucher_targets;
+
+    // fill the public inputs
+    for i in 0..PUBLIC_KEY_LENGTH {
+        partial_witness.set_target(origin_targets[i], origin[i]);
+    }
+    for i in 0..DIGEST_LENGTH {
+        partial_witness.set_target(locus_commitment_targets[i], locus_commitment[i]);
+    }
+    partial_witness.set_target(degree_target, degree);
+    partial_witness.set_target(capabilities_target, capabilities);
+    let score = fill_path_score(
+        partial_witness,
+        weight_target,
+        weight_is_lower_target,
+        inner_score,
+        weight,
     );
+    partial_witness.set_target(score_target, score);
+    partial_witness.set_target(max_degree_target, max_degree);
+    for i in 0..DIGEST_LENGTH {
+        partial_witness.set_target(epoch_scope_targets[i], epoch_scope[i]);
+    }
+    partial_witness.set_target(epoch_target, epoch);
+
+    // fill the private key, loci and blindings, which stay in the witness
+    for i in 0..PRIVATE_KEY_LENGTH {
+        partial_witness.set_target(private_key_targets[i], private_key[i]);
+    }
+    for i in 0..DIGEST_LENGTH {
+        partial_witness.set_target(inner_blinding_targets[i], inner_blinding[i]);
+        partial_witness.set_target(outer_blinding_targets[i], outer_blinding[i]);
+    }
+    for i in 0..PUBLIC_KEY_LENGTH {
+        partial_witness.set_target(outer_locus_targets[i], outer_locus[i]);
+    }
+
+    partial_witness.set_proof_with_pis_target(&inner_proof_targets, inner_proof_with_pis);
+}
+
+/// A presentation of a voucher (or private voucher) to one verifier. It proves knowledge of
+/// the private key of the locus, but publishes only the origin, degree, capabilities, score
+/// and epoch scope of the voucher, the current epoch of the origin and the grace window, the
+/// verifier id and challenge, and the pseudonym Poseidon(private key || verifier id) in
+/// place of the locus. The epoch the voucher was issued in stays in the witness, the circuit
+/// only shows it is within the grace window of the current epoch.
+pub fn make_presentation_circuit(
+    builder: &mut CircuitBuilder<F, D>,
+    inner_circuit_data: &CircuitData<F, C, D>,
+    inner_is_private: bool,
+) -> PresentationTargets {
+    // allocate targets for the public inputs
+    let origin_targets = builder.add_virtual_targets(PUBLIC_KEY_LENGTH);
+    let degree_target = builder.add_virtual_target();
+    let capabilities_target = builder.add_virtual_target();
+    let score_target = builder.add_virtual_target();
+    let epoch_scope_targets = builder.add_virtual_targets(DIGEST_LENGTH);
+    let origin_epoch_target = builder.add_virtual_target();
+    let epoch_grace_target = builder.add_virtual_target();
+    let verifier_id_targets = builder.add_virtual_targets(DIGEST_LENGTH);
+    // the challenge is only bound to the proof by being a public input
+    let challenge_targets = builder.add_virtual_targets(DIGEST_LENGTH);
+    let pseudonym_targets = builder.add_virtual_targets(DIGEST_LENGTH);
+
+    builder.register_public_inputs(&origin_targets);
+    builder.register_public_input(degree_target);
+    builder.register_public_input(capabilities_target);
+    builder.register_public_input(score_target);
+    builder.register_public_inputs(&epoch_scope_targets);
+    builder.register_public_input(origin_epoch_target);
+    builder.register_public_input(epoch_grace_target);
+    builder.register_public_inputs(&verifier_id_targets);
+    builder.register_public_inputs(&challenge_targets);
+    builder.register_public_inputs(&pseudonym_targets);
+
+    // allocate remaining internal targets
+    let private_key_targets = builder.add_virtual_targets(PRIVATE_KEY_LENGTH);
+    let topic_public_key_targets = builder.add_virtual_targets(DIGEST_LENGTH);
+    let inner_blinding_targets = builder.add_virtual_targets(DIGEST_LENGTH);
+
+    let (inner_proof_targets, inner_indices) =
+        verify_inner_voucher_proof(builder, inner_circuit_data, inner_is_private);
+    let inner_public_inputs = inner_proof_targets.public_inputs.clone();
+
+    // the origin, degree, capabilities and score are the ones of the voucher
+    for i in 0..PUBLIC_KEY_LENGTH {
+        builder.connect(origin_targets[i], inner_public_inputs[i]);
+    }
+    builder.connect(degree_target, inner_public_inputs[inner_indices.degree]);
+    builder.connect(
+        capabilities_target,
+        inner_public_inputs[inner_indices.capabilities],
+    );
+    builder.connect(score_target, inner_public_inputs[inner_indices.score]);
+
+    // the voucher was issued in the epoch scope, at most the grace window before the
+    // current epoch of the origin
+    for i in 0..DIGEST_LENGTH {
+        builder.connect(
+            epoch_scope_targets[i],
+            inner_public_inputs[inner_indices.epoch_scope + i],
+        );
+    }
+    connect_epoch_window(
+        builder,
+        inner_public_inputs[inner_indices.epoch],
+        origin_epoch_target,
+        epoch_grace_target,
+    );
+
+    // prove that we know the private key of the locus
+    // topic for public key is [F::ZERO; 4]
+    let zero_target = builder.zero();
+    for i in 0..DIGEST_LENGTH {
+        builder.connect(topic_public_key_targets[i], zero_target);
+    }
+    connect_inner_locus(
+        builder,
+        &private_key_targets,
+        topic_public_key_targets,
+        &inner_blinding_targets,
+        &inner_public_inputs,
+        inner_is_private,
+    );
+
+    // the pseudonym is derived like the public key, with the verifier id as topic
+    let should_be_pseudonym_targets = builder.hash_n_to_hash_no_pad::<PoseidonHash>(
+        [private_key_targets.clone(), verifier_id_targets.clone()].concat(),
+    );
+    for i in 0..DIGEST_LENGTH {
+        builder.connect(
+            pseudonym_targets[i],
+            should_be_pseudonym_targets.elements[i],
+        );
+    }
+
+    PresentationTargets {
+        origin_targets,
+        degree_target,
+        capabilities_target,
+        score_target,
+        epoch_scope_targets,
+        origin_epoch_target,
+        epoch_grace_target,
+        verifier_id_targets,
+        challenge_targets,
+        pseudonym_targets,
+        private_key_targets,
+        inner_blinding_targets,
+        inner_proof_targets,
+    }
+}
+
+pub fn fill_presentation_circuit(
+    partial_witness: &mut PartialWitness<F>,
+    presentation_targets: PresentationTargets,
+    origin: [F; PUBLIC_KEY_LENGTH],
+    degree: F,
+    capabilities: F,
+    score: F,
+    epoch_scope: [F; DIGEST_LENGTH],
+    origin_epoch: F,
+    epoch_grace: F,
+    verifier_id: [F; DIGEST_LENGTH],
+    challenge: [F; DIGEST_LENGTH],
+    pseudonym: [F; DIGEST_LENGTH],
+    private_key: [F; PRIVATE_KEY_LENGTH],
+    inner_blinding: [F; DIGEST_LENGTH],
+    inner_proof_with_pis: &ProofWithPublicInputs<F, C, D>,
+) {
+    let PresentationTargets {
+        origin_targets,
+        degree_target,
+        capabilities_target,
+        score_target,
+        epoch_scope_targets,
+        origin_epoch_target,
+        epoch_grace_target,
+        verifier_id_targets,
+        challenge_targets,
+        pseudonym_targets,
+        private_key_targets,
+        inner_blinding_targets,
+        inner_proof_targets,
+    } = presentation_targets;
+
+    // fill the public inputs
+    for i in 0..PUBLIC_KEY_LENGTH {
+        partial_witness.set_target(origin_targets[i], origin[i]);
+    }
+    partial_witness.set_target(degree_target, degree);
+    partial_witness.set_target(capabilities_target, capabilities);
+    partial_witness.set_target(score_target, score);
+    partial_witness.set_target(origin_epoch_target, origin_epoch);
+    partial_witness.set_target(epoch_grace_target, epoch_grace);
+    for i in 0..DIGEST_LENGTH {
+        partial_witness.set_target(epoch_scope_targets[i], epoch_scope[i]);
+        partial_witness.set_target(verifier_id_targets[i], verifier_id[i]);
+        partial_witness.set_target(challenge_targets[i], challenge[i]);
+        partial_witness.set_target(pseudonym_targets[i], pseudonym[i]);
+    }
+
+    // fill the private key and the blinding of the locus, which stay in the witness
+    for i in 0..PRIVATE_KEY_LENGTH {
+        partial_witness.set_target(private_key_targets[i], private_key[i]);
+    }
+    for i in 0..DIGEST_LENGTH {
+        partial_witness.set_target(inner_blinding_targets[i], inner_blinding[i]);
+    }
+
+    partial_witness.set_proof_with_pis_target(&inner_proof_targets, inner_proof_with_pis);
+}
+
+/// A rate limited presentation, in the style of rate limiting nullifiers. Instead of a
+/// pseudonym it publishes the nullifier Poseidon(private key || scope || epoch), which is the
+/// same for every presentation within an epoch and unlinkable across epochs. With
+/// `with_share`, it also publishes the share private key + slope * x of a line through the
+/// private key, where slope = Poseidon(private key || nullifier) and x is the first element
+/// of Poseidon(challenge): two presentations in one epoch reveal the private key. Like a
+/// presentation, it also shows that the voucher is within the grace window of the current
+/// epoch its origin announced, which is unrelated to the epoch of the nullifier.
+pub fn make_rate_limited_presentation_circuit(
+    builder: &mut CircuitBuilder<F, D>,
+    inner_circuit_data: &CircuitData<F, C, D>,
+    inner_is_private: bool,
+    with_share: bool,
+) -> RateLimitedPresentationTargets {
+    // allocate targets for the public inputs
+    let origin_targets = builder.add_virtual_targets(PUBLIC_KEY_LENGTH);
+    let degree_target = builder.add_virtual_target();
+    let capabilities_target = builder.add_virtual_target();
+    let score_target = builder.add_virtual_target();
+    let epoch_scope_targets = builder.add_virtual_targets(DIGEST_LENGTH);
+    let origin_epoch_target = builder.add_virtual_target();
+    let epoch_grace_target = builder.add_virtual_target();
+    let scope_targets = builder.add_virtual_targets(DIGEST_LENGTH);
+    let epoch_target = builder.add_virtual_target();
+    let challenge_targets = builder.add_virtual_targets(DIGEST_LENGTH);
+    let nullifier_targets = builder.add_virtual_targets(DIGEST_LENGTH);
+    let share_targets = with_share.then(|| builder.add_virtual_targets(PRIVATE_KEY_LENGTH));
+
+    builder.register_public_inputs(&origin_targets);
+    builder.register_public_input(degree_target);
+    builder.register_public_input(capabilities_target);
+    builder.register_public_input(score_target);
+    builder.register_public_inputs(&epoch_scope_targets);
+    builder.register_public_input(origin_epoch_target);
+    builder.register_public_input(epoch_grace_target);
+    builder.register_public_inputs(&scope_targets);
+    builder.register_public_input(epoch_target);
+    builder.register_public_inputs(&challenge_targets);
+    builder.register_public_inputs(&nullifier_targets);
+    if let Some(share_targets) = &share_targets {
+        builder.register_public_inputs(share_targets);
+    }
+
+    // allocate remaining internal targets
+    let private_key_targets = builder.add_virtual_targets(PRIVATE_KEY_LENGTH);
+    let topic_public_key_targets = builder.add_virtual_targets(DIGEST_LENGTH);
+    let inner_blinding_targets = builder.add_virtual_targets(DIGEST_LENGTH);
+
+    let (inner_proof_targets, inner_indices) =
+        verify_inner_voucher_proof(builder, inner_circuit_data, inner_is_private);
+    let inner_public_inputs = inner_proof_targets.public_inputs.clone();
+
+    // the origin, degree, capabilities and score are the ones of the voucher
+    for i in 0..PUBLIC_KEY_LENGTH {
+        builder.connect(origin_targets[i], inner_public_inputs[i]);
+    }
+    builder.connect(degree_target, inner_public_inputs[inner_indices.degree]);
+    builder.connect(
+        capabilities_target,
+        inner_public_inputs[inner_indices.capabilities],
+    );
+    builder.connect(score_target, inner_public_inputs[inner_indices.score]);
+
+    // the voucher was issued in the epoch scope, at most the grace window before the
+    // current epoch of the origin
+    for i in 0..DIGEST_LENGTH {
+        builder.connect(
+            epoch_scope_targets[i],
+            inner_public_inputs[inner_indices.epoch_scope + i],
+        );
+    }
+    connect_epoch_window(
+        builder,
+        inner_public_inputs[inner_indices.epoch],
+        origin_epoch_target,
+        epoch_grace_target,
+    );
+
+    // prove that we know the private key of the locus
+    // topic for public key is [F::ZERO; 4]
+    let zero_target = builder.zero();
+    for i in 0..DIGEST_LENGTH {
+        builder.connect(topic_public_key_targets[i], zero_target);
+    }
+    connect_inner_locus(
+        builder,
+        &private_key_targets,
+        topic_public_key_targets,
+        &inner_blinding_targets,
+        &inner_public_inputs,
+        inner_is_private,
+    );
+
+    // the nullifier binds the private key to the scope and epoch chosen by the verifier
+    let should_be_nullifier_targets = builder.hash_n_to_hash_no_pad::<PoseidonHash>(
+        [
+            private_key_targets.clone(),
+            scope_targets.clone(),
+            vec![epoch_target],
+        ]
+        .concat(),
+    );
+    for i in 0..DIGEST_LENGTH {
+        builder.connect(
+            nullifier_targets[i],
+            should_be_nullifier_targets.elements[i],
+        );
+    }
+
+    // the share is a point on a line through the private key, with a slope per epoch
+    if let Some(share_targets) = &share_targets {
+        let slope_targets = builder.hash_n_to_hash_no_pad::<PoseidonHash>(
+            [private_key_targets.clone(), nullifier_targets.clone()].concat(),
+        );
+        let x_target = builder
+            .hash_n_to_hash_no_pad::<PoseidonHash>(challenge_targets.clone())
+            .elements[0];
+        for i in 0..PRIVATE_KEY_LENGTH {
+            let should_be_share_target =
+                builder.mul_add(slope_targets.elements[i], x_target, private_key_targets[i]);
+            builder.connect(share_targets[i], should_be_share_target);
+        }
+    }
+
+    RateLimitedPresentationTargets {
+        origin_targets,
+        degree_target,
+        capabilities_target,
+        score_target,
+        epoch_scope_targets,
+        origin_epoch_target,
+        epoch_grace_target,
+        scope_targets,
+        epoch_target,
+        challenge_targets,
+        nullifier_targets,
+        share_targets,
+        private_key_targets,
+        inner_blinding_targets,
+        inner_proof_targets,
+    }
+}
+
+pub fn fill_rate_limited_presentation_circuit(
+    partial_witness: &mut PartialWitness<F>,
+    presentation_targets: RateLimitedPresentationTargets,
+    origin: [F; PUBLIC_KEY_LENGTH],
+    degree: F,
+    capabilities: F,
+    score: F,
+    epoch_scope: [F; DIGEST_LENGTH],
+    origin_epoch: F,
+    epoch_grace: F,
+    scope: [F; DIGEST_LENGTH],
+    epoch: F,
+    challenge: [F; DIGEST_LENGTH],
+    nullifier: [F; DIGEST_LENGTH],
+    share: Option<[F; PRIVATE_KEY_LENGTH]>,
+    private_key: [F; PRIVATE_KEY_LENGTH],
+    inner_blinding: [F; DIGEST_LENGTH],
+    inner_proof_with_pis: &ProofWithPublicInputs<F, C, D>,
+) {
+    let RateLimitedPresentationTargets {
+        origin_targets,
+        degree_target,
+        capabilities_target,
+        score_target,
+        epoch_scope_targets,
+        origin_epoch_target,
+        epoch_grace_target,
+        scope_targets,
+        epoch_target,
+        challenge_targets,
+        nullifier_targets,
+        share_targets,
+        private_key_targets,
+        inner_blinding_targets,
+        inner_proof_targets,
+    } = presentation_targets;
+
+    // fill the public inputs
+    for i in 0..PUBLIC_KEY_LENGTH {
+        partial_witness.set_target(origin_targets[i], origin[i]);
+    }
+    partial_witness.set_target(degree_target, degree);
+    partial_witness.set_target(capabilities_target, capabilities);
+    partial_witness.set_target(score_target, score);
+    partial_witness.set_target(origin_epoch_target, origin_epoch);
+    partial_witness.set_target(epoch_grace_target, epoch_grace);
+    partial_witness.set_target(epoch_target, epoch);
+    for i in 0..DIGEST_LENGTH {
+        partial_witness.set_target(epoch_scope_targets[i], epoch_scope[i]);
+        partial_witness.set_target(scope_targets[i], scope[i]);
+        partial_witness.set_target(challenge_targets[i], challenge[i]);
+        partial_witness.set_target(nullifier_targets[i], nullifier[i]);
+    }
+    if let (Some(share_targets), Some(share)) = (share_targets, share) {
+        for i in 0..PRIVATE_KEY_LENGTH {
+            partial_witness.set_target(share_targets[i], share[i]);
+        }
+    }
+
+    // fill the private key and the blinding of the locus, which stay in the witness
+    for i in 0..PRIVATE_KEY_LENGTH {
+        partial_witness.set_target(private_key_targets[i], private_key[i]);
+    }
+    for i in 0..DIGEST_LENGTH {
+        partial_witness.set_target(inner_blinding_targets[i], inner_blinding[i]);
+    }
+
+    partial_witness.set_proof_with_pis_target(&inner_proof_targets, inner_proof_with_pis);
+}
+
+/// A signature is a proof of knowledge of the private key behind a public key; the message
+/// (digest) is a public input, so the fiat-shamir challenges bind the proof to it.
+pub fn make_signature_circuit(builder: &mut CircuitBuilder<F, D>) -> SignatureTargets {
+    // allocate targets for the public inputs
+    let public_key_targets = builder.add_virtual_targets(PUBLIC_KEY_LENGTH);
+    let message_targets = builder.add_virtual_targets(DIGEST_LENGTH);
+
+    builder.register_public_inputs(&public_key_targets);
+    builder.register_public_inputs(&message_targets);
+
+    // allocate remaining internal targets
+    let private_key_targets = builder.add_virtual_targets(PRIVATE_KEY_LENGTH);
+    let topic_public_key_targets = builder.add_virtual_targets(DIGEST_LENGTH);
+
+    // topic for public key is [F::ZERO; 4]
+    let zero_target = builder.zero();
+    for i in 0..DIGEST_LENGTH {
+        builder.connect(topic_public_key_targets[i], zero_target);
+    }
+
+    // the prover must know the private key, so it must hash to public key
+    let should_be_public_key_targets = builder.hash_n_to_hash_no_pad::<PoseidonHash>(
+        [private_key_targets.clone(), topic_public_key_targets].concat(),
+    );
+    for i in 0..PUBLIC_KEY_LENGTH {
+        builder.connect(
+            public_key_targets[i],
+            should_be_public_key_targets.elements[i],
+        );
+    }
+
+    SignatureTargets {
+        public_key_targets,
+        message_targets,
+        private_key_targets,
+    }
+}
+
+pub fn fill_signature_circuit(
+    partial_witness: &mut PartialWitness<F>,
+    signature_targets: SignatureTargets,
+    public_key: [F; PUBLIC_KEY_LENGTH],
+    message: [F; DIGEST_LENGTH],
+    private_key: [F; PRIVATE_KEY_LENGTH],
+) {
+    let SignatureTargets {
+        public_key_targets,
+        message_targets,
+        private_key_targets,
+    } = signature_targets;
+
+    // fill public key targets with public key entries
+    for i in 0..PUBLIC_KEY_LENGTH {
+        partial_witness.set_target(public_key_targets[i], public_key[i]);
+    }
+
+    // fill message targets with message digest entries
+    for i in 0..DIGEST_LENGTH {
+        partial_witness.set_target(message_targets[i], message[i]);
+    }
+
+    // fill private key targets with private key entries
+    for i in 0..PRIVATE_KEY_LENGTH {
+        partial_witness.set_target(private_key_targets[i], private_key[i]);
+    }
 }
@@ -0,0 +1,238 @@
+//! Revocation of whole subtrees by epoch.
+//!
+//! Revoking an edge only reaches the vouchers that show it. To invalidate everything below
+//! a compromised locus, an origin keeps an epoch counter per scope and issues its vouchers
+//! in the current epoch of a scope, which every extension inherits. The origin publishes
+//! signed [`EpochAnnouncement`]s, and presentations prove that the voucher was issued at most
+//! a grace window before the announced epoch, without revealing the epoch it was issued in.
+//! Bumping the epoch of a scope invalidates every outstanding voucher of that scope once the
+//! grace window has passed, and the origin reissues vouchers to the loci it still trusts.
+
+use anyhow::{anyhow, Result};
+use plonky2::field::types::{Field, PrimeField64};
+use simple_crypto::{
+    field_elements_from_bytes, field_elements_to_bytes, Digest, PrivateKey, PublicKey,
+    DIGEST_LENGTH, F, PUBLIC_KEY_LENGTH,
+};
+use std::collections::HashMap;
+
+use crate::circuit_builder::EPOCH_BITS;
+use crate::signature::Signature;
+
+/// the highest epoch the circuits accept
+pub const MAX_EPOCH: u64 = (1 << EPOCH_BITS) - 1;
+
+/// The scope vouchers of origins that do not choose one are issued in, at epoch zero.
+pub const DEFAULT_EPOCH_SCOPE: Digest = [F::ZERO; DIGEST_LENGTH];
+
+/// What a presentation proves about the epoch of a voucher: it was issued in `scope`, at
+/// most `grace` epochs before `epoch`, the current epoch of the scope.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EpochWindow {
+    pub scope: Digest,
+    pub epoch: u64,
+    pub grace: u64,
+}
+
+impl EpochWindow {
+    /// whether a voucher issued in `epoch` of `scope` falls within the window
+    pub fn admits(&self, scope: Digest, epoch: u64) -> bool {
+        scope == self.scope && epoch <= self.epoch && self.epoch - epoch <= self.grace
+    }
+
+    /// check that the window fits in the range checks of the presentation circuits
+    pub(crate) fn check_range(&self) -> Result<()> {
+        if self.epoch > MAX_EPOCH || self.grace > MAX_EPOCH {
+            return Err(anyhow!("epoch window is beyond {MAX_EPOCH}"));
+        }
+        Ok(())
+    }
+}
+
+/// The current epoch of a scope, signed by the origin.
+pub struct EpochAnnouncement {
+    pub scope: Digest,
+    pub epoch: u64,
+    signature: Signature,
+}
+
+impl EpochAnnouncement {
+    /// sign `epoch` as the current epoch of `scope` with the private key of the origin
+    pub fn new(private_key: PrivateKey, scope: Digest, epoch: u64) -> Result<Self> {
+        if epoch > MAX_EPOCH {
+            return Err(anyhow!("epoch {epoch} is above {MAX_EPOCH}"));
+        }
+        Ok(EpochAnnouncement {
+            scope,
+            epoch,
+            signature: Signature::sign(private_key, &announcement_message(scope, epoch)),
+        })
+    }
+
+    /// the origin that announced the epoch
+    pub fn origin(&self) -> PublicKey {
+        self.signature.signer()
+    }
+
+    /// the window of presentations this announcement admits with `grace` epochs
+    pub fn window(&self, grace: u64) -> EpochWindow {
+        EpochWindow {
+            scope: self.scope,
+            epoch: self.epoch,
+            grace,
+        }
+    }
+
+    /// an announcement is valid if the origin signed the scope and epoch
+    pub fn verify(&self) -> bool {
+        self.epoch <= MAX_EPOCH
+            && self
+                .signature
+                .verify(&announcement_message(self.scope, self.epoch))
+    }
+
+    /// the scope and epoch (as little-endian u64s) followed by the signature
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = field_elements_to_bytes(&self.scope);
+        bytes.extend(self.epoch.to_le_bytes());
+        bytes.extend(self.signature.to_bytes());
+        bytes
+    }
+
+    /// decode an announcement; it still has to be verified
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        const HEADER_LENGTH: usize = (DIGEST_LENGTH + 1) * 8;
+        if bytes.len() < HEADER_LENGTH {
+            return Err(anyhow!("epoch announcement encoding is too short"));
+        }
+        let (header, signature) = bytes.split_at(HEADER_LENGTH);
+        let (scope, epoch) = header.split_at(DIGEST_LENGTH * 8);
+        Ok(EpochAnnouncement {
+            scope: field_elements_from_bytes(scope)?
+                .try_into()
+                .map_err(|_| anyhow!("epoch scope has the wrong length"))?,
+            epoch: u64::from_le_bytes(epoch.try_into()?),
+            signature: Signature::from_bytes(signature)?,
+        })
+    }
+}
+
+fn announcement_message(scope: Digest, epoch: u64) -> Vec<F> {
+    [&scope[..], &[F::from_canonical_u64(epoch)][..]].concat()
+}
+
+/// The current epoch of every scope of every origin a verifier follows.
+#[derive(Default)]
+pub struct EpochRegistry {
+    epochs: HashMap<([u64; PUBLIC_KEY_LENGTH], [u64; DIGEST_LENGTH]), u64>,
+}
+
+impl EpochRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a verified announcement, returning whether it moved the epoch of its scope.
+    /// Epochs only move forward, so a replayed older announcement cannot bring revoked
+    /// vouchers back.
+    pub fn record(&mut self, announcement: &EpochAnnouncement) -> Result<bool> {
+        if !announcement.verify() {
+            return Err(anyhow!("epoch announcement does not verify"));
+        }
+        let key = registry_key(announcement.origin(), announcement.scope);
+        match self.epochs.get(&key) {
+            Some(&epoch) if epoch > announcement.epoch => {
+                Err(anyhow!("epoch announcement is older than epoch {epoch}"))
+            }
+            Some(&epoch) if epoch == announcement.epoch => Ok(false),
+            _ => {
+                self.epochs.insert(key, announcement.epoch);
+                Ok(true)
+            }
+        }
+    }
+
+    /// the current epoch of `scope` of `origin`, if it announced one
+    pub fn current(&self, origin: PublicKey, scope: Digest) -> Option<u64> {
+        self.epochs.get(&registry_key(origin, scope)).copied()
+    }
+
+    /// Check the epoch window a presentation proved against the current epoch of the scope,
+    /// accepting windows up to `grace` epochs.
+    pub(crate) fn check_window(
+        &self,
+        origin: PublicKey,
+        scope: Digest,
+        origin_epoch: F,
+        epoch_grace: F,
+        grace: u64,
+    ) -> Result<()> {
+        let current = self
+            .current(origin, scope)
+            .ok_or_else(|| anyhow!("the origin announced no epoch for the scope"))?;
+        if origin_epoch.to_canonical_u64() != current {
+            return Err(anyhow!(
+                "presentation is meant for another epoch than {current}"
+            ));
+        }
+        if epoch_grace.to_canonical_u64() > grace {
+            return Err(anyhow!("presentation allows a grace window above {grace}"));
+        }
+        Ok(())
+    }
+}
+
+fn registry_key(
+    origin: PublicKey,
+    scope: Digest,
+) -> ([u64; PUBLIC_KEY_LENGTH], [u64; DIGEST_LENGTH]) {
+    (
+        origin.map(|element| element.to_canonical_u64()),
+        scope.map(|element| element.to_canonical_u64()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simple_crypto::KeyPair;
+
+    #[test]
+    fn it_works_epoch_registry() {
+        let origin = KeyPair::generate_key_pair();
+        let scope = [F::ONE; DIGEST_LENGTH];
+        let announcement = EpochAnnouncement::new(origin.private_key, scope, 3).unwrap();
+        assert!(EpochAnnouncement::new(origin.private_key, scope, MAX_EPOCH + 1).is_err());
+
+        let decoded = EpochAnnouncement::from_bytes(&announcement.to_bytes()).unwrap();
+        assert_eq!(decoded.origin(), origin.public_key);
+        assert!(decoded.verify());
+
+        // the announcement covers its scope and epoch only
+        let mut forged = EpochAnnouncement::from_bytes(&announcement.to_bytes()).unwrap();
+        forged.epoch = 2;
+        assert!(!forged.verify());
+
+        let mut registry = EpochRegistry::new();
+        assert!(registry.record(&decoded).unwrap());
+        assert!(!registry.record(&announcement).unwrap());
+        assert!(registry.record(&forged).is_err());
+        assert_eq!(registry.current(origin.public_key, scope), Some(3));
+        assert_eq!(
+            registry.current(origin.public_key, DEFAULT_EPOCH_SCOPE),
+            None
+        );
+
+        // bumping the epoch cannot be rolled back
+        let bumped = EpochAnnouncement::new(origin.private_key, scope, 4).unwrap();
+        assert!(registry.record(&bumped).unwrap());
+        assert!(registry.record(&announcement).is_err());
+
+        let window = bumped.window(1);
+        assert!(window.admits(scope, 4));
+        assert!(window.admits(scope, 3));
+        assert!(!window.admits(scope, 2));
+        assert!(!window.admits(scope, 5));
+        assert!(!window.admits(DEFAULT_EPOCH_SCOPE, 4));
+    }
+}
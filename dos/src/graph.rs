@@ -0,0 +1,354 @@
+//! A local view of the social graph, learned from the vouchers a node has seen.
+//!
+//! Every voucher is an edge from its issuer to its locus. To get a voucher of some origin,
+//! a locus needs a chain of peers that can extend to it, starting at someone who already
+//! holds a voucher of that origin. [`SocialGraph::plan`] finds the shortest such chain.
+
+use anyhow::{anyhow, Result};
+use plonky2::field::types::{Field, PrimeField64};
+use simple_crypto::{field_elements_from_bytes, field_elements_to_bytes, PublicKey, F};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::voucher::Voucher;
+
+const GRAPH_FILE: &str = "graph.bin";
+const KEY_BYTES: usize = 4 * 8;
+const EDGE_BYTES: usize = 3 * KEY_BYTES + 3 * 8;
+
+type NodeId = [u64; 4];
+
+/// the degree a node is reached at, the origin of that voucher and the node extending to it
+type Reached = (u64, NodeId, Option<NodeId>);
+
+/// `issuer` vouched for `locus`, giving it a voucher of `origin` at `degree`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VouchEdge {
+    pub origin: PublicKey,
+    pub issuer: PublicKey,
+    pub locus: PublicKey,
+    pub degree: u64,
+    /// unix timestamps in seconds of when the edge was first and last seen
+    pub first_seen: u64,
+    pub last_seen: u64,
+}
+
+impl VouchEdge {
+    pub fn from_voucher(voucher: &Voucher, now: u64) -> Self {
+        VouchEdge {
+            origin: voucher.origin(),
+            issuer: voucher.issuer(),
+            locus: voucher.locus(),
+            degree: voucher.degree().to_canonical_u64(),
+            first_seen: now,
+            last_seen: now,
+        }
+    }
+
+    fn key(&self) -> (NodeId, NodeId, NodeId) {
+        (
+            node_id(&self.issuer),
+            node_id(&self.locus),
+            node_id(&self.origin),
+        )
+    }
+}
+
+/// One extension: `issuer` extends its voucher to `locus`, which then holds one of `degree`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExtensionStep {
+    pub issuer: PublicKey,
+    pub locus: PublicKey,
+    pub degree: u64,
+}
+
+/// The extensions, in order, that get a voucher of `origin` to a locus.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtensionPlan {
+    pub origin: PublicKey,
+    /// the degree of the voucher the first issuer already holds
+    pub start_degree: u64,
+    /// empty if the locus already holds a voucher of `origin`
+    pub steps: Vec<ExtensionStep>,
+}
+
+impl ExtensionPlan {
+    /// the degree of the voucher the locus ends up with
+    pub fn degree(&self) -> u64 {
+        self.start_degree + self.steps.len() as u64
+    }
+}
+
+/// Known vouch edges, stored in a single file in a directory and rewritten on every change.
+pub struct SocialGraph {
+    path: PathBuf,
+    edges: BTreeMap<(NodeId, NodeId, NodeId), VouchEdge>,
+}
+
+impl SocialGraph {
+    pub fn open(path: &Path) -> Result<Self> {
+        fs::create_dir_all(path)?;
+        let mut graph = SocialGraph {
+            path: path.to_path_buf(),
+            edges: BTreeMap::new(),
+        };
+
+        let graph_file = graph.path.join(GRAPH_FILE);
+        if graph_file.exists() {
+            for edge in decode_edges(&fs::read(graph_file)?)? {
+                graph.add_edge(edge);
+            }
+        }
+        Ok(graph)
+    }
+
+    pub fn len(&self) -> usize {
+        self.edges.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.edges.is_empty()
+    }
+
+    pub fn edges(&self) -> impl Iterator<Item = &VouchEdge> {
+        self.edges.values()
+    }
+
+    /// record the edge a verified voucher proves
+    pub fn record_voucher(&mut self, voucher: &Voucher, now: u64) -> Result<()> {
+        if !voucher.verify() {
+            return Err(anyhow!("voucher proof does not verify"));
+        }
+        self.record(VouchEdge::from_voucher(voucher, now))
+    }
+
+    /// record an edge, merging it with an edge already known between the same keys
+    pub fn record(&mut self, edge: VouchEdge) -> Result<()> {
+        self.add_edge(edge);
+        self.save()
+    }
+
+    /// The shortest extension plan giving `locus` a voucher of one of `origins`. Every
+    /// origin and every locus known to hold a voucher of an origin can start the plan;
+    /// after that, extensions follow known edges of any origin.
+    pub fn plan(&self, origins: &[PublicKey], locus: PublicKey) -> Option<ExtensionPlan> {
+        let origin_ids: Vec<NodeId> = origins.iter().map(node_id).collect();
+
+        // the lowest degree voucher each node holds or can get, and where it comes from
+        let mut best: BTreeMap<NodeId, Reached> = BTreeMap::new();
+        let mut queue = BinaryHeap::new();
+        for &origin in &origin_ids {
+            offer(&mut best, &mut queue, origin, 0, origin, None);
+        }
+        for edge in self.edges.values() {
+            let origin = node_id(&edge.origin);
+            if origin_ids.contains(&origin) {
+                offer(
+                    &mut best,
+                    &mut queue,
+                    node_id(&edge.locus),
+                    edge.degree,
+                    origin,
+                    None,
+                );
+            }
+        }
+
+        let mut adjacency: BTreeMap<NodeId, Vec<NodeId>> = BTreeMap::new();
+        for (issuer, locus, _) in self.edges.keys() {
+            if issuer != locus {
+                adjacency.entry(*issuer).or_default().push(*locus);
+            }
+        }
+
+        let target = node_id(&locus);
+        while let Some(Reverse((degree, node))) = queue.pop() {
+            let (known, origin, _) = best[&node];
+            if degree > known {
+                continue;
+            }
+            if node == target {
+                break;
+            }
+            for &next in adjacency.get(&node).into_iter().flatten() {
+                offer(&mut best, &mut queue, next, degree + 1, origin, Some(node));
+            }
+        }
+
+        let &(_, origin, _) = best.get(&target)?;
+        let mut steps = Vec::new();
+        let mut node = target;
+        while let Some(&(degree, _, Some(previous))) = best.get(&node) {
+            steps.push(ExtensionStep {
+                issuer: public_key(previous),
+                locus: public_key(node),
+                degree,
+            });
+            node = previous;
+        }
+        steps.reverse();
+
+        Some(ExtensionPlan {
+            origin: public_key(origin),
+            start_degree: best[&node].0,
+            steps,
+        })
+    }
+
+    fn add_edge(&mut self, edge: VouchEdge) {
+        self.edges
+            .entry(edge.key())
+            .and_modify(|known| {
+                known.degree = known.degree.min(edge.degree);
+                known.first_seen = known.first_seen.min(edge.first_seen);
+                known.last_seen = known.last_seen.max(edge.last_seen);
+            })
+            .or_insert(edge);
+    }
+
+    fn save(&self) -> Result<()> {
+        let mut bytes = Vec::with_capacity(self.edges.len() * EDGE_BYTES);
+        for edge in self.edges.values() {
+            bytes.extend(field_elements_to_bytes(&edge.origin));
+            bytes.extend(field_elements_to_bytes(&edge.issuer));
+            bytes.extend(field_elements_to_bytes(&edge.locus));
+            bytes.extend(edge.degree.to_le_bytes());
+            bytes.extend(edge.first_seen.to_le_bytes());
+            bytes.extend(edge.last_seen.to_le_bytes());
+        }
+        let graph_file = self.path.join(GRAPH_FILE);
+        let temporary_file = graph_file.with_extension("tmp");
+        fs::write(&temporary_file, bytes)?;
+        fs::rename(temporary_file, graph_file)?;
+        Ok(())
+    }
+}
+
+/// record that `node` can get a voucher of `degree`, if that is the best known so far
+fn offer(
+    best: &mut BTreeMap<NodeId, Reached>,
+    queue: &mut BinaryHeap<Reverse<(u64, NodeId)>>,
+    node: NodeId,
+    degree: u64,
+    origin: NodeId,
+    previous: Option<NodeId>,
+) {
+    if best
+        .get(&node)
+        .map_or(true, |&(known, _, _)| degree < known)
+    {
+        best.insert(node, (degree, origin, previous));
+        queue.push(Reverse((degree, node)));
+    }
+}
+
+fn node_id(key: &PublicKey) -> NodeId {
+    key.map(|element| element.to_canonical_u64())
+}
+
+fn public_key(node: NodeId) -> PublicKey {
+    node.map(F::from_canonical_u64)
+}
+
+fn decode_edges(bytes: &[u8]) -> Result<Vec<VouchEdge>> {
+    if bytes.len() % EDGE_BYTES != 0 {
+        return Err(anyhow!("graph file is truncated"));
+    }
+    bytes
+        .chunks_exact(EDGE_BYTES)
+        .map(|chunk| {
+            let keys = field_elements_from_bytes(&chunk[..3 * KEY_BYTES])?;
+            let integer = |index: usize| {
+                let start = 3 * KEY_BYTES + index * 8;
+                u64::from_le_bytes(chunk[start..start + 8].try_into().expect("8 bytes"))
+            };
+            Ok(VouchEdge {
+                origin: keys[..4].try_into()?,
+                issuer: keys[4..8].try_into()?,
+                locus: keys[8..].try_into()?,
+                degree: integer(0),
+                first_seen: integer(1),
+                last_seen: integer(2),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simple_crypto::KeyPair;
+
+    fn key() -> PublicKey {
+        KeyPair::generate_key_pair().public_key
+    }
+
+    fn edge(origin: PublicKey, issuer: PublicKey, locus: PublicKey, degree: u64) -> VouchEdge {
+        VouchEdge {
+            origin,
+            issuer,
+            locus,
+            degree,
+            first_seen: 10,
+            last_seen: 10,
+        }
+    }
+
+    #[test]
+    fn it_works_graph_plan() {
+        let path = std::env::temp_dir().join(format!("dos-graph-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+
+        let (origin, other_origin) = (key(), key());
+        let (alice, bob, carol, dave) = (key(), key(), key(), key());
+        let mut graph = SocialGraph::open(&path).unwrap();
+        // origin -> alice -> bob, and other origin -> carol -> dave, with bob knowing carol
+        graph.record(edge(origin, origin, alice, 1)).unwrap();
+        graph.record(edge(origin, alice, bob, 2)).unwrap();
+        graph
+            .record(edge(other_origin, other_origin, carol, 1))
+            .unwrap();
+        graph.record(edge(other_origin, carol, dave, 2)).unwrap();
+        graph.record(edge(other_origin, carol, dave, 2)).unwrap();
+        graph.record(edge(origin, bob, carol, 3)).unwrap();
+        assert_eq!(graph.len(), 5);
+
+        // bob already holds a voucher of origin
+        let plan = graph.plan(&[origin], bob).unwrap();
+        assert!(plan.steps.is_empty());
+        assert_eq!(plan.degree(), 2);
+
+        // dave is reached through bob and carol
+        let plan = graph.plan(&[origin], dave).unwrap();
+        assert_eq!(plan.origin, origin);
+        assert_eq!(plan.start_degree, 2);
+        assert_eq!(
+            plan.steps,
+            vec![
+                ExtensionStep {
+                    issuer: bob,
+                    locus: carol,
+                    degree: 3
+                },
+                ExtensionStep {
+                    issuer: carol,
+                    locus: dave,
+                    degree: 4
+                },
+            ]
+        );
+
+        // the other origin is closer to dave, and nobody leads to alice
+        let plan = graph.plan(&[origin, other_origin], dave).unwrap();
+        assert_eq!(plan.origin, other_origin);
+        assert_eq!(plan.degree(), 2);
+        assert!(graph.plan(&[other_origin], alice).is_none());
+
+        let reopened = SocialGraph::open(&path).unwrap();
+        assert_eq!(reopened.edges().count(), 5);
+        assert_eq!(reopened.plan(&[origin], dave), graph.plan(&[origin], dave));
+        fs::remove_dir_all(path).unwrap();
+    }
+}
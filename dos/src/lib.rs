@@ -1,2 +1,13 @@
+// circuits are filled element by element, mirroring how the targets are allocated
+#![allow(clippy::needless_range_loop, clippy::too_many_arguments)]
+
+pub mod capabilities;
 pub mod voucher;
-pub mod circuit_builder;
\ No newline at end of file
+pub mod circuit_builder;
+pub mod epoch;
+pub mod graph;
+pub mod presentation;
+pub mod private_voucher;
+pub mod rate_limit;
+pub mod signature;
+pub mod wallet;
\ No newline at end of file
@@ -0,0 +1,535 @@
+//! Unlinkable presentations of vouchers.
+//!
+//! Showing a voucher shows its locus, so two verifiers can tell they were visited by the same
+//! holder. A [`Presentation`] proves that the holder knows the private key of the locus of a
+//! valid voucher, but publishes the pseudonym Poseidon(private key || verifier id) instead:
+//! stable for one verifier, unlinkable across verifiers. The verifier also picks a challenge,
+//! so a presentation cannot be replayed, and checks against its [`EpochRegistry`] that the
+//! presentation proves the voucher current in the epoch scope it was issued in.
+
+use anyhow::{anyhow, Result};
+use plonky2::{
+    field::types::{Field, PrimeField64},
+    iop::witness::PartialWitness,
+    plonk::{
+        circuit_builder::CircuitBuilder,
+        circuit_data::{CircuitConfig, CircuitData},
+        proof::ProofWithPublicInputs,
+    },
+};
+use simple_crypto::{
+    derive_pseudonym, field_elements_from_bytes, field_elements_to_bytes, Digest, KeyPair,
+    PrivateKey, PublicKey, C, D, DIGEST_LENGTH, F, PUBLIC_KEY_LENGTH, PUBLIC_KEY_POSTFIX,
+};
+
+use crate::capabilities::Capabilities;
+use crate::circuit_builder::{fill_presentation_circuit, make_presentation_circuit, MAX_DEGREE};
+use crate::epoch::{EpochRegistry, EpochWindow};
+use crate::private_voucher::{private_voucher_circuit_data, PrivateVoucher};
+use crate::voucher::{voucher_circuit_data, Voucher};
+
+// public inputs: origin, degree, capabilities, score, epoch scope, origin epoch, epoch grace,
+// verifier id, challenge, pseudonym
+const DEGREE_INDEX: usize = PUBLIC_KEY_LENGTH;
+const EPOCH_SCOPE_INDEX: usize = DEGREE_INDEX + 3;
+const ORIGIN_EPOCH_INDEX: usize = EPOCH_SCOPE_INDEX + DIGEST_LENGTH;
+const VERIFIER_ID_INDEX: usize = ORIGIN_EPOCH_INDEX + 2;
+const CHALLENGE_INDEX: usize = VERIFIER_ID_INDEX + DIGEST_LENGTH;
+const PSEUDONYM_INDEX: usize = CHALLENGE_INDEX + DIGEST_LENGTH;
+const PRESENTATION_PUBLIC_INPUTS_LENGTH: usize = PSEUDONYM_INDEX + DIGEST_LENGTH;
+
+/// The public inputs of a presentation proof.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PresentationPublicInputs {
+    pub origin: PublicKey,
+    pub degree: F,
+    pub capabilities: Capabilities,
+    pub score: F,
+    pub epoch_scope: Digest,
+    /// the current epoch of the scope, which the voucher is at most `epoch_grace` behind
+    pub origin_epoch: F,
+    pub epoch_grace: F,
+    pub verifier_id: Digest,
+    pub challenge: Digest,
+    pub pseudonym: Digest,
+}
+
+impl PresentationPublicInputs {
+    pub fn decode(public_inputs: &[F]) -> Result<Self> {
+        if public_inputs.len() != PRESENTATION_PUBLIC_INPUTS_LENGTH {
+            return Err(anyhow!(
+                "unexpected number of public inputs {}",
+                public_inputs.len()
+            ));
+        }
+        Ok(PresentationPublicInputs {
+            origin: public_inputs[..PUBLIC_KEY_LENGTH].try_into()?,
+            degree: public_inputs[DEGREE_INDEX],
+            capabilities: Capabilities::from_field(public_inputs[DEGREE_INDEX + 1])?,
+            score: public_inputs[DEGREE_INDEX + 2],
+            epoch_scope: public_inputs[EPOCH_SCOPE_INDEX..ORIGIN_EPOCH_INDEX].try_into()?,
+            origin_epoch: public_inputs[ORIGIN_EPOCH_INDEX],
+            epoch_grace: public_inputs[ORIGIN_EPOCH_INDEX + 1],
+            verifier_id: public_inputs[VERIFIER_ID_INDEX..CHALLENGE_INDEX].try_into()?,
+            challenge: public_inputs[CHALLENGE_INDEX..PSEUDONYM_INDEX].try_into()?,
+            pseudonym: public_inputs[PSEUDONYM_INDEX..].try_into()?,
+        })
+    }
+}
+
+pub struct Presentation {
+    /// whether the presented voucher is a private voucher, which selects the inner circuit
+    pub(crate) private_path: bool,
+    pub(crate) public_inputs: PresentationPublicInputs,
+    pub(crate) circuit_data: CircuitData<F, C, D>,
+    pub(crate) proof_data: ProofWithPublicInputs<F, C, D>,
+}
+
+impl Presentation {
+    /// present `voucher` to the verifier `verifier_id`, with the private key of its locus,
+    /// proving it within the epoch `window` of the verifier
+    pub fn present(
+        voucher: &Voucher,
+        private_key: PrivateKey,
+        verifier_id: Digest,
+        challenge: Digest,
+        window: EpochWindow,
+    ) -> Result<Self> {
+        if KeyPair::from_private_key(private_key).public_key != voucher.locus() {
+            return Err(anyhow!("only the locus can present a voucher"));
+        }
+        let (inner_circuit_data, inner_proof_data) = voucher.voucher_proof_data.data();
+        prove_presentation(
+            inner_circuit_data,
+            inner_proof_data,
+            false,
+            voucher.origin(),
+            voucher.degree(),
+            voucher.capabilities(),
+            voucher.score,
+            voucher.epoch_scope,
+            voucher.epoch,
+            private_key,
+            [F::ZERO; DIGEST_LENGTH],
+            verifier_id,
+            challenge,
+            window,
+        )
+    }
+
+    /// present a private voucher, which needs its locus opening
+    pub fn present_private(
+        voucher: &PrivateVoucher,
+        private_key: PrivateKey,
+        verifier_id: Digest,
+        challenge: Digest,
+        window: EpochWindow,
+    ) -> Result<Self> {
+        let opening = voucher
+            .opening()
+            .ok_or_else(|| anyhow!("only the holder of the locus opening can present"))?;
+        if KeyPair::from_private_key(private_key).public_key != opening.locus {
+            return Err(anyhow!("only the locus can present a voucher"));
+        }
+        prove_presentation(
+            &voucher.circuit_data,
+            &voucher.proof_data,
+            true,
+            voucher.origin(),
+            voucher.degree(),
+            voucher.capabilities(),
+            voucher.score,
+            voucher.epoch_scope,
+            voucher.epoch,
+            private_key,
+            opening.blinding,
+            verifier_id,
+            challenge,
+            window,
+        )
+    }
+
+    pub fn origin(&self) -> PublicKey {
+        self.public_inputs.origin
+    }
+
+    pub fn degree(&self) -> F {
+        self.public_inputs.degree
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        self.public_inputs.capabilities
+    }
+
+    /// the lowest trust weight along the path of the presented voucher
+    pub fn score(&self) -> u64 {
+        self.public_inputs.score.to_canonical_u64()
+    }
+
+    /// the identifier of the holder towards this verifier
+    pub fn pseudonym(&self) -> Digest {
+        self.public_inputs.pseudonym
+    }
+
+    /// verify the proof, and that it attests the claimed public inputs
+    pub fn verify(&self) -> bool {
+        match PresentationPublicInputs::decode(&self.proof_data.public_inputs) {
+            Ok(public_inputs) if public_inputs == self.public_inputs => {
+                self.circuit_data.verify(self.proof_data.clone()).is_ok()
+            }
+            _ => false,
+        }
+    }
+
+    /// check that the presentation was made for this verifier and the challenge it issued
+    pub fn check(&self, verifier_id: Digest, challenge: Digest) -> Result<()> {
+        if self.public_inputs.verifier_id != verifier_id {
+            return Err(anyhow!("presentation is meant for another verifier"));
+        }
+        if self.public_inputs.challenge != challenge {
+            return Err(anyhow!("presentation answers another challenge"));
+        }
+        Ok(())
+    }
+
+    /// check that the presentation proves the voucher current in the epoch its origin last
+    /// announced for the epoch scope, with a grace window of at most `grace` epochs
+    pub fn check_epoch(&self, registry: &EpochRegistry, grace: u64) -> Result<()> {
+        registry.check_window(
+            self.public_inputs.origin,
+            self.public_inputs.epoch_scope,
+            self.public_inputs.origin_epoch,
+            self.public_inputs.epoch_grace,
+            grace,
+        )
+    }
+
+    /// serialize the presentation as the kind of voucher (0 for a voucher, 1 for a private
+    /// voucher) and the public inputs, followed by the proof bytes
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = field_elements_to_bytes(&[F::from_bool(self.private_path)]);
+        bytes.extend(field_elements_to_bytes(&self.proof_data.public_inputs));
+        bytes.extend(self.proof_data.to_bytes());
+        bytes
+    }
+
+    /// deserialize a presentation, rebuilding the circuit data for the kind and degree of
+    /// the presented voucher and verifying the proof against it
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        const HEADER_LENGTH: usize = (1 + PRESENTATION_PUBLIC_INPUTS_LENGTH) * 8;
+        if bytes.len() < HEADER_LENGTH {
+            return Err(anyhow!("presentation encoding is too short"));
+        }
+
+        let (header, proof_bytes) = bytes.split_at(HEADER_LENGTH);
+        let header = field_elements_from_bytes(header)?;
+        let private_path = match header[0].to_canonical_u64() {
+            0 => false,
+            1 => true,
+            kind => return Err(anyhow!("unknown kind of voucher {kind}")),
+        };
+        let public_inputs = PresentationPublicInputs::decode(&header[1..])?;
+        let degree = public_inputs.degree.to_canonical_u64();
+        // refuse before rebuilding a circuit per degree
+        if degree > MAX_DEGREE {
+            return Err(anyhow!("presentation degree is beyond {MAX_DEGREE}"));
+        }
+
+        let circuit_data = if private_path {
+            if degree == 0 {
+                return Err(anyhow!("private vouchers have at least degree one"));
+            }
+            presentation_circuit_data(&private_voucher_circuit_data(degree), true)
+        } else {
+            presentation_circuit_data(&voucher_circuit_data(degree), false)
+        };
+        let proof_data =
+            ProofWithPublicInputs::from_bytes(proof_bytes.to_vec(), &circuit_data.common)?;
+        let presentation = Presentation {
+            private_path,
+            public_inputs,
+            circuit_data,
+            proof_data,
+        };
+        if !presentation.verify() {
+            return Err(anyhow!("presentation proof does not verify"));
+        }
+        Ok(presentation)
+    }
+}
+
+fn prove_presentation(
+    inner_circuit_data: &CircuitData<F, C, D>,
+    inner_proof_data: &ProofWithPublicInputs<F, C, D>,
+    inner_is_private: bool,
+    origin: PublicKey,
+    degree: F,
+    capabilities: Capabilities,
+    score: F,
+    epoch_scope: Digest,
+    epoch: F,
+    private_key: PrivateKey,
+    inner_blinding: Digest,
+    verifier_id: Digest,
+    challenge: Digest,
+    window: EpochWindow,
+) -> Result<Presentation> {
+    // with the public key topic as verifier id, the pseudonym would be the locus
+    if verifier_id == PUBLIC_KEY_POSTFIX {
+        return Err(anyhow!("verifier id must differ from the public key topic"));
+    }
+    window.check_range()?;
+    if !window.admits(epoch_scope, epoch.to_canonical_u64()) {
+        return Err(anyhow!("the voucher was issued outside the epoch window"));
+    }
+    let (origin_epoch, epoch_grace) = (
+        F::from_canonical_u64(window.epoch),
+        F::from_canonical_u64(window.grace),
+    );
+    let pseudonym = derive_pseudonym(private_key, verifier_id);
+
+    let config = CircuitConfig::standard_recursion_zk_config();
+    let mut circuit_builder = CircuitBuilder::<F, D>::new(config);
+    let mut partial_witness = PartialWitness::<F>::new();
+
+    let presentation_targets =
+        make_presentation_circuit(&mut circuit_builder, inner_circuit_data, inner_is_private);
+    fill_presentation_circuit(
+        &mut partial_witness,
+        presentation_targets,
+        origin,
+        degree,
+        capabilities.to_field(),
+        score,
+        epoch_scope,
+        origin_epoch,
+        epoch_grace,
+        verifier_id,
+        challenge,
+        pseudonym,
+        private_key,
+        inner_blinding,
+        inner_proof_data,
+    );
+
+    let circuit_data = circuit_builder.build::<C>();
+    let proof_data = circuit_data
+        .prove(partial_witness)
+        .map_err(|e| anyhow!("Failed to prove presentation circuit: {e}"))?;
+
+    Ok(Presentation {
+        private_path: inner_is_private,
+        public_inputs: PresentationPublicInputs {
+            origin,
+            degree,
+            capabilities,
+            score,
+            epoch_scope,
+            origin_epoch,
+            epoch_grace,
+            verifier_id,
+            challenge,
+            pseudonym,
+        },
+        circuit_data,
+        proof_data,
+    })
+}
+
+/// rebuild the (witness independent) circuit data of a presentation of a voucher proven
+/// with `inner_circuit_data`
+fn presentation_circuit_data(
+    inner_circuit_data: &CircuitData<F, C, D>,
+    inner_is_private: bool,
+) -> CircuitData<F, C, D> {
+    let config = CircuitConfig::standard_recursion_zk_config();
+    let mut circuit_builder = CircuitBuilder::<F, D>::new(config);
+    make_presentation_circuit(&mut circuit_builder, inner_circuit_data, inner_is_private);
+    circuit_builder.build::<C>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::epoch::{EpochAnnouncement, DEFAULT_EPOCH_SCOPE};
+
+    #[test]
+    fn it_works_presentation() {
+        let origin_key_pair = KeyPair::generate_key_pair();
+        let locus_key_pair = KeyPair::generate_key_pair();
+        let origin_voucher =
+            Voucher::new_origin(origin_key_pair.public_key, origin_key_pair.private_key);
+        let voucher =
+            origin_voucher.extend_voucher(origin_key_pair.private_key, locus_key_pair.public_key);
+        let (edge, other_edge) = ([F::ONE; DIGEST_LENGTH], [F::TWO; DIGEST_LENGTH]);
+        let challenge = [F::from_canonical_u64(42); DIGEST_LENGTH];
+        let window = EpochWindow {
+            scope: DEFAULT_EPOCH_SCOPE,
+            epoch: 0,
+            grace: 0,
+        };
+
+        assert!(Presentation::present(
+            &voucher,
+            origin_key_pair.private_key,
+            edge,
+            challenge,
+            window
+        )
+        .is_err());
+        assert!(Presentation::present(
+            &voucher,
+            locus_key_pair.private_key,
+            PUBLIC_KEY_POSTFIX,
+            challenge,
+            window
+        )
+        .is_err());
+
+        let presentation = Presentation::present(
+            &voucher,
+            locus_key_pair.private_key,
+            edge,
+            challenge,
+            window,
+        )
+        .unwrap();
+        assert!(presentation.verify());
+        assert_eq!(presentation.origin(), origin_key_pair.public_key);
+        assert_eq!(presentation.degree(), F::ONE);
+        assert!(!presentation
+            .proof_data
+            .public_inputs
+            .windows(PUBLIC_KEY_LENGTH)
+            .any(|window| window == locus_key_pair.public_key));
+
+        // the verifier decodes the presentation and checks it was meant for it
+        let decoded = Presentation::from_bytes(&presentation.to_bytes()).unwrap();
+        assert!(decoded.check(edge, challenge).is_ok());
+        assert!(decoded.check(other_edge, challenge).is_err());
+        assert!(decoded.check(edge, [F::ZERO; DIGEST_LENGTH]).is_err());
+
+        // the pseudonym is stable for one verifier, and distinct across verifiers
+        let again = Presentation::present(
+            &voucher,
+            locus_key_pair.private_key,
+            edge,
+            challenge,
+            window,
+        )
+        .unwrap();
+        let elsewhere = Presentation::present(
+            &voucher,
+            locus_key_pair.private_key,
+            other_edge,
+            challenge,
+            window,
+        )
+        .unwrap();
+        assert_eq!(again.pseudonym(), presentation.pseudonym());
+        assert_ne!(elsewhere.pseudonym(), presentation.pseudonym());
+
+        // private vouchers are presented with their opening
+        let private_voucher = PrivateVoucher::from_origin(
+            &origin_voucher,
+            origin_key_pair.private_key,
+            locus_key_pair.public_key,
+            Capabilities::SSH,
+            40,
+        )
+        .unwrap();
+        let private_presentation = Presentation::present_private(
+            &private_voucher,
+            locus_key_pair.private_key,
+            edge,
+            challenge,
+            window,
+        )
+        .unwrap();
+        assert_eq!(private_presentation.pseudonym(), presentation.pseudonym());
+        let decoded = Presentation::from_bytes(&private_presentation.to_bytes()).unwrap();
+        assert_eq!(decoded.capabilities(), Capabilities::SSH);
+        assert_eq!(decoded.score(), 40);
+    }
+
+    #[test]
+    fn it_works_epoch_revocation() {
+        let origin_key_pair = KeyPair::generate_key_pair();
+        let alice = KeyPair::generate_key_pair();
+        let bob = KeyPair::generate_key_pair();
+        let scope = [F::from_canonical_u64(7); DIGEST_LENGTH];
+        let (edge, challenge) = ([F::ONE; DIGEST_LENGTH], [F::TWO; DIGEST_LENGTH]);
+        let announce =
+            |epoch| EpochAnnouncement::new(origin_key_pair.private_key, scope, epoch).unwrap();
+
+        // alice is vouched for in epoch 2 of the scope, and vouches for bob
+        let origin_voucher = Voucher::new_origin_in_epoch(
+            origin_key_pair.public_key,
+            origin_key_pair.private_key,
+            Capabilities::ALL,
+            3,
+            scope,
+            2,
+        )
+        .unwrap();
+        let alice_voucher =
+            origin_voucher.extend_voucher(origin_key_pair.private_key, alice.public_key);
+        let bob_voucher = alice_voucher.extend_voucher(alice.private_key, bob.public_key);
+        assert_eq!(bob_voucher.epoch_scope(), scope);
+        assert_eq!(bob_voucher.epoch(), 2);
+        assert_eq!(
+            bob_voucher
+                .proof_public_inputs()
+                .unwrap()
+                .inner
+                .unwrap()
+                .epoch,
+            F::TWO
+        );
+
+        let mut registry = EpochRegistry::new();
+        registry.record(&announce(2)).unwrap();
+        let presentation = Presentation::present(
+            &bob_voucher,
+            bob.private_key,
+            edge,
+            challenge,
+            announce(2).window(1),
+        )
+        .unwrap();
+        let decoded = Presentation::from_bytes(&presentation.to_bytes()).unwrap();
+        assert!(decoded.check_epoch(&registry, 1).is_ok());
+        assert!(decoded.check_epoch(&EpochRegistry::new(), 1).is_err());
+
+        // bumping the epoch invalidates presentations proven for the previous one, while
+        // the subtree is still within the grace window
+        registry.record(&announce(3)).unwrap();
+        assert!(decoded.check_epoch(&registry, 1).is_err());
+        let within_grace = Presentation::present(
+            &bob_voucher,
+            bob.private_key,
+            edge,
+            challenge,
+            announce(3).window(1),
+        )
+        .unwrap();
+        assert!(within_grace.check_epoch(&registry, 1).is_ok());
+        assert!(within_grace.check_epoch(&registry, 0).is_err());
+
+        // once the grace window has passed, no voucher of the subtree can be presented
+        registry.record(&announce(4)).unwrap();
+        for (voucher, private_key) in [
+            (&alice_voucher, alice.private_key),
+            (&bob_voucher, bob.private_key),
+        ] {
+            assert!(Presentation::present(
+                voucher,
+                private_key,
+                edge,
+                challenge,
+                announce(4).window(1),
+            )
+            .is_err());
+        }
+    }
+}
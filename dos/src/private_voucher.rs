@@ -0,0 +1,512 @@
+//! Vouchers that do not reveal who vouched for the holder.
+//!
+//! The path circuit of a [`Voucher`] exposes the locus it extends, so a degree one voucher
+//! tells every verifier who the issuer is. A [`PrivateVoucher`] only exposes the origin, a
+//! commitment to its locus, its degree, capabilities, score, maximum degree and epoch: the
+//! loci along the path stay in the witness, and every hop still proves (recursively) that
+//! the previous locus signed it.
+//!
+//! The holder receives a [`LocusOpening`] with the voucher, which it needs to extend the
+//! voucher and can show to a verifier that should learn its locus.
+
+use anyhow::{anyhow, Result};
+use plonky2::{
+    field::types::{Field, PrimeField64, Sample},
+    hash::poseidon::PoseidonHash,
+    iop::witness::PartialWitness,
+    plonk::{
+        circuit_builder::CircuitBuilder,
+        circuit_data::{CircuitConfig, CircuitData},
+        config::Hasher,
+        proof::ProofWithPublicInputs,
+    },
+};
+use simple_crypto::{
+    field_elements_from_bytes, field_elements_to_bytes, Digest, KeyPair, PrivateKey, PublicKey, C,
+    D, DIGEST_LENGTH, F, PUBLIC_KEY_LENGTH,
+};
+
+use crate::capabilities::Capabilities;
+use crate::circuit_builder::{
+    fill_private_extended_voucher_circuit, make_private_extended_voucher_circuit, MAX_DEGREE,
+    MAX_TRUST_WEIGHT, PRIVATE_CAPABILITIES_PUBLIC_INPUT_INDEX, PRIVATE_DEGREE_PUBLIC_INPUT_INDEX,
+    PRIVATE_EPOCH_PUBLIC_INPUT_INDEX, PRIVATE_EPOCH_SCOPE_PUBLIC_INPUT_INDEX,
+    PRIVATE_MAX_DEGREE_PUBLIC_INPUT_INDEX, PRIVATE_SCORE_PUBLIC_INPUT_INDEX,
+};
+use crate::voucher::{voucher_circuit_data, Voucher};
+
+const PRIVATE_PUBLIC_INPUTS_LENGTH: usize = PRIVATE_EPOCH_PUBLIC_INPUT_INDEX + 1;
+
+/// The locus a private voucher commits to, and the blinding of that commitment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocusOpening {
+    pub locus: PublicKey,
+    pub blinding: Digest,
+}
+
+impl LocusOpening {
+    /// open a fresh commitment to `locus`
+    pub fn new(locus: PublicKey) -> Self {
+        LocusOpening {
+            locus,
+            blinding: F::rand_array(),
+        }
+    }
+
+    /// Poseidon(locus || blinding)
+    pub fn commitment(&self) -> Digest {
+        PoseidonHash::hash_no_pad(&[self.locus, self.blinding].concat()).elements
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        field_elements_to_bytes(&[self.locus, self.blinding].concat())
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let elements = field_elements_from_bytes(bytes)?;
+        if elements.len() != PUBLIC_KEY_LENGTH + DIGEST_LENGTH {
+            return Err(anyhow!("locus opening has the wrong length"));
+        }
+        Ok(LocusOpening {
+            locus: elements[..PUBLIC_KEY_LENGTH].try_into()?,
+            blinding: elements[PUBLIC_KEY_LENGTH..].try_into()?,
+        })
+    }
+}
+
+/// The public inputs of a private path proof.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PrivatePublicInputs {
+    pub origin: PublicKey,
+    pub locus_commitment: Digest,
+    pub degree: F,
+    pub capabilities: Capabilities,
+    pub score: F,
+    pub max_degree: F,
+    pub epoch_scope: Digest,
+    pub epoch: F,
+}
+
+impl PrivatePublicInputs {
+    pub fn decode(public_inputs: &[F]) -> Result<Self> {
+        if public_inputs.len() != PRIVATE_PUBLIC_INPUTS_LENGTH {
+            return Err(anyhow!(
+                "unexpected number of public inputs {}",
+                public_inputs.len()
+            ));
+        }
+        Ok(PrivatePublicInputs {
+            origin: public_inputs[..PUBLIC_KEY_LENGTH].try_into()?,
+            locus_commitment: public_inputs[PUBLIC_KEY_LENGTH..PRIVATE_DEGREE_PUBLIC_INPUT_INDEX]
+                .try_into()?,
+            degree: public_inputs[PRIVATE_DEGREE_PUBLIC_INPUT_INDEX],
+            capabilities: Capabilities::from_field(
+                public_inputs[PRIVATE_CAPABILITIES_PUBLIC_INPUT_INDEX],
+            )?,
+            score: public_inputs[PRIVATE_SCORE_PUBLIC_INPUT_INDEX],
+            max_degree: public_inputs[PRIVATE_MAX_DEGREE_PUBLIC_INPUT_INDEX],
+            epoch_scope: public_inputs
+                [PRIVATE_EPOCH_SCOPE_PUBLIC_INPUT_INDEX..PRIVATE_EPOCH_PUBLIC_INPUT_INDEX]
+                .try_into()?,
+            epoch: public_inputs[PRIVATE_EPOCH_PUBLIC_INPUT_INDEX],
+        })
+    }
+}
+
+pub struct PrivateVoucher {
+    pub(crate) origin: PublicKey,
+    pub(crate) locus_commitment: Digest,
+    pub(crate) degree: F,
+    pub(crate) capabilities: Capabilities,
+    pub(crate) score: F,
+    pub(crate) max_degree: F,
+    pub(crate) epoch_scope: Digest,
+    pub(crate) epoch: F,
+    /// only known to the holder (and the issuer)
+    pub(crate) opening: Option<LocusOpening>,
+    pub(crate) circuit_data: CircuitData<F, C, D>,
+    pub(crate) proof_data: ProofWithPublicInputs<F, C, D>,
+}
+
+impl PrivateVoucher {
+    /// Vouch for `outer_locus` with an origin voucher, with a trust `weight` in
+    /// 1..=[`MAX_TRUST_WEIGHT`]. Only the locus is hidden, behind a commitment: the origin is
+    /// public, and at degree one it is also the issuer. Private paths always start at an
+    /// origin, so that the chain of circuits only depends on the degree.
+    pub fn from_origin(
+        origin_voucher: &Voucher,
+        origin_private_key: PrivateKey,
+        outer_locus: PublicKey,
+        outer_capabilities: Capabilities,
+        weight: u64,
+    ) -> Result<Self> {
+        if !origin_voucher.is_origin() {
+            return Err(anyhow!("private paths start at an origin voucher"));
+        }
+        let (inner_circuit_data, inner_proof_data) = origin_voucher.voucher_proof_data.data();
+        extend_private_path(
+            inner_circuit_data,
+            inner_proof_data,
+            false,
+            origin_voucher.origin(),
+            origin_voucher.degree(),
+            origin_voucher.capabilities(),
+            origin_voucher.score(),
+            origin_voucher.max_degree(),
+            origin_voucher.epoch_scope,
+            origin_voucher.epoch,
+            origin_private_key,
+            [F::ZERO; DIGEST_LENGTH],
+            outer_locus,
+            outer_capabilities,
+            weight,
+        )
+    }
+
+    /// extend the voucher to `outer_locus` with a trust `weight`, with the private key of the
+    /// locus it commits to
+    pub fn extend(
+        &self,
+        private_key: PrivateKey,
+        outer_locus: PublicKey,
+        outer_capabilities: Capabilities,
+        weight: u64,
+    ) -> Result<Self> {
+        let opening = self
+            .opening
+            .ok_or_else(|| anyhow!("only the holder of the locus opening can extend"))?;
+        if KeyPair::from_private_key(private_key).public_key != opening.locus {
+            return Err(anyhow!("only the locus can extend a voucher"));
+        }
+        extend_private_path(
+            &self.circuit_data,
+            &self.proof_data,
+            true,
+            self.origin,
+            self.degree,
+            self.capabilities,
+            self.score(),
+            self.max_degree(),
+            self.epoch_scope,
+            self.epoch,
+            private_key,
+            opening.blinding,
+            outer_locus,
+            outer_capabilities,
+            weight,
+        )
+    }
+
+    pub fn origin(&self) -> PublicKey {
+        self.origin
+    }
+
+    pub fn locus_commitment(&self) -> Digest {
+        self.locus_commitment
+    }
+
+    pub fn degree(&self) -> F {
+        self.degree
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    /// the lowest trust weight along the path
+    pub fn score(&self) -> u64 {
+        self.score.to_canonical_u64()
+    }
+
+    /// the highest degree the origin lets the path reach
+    pub fn max_degree(&self) -> u64 {
+        self.max_degree.to_canonical_u64()
+    }
+
+    /// the scope the origin issued the path in
+    pub fn epoch_scope(&self) -> Digest {
+        self.epoch_scope
+    }
+
+    /// the epoch of the scope the origin issued the path in
+    pub fn epoch(&self) -> u64 {
+        self.epoch.to_canonical_u64()
+    }
+
+    pub fn opening(&self) -> Option<LocusOpening> {
+        self.opening
+    }
+
+    /// attach the opening handed over by the issuer, checking it opens the commitment
+    pub fn with_opening(mut self, opening: LocusOpening) -> Result<Self> {
+        if opening.commitment() != self.locus_commitment {
+            return Err(anyhow!("opening does not match the locus commitment"));
+        }
+        self.opening = Some(opening);
+        Ok(self)
+    }
+
+    pub fn proof_public_inputs(&self) -> Result<PrivatePublicInputs> {
+        PrivatePublicInputs::decode(&self.proof_data.public_inputs)
+    }
+
+    /// verify the proof, and that it attests what the voucher claims
+    pub fn verify(&self) -> bool {
+        let claimed = PrivatePublicInputs {
+            origin: self.origin,
+            locus_commitment: self.locus_commitment,
+            degree: self.degree,
+            capabilities: self.capabilities,
+            score: self.score,
+            max_degree: self.max_degree,
+            epoch_scope: self.epoch_scope,
+            epoch: self.epoch,
+        };
+        match self.proof_public_inputs() {
+            Ok(public_inputs) if public_inputs == claimed => {
+                self.circuit_data.verify(self.proof_data.clone()).is_ok()
+            }
+            _ => false,
+        }
+    }
+
+    /// serialize the public part of the voucher, without the opening: origin, locus
+    /// commitment, degree, capabilities, score, maximum degree, epoch scope and epoch
+    /// followed by the proof bytes
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = field_elements_to_bytes(&self.origin);
+        bytes.extend(field_elements_to_bytes(&self.locus_commitment));
+        bytes.extend(field_elements_to_bytes(&[
+            self.degree,
+            self.capabilities.to_field(),
+            self.score,
+            self.max_degree,
+        ]));
+        bytes.extend(field_elements_to_bytes(&self.epoch_scope));
+        bytes.extend(field_elements_to_bytes(&[self.epoch]));
+        bytes.extend(self.proof_data.to_bytes());
+        bytes
+    }
+
+    /// deserialize a voucher, rebuilding the circuit data for its degree and verifying
+    /// the proof against it
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        const HEADER_LENGTH: usize = PRIVATE_PUBLIC_INPUTS_LENGTH * 8;
+        if bytes.len() < HEADER_LENGTH {
+            return Err(anyhow!("voucher encoding is too short"));
+        }
+
+        let (header, proof_bytes) = bytes.split_at(HEADER_LENGTH);
+        let public_inputs = PrivatePublicInputs::decode(&field_elements_from_bytes(header)?)?;
+        let degree = public_inputs.degree.to_canonical_u64();
+        if degree == 0 {
+            return Err(anyhow!("private vouchers have at least degree one"));
+        }
+        // refuse before rebuilding a circuit per degree
+        let max_degree = public_inputs.max_degree.to_canonical_u64();
+        if max_degree > MAX_DEGREE || degree > max_degree {
+            return Err(anyhow!("voucher degree is beyond the maximum degree"));
+        }
+
+        let circuit_data = private_voucher_circuit_data(degree);
+        let proof_data =
+            ProofWithPublicInputs::from_bytes(proof_bytes.to_vec(), &circuit_data.common)?;
+        let voucher = PrivateVoucher {
+            origin: public_inputs.origin,
+            locus_commitment: public_inputs.locus_commitment,
+            degree: public_inputs.degree,
+            capabilities: public_inputs.capabilities,
+            score: public_inputs.score,
+            max_degree: public_inputs.max_degree,
+            epoch_scope: public_inputs.epoch_scope,
+            epoch: public_inputs.epoch,
+            opening: None,
+            circuit_data,
+            proof_data,
+        };
+        if !voucher.verify() {
+            return Err(anyhow!("voucher proof does not verify"));
+        }
+        Ok(voucher)
+    }
+}
+
+/// prove one private hop on top of the inner proof, committing to `outer_locus` with a
+/// fresh blinding
+fn extend_private_path(
+    inner_circuit_data: &CircuitData<F, C, D>,
+    inner_proof_data: &ProofWithPublicInputs<F, C, D>,
+    inner_is_private: bool,
+    origin: PublicKey,
+    inner_degree: F,
+    inner_capabilities: Capabilities,
+    inner_score: u64,
+    max_degree: u64,
+    epoch_scope: Digest,
+    epoch: F,
+    private_key: PrivateKey,
+    inner_blinding: Digest,
+    outer_locus: PublicKey,
+    outer_capabilities: Capabilities,
+    weight: u64,
+) -> Result<PrivateVoucher> {
+    if !outer_capabilities.is_subset_of(inner_capabilities) {
+        return Err(anyhow!(
+            "cannot delegate capabilities {outer_capabilities}, the voucher holds {inner_capabilities}"
+        ));
+    }
+    if !(1..=MAX_TRUST_WEIGHT).contains(&weight) {
+        return Err(anyhow!(
+            "trust weight {weight} is not between 1 and {MAX_TRUST_WEIGHT}"
+        ));
+    }
+    if inner_degree.to_canonical_u64() >= max_degree {
+        return Err(anyhow!("the origin limits paths to degree {max_degree}"));
+    }
+    if outer_locus == origin {
+        return Err(anyhow!("cannot extend a voucher back to its origin"));
+    }
+    let opening = LocusOpening::new(outer_locus);
+    let locus_commitment = opening.commitment();
+    let degree = inner_degree + F::ONE;
+    let max_degree = F::from_canonical_u64(max_degree);
+    let score = F::from_canonical_u64(weight.min(inner_score));
+
+    let config = CircuitConfig::standard_recursion_zk_config();
+    let mut circuit_builder = CircuitBuilder::<F, D>::new(config);
+    let mut partial_witness = PartialWitness::<F>::new();
+
+    let voucher_targets = make_private_extended_voucher_circuit(
+        &mut circuit_builder,
+        inner_circuit_data,
+        inner_is_private,
+    );
+    fill_private_extended_voucher_circuit(
+        &mut partial_witness,
+        voucher_targets,
+        origin,
+        locus_commitment,
+        degree,
+        outer_capabilities.to_field(),
+        F::from_canonical_u64(inner_score),
+        F::from_canonical_u64(weight),
+        max_degree,
+        epoch_scope,
+        epoch,
+        private_key,
+        inner_blinding,
+        outer_locus,
+        opening.blinding,
+        inner_proof_data,
+    );
+
+    let circuit_data = circuit_builder.build::<C>();
+    let proof_data = circuit_data
+        .prove(partial_witness)
+        .map_err(|e| anyhow!("Failed to prove private voucher circuit: {e}"))?;
+
+    Ok(PrivateVoucher {
+        origin,
+        locus_commitment,
+        degree,
+        capabilities: outer_capabilities,
+        score,
+        max_degree,
+        epoch_scope,
+        epoch,
+        opening: Some(opening),
+        circuit_data,
+        proof_data,
+    })
+}
+
+/// rebuild the circuit data of a private voucher of the given (non zero) degree: an origin
+/// circuit wrapped in `degree` private path circuits
+pub(crate) fn private_voucher_circuit_data(degree: u64) -> CircuitData<F, C, D> {
+    let mut circuit_data = voucher_circuit_data(0);
+    for hop in 0..degree {
+        let config = CircuitConfig::standard_recursion_zk_config();
+        let mut circuit_builder = CircuitBuilder::<F, D>::new(config);
+        make_private_extended_voucher_circuit(&mut circuit_builder, &circuit_data, hop > 0);
+        circuit_data = circuit_builder.build::<C>();
+    }
+    circuit_data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_works_private_voucher() {
+        let origin_key_pair = KeyPair::generate_key_pair();
+        let alice = KeyPair::generate_key_pair();
+        let bob = KeyPair::generate_key_pair().public_key;
+        let origin_voucher =
+            Voucher::new_origin(origin_key_pair.public_key, origin_key_pair.private_key);
+
+        let alice_voucher = PrivateVoucher::from_origin(
+            &origin_voucher,
+            origin_key_pair.private_key,
+            alice.public_key,
+            Capabilities::ALL,
+            MAX_TRUST_WEIGHT,
+        )
+        .unwrap();
+        assert!(alice_voucher.verify());
+        assert_eq!(alice_voucher.opening().unwrap().locus, alice.public_key);
+
+        // only alice can extend, and bob's voucher does not mention alice
+        assert!(alice_voucher
+            .extend(origin_key_pair.private_key, bob, Capabilities::ALL, 60)
+            .is_err());
+        let bob_voucher = alice_voucher
+            .extend(alice.private_key, bob, Capabilities::SSH, 60)
+            .unwrap();
+        // no hop leads back to the origin
+        assert!(alice_voucher
+            .extend(
+                alice.private_key,
+                origin_key_pair.public_key,
+                Capabilities::SSH,
+                60
+            )
+            .is_err());
+        assert!(bob_voucher.verify());
+        let public_inputs = bob_voucher.proof_public_inputs().unwrap();
+        assert_eq!(public_inputs.origin, origin_key_pair.public_key);
+        assert_eq!(public_inputs.degree, F::TWO);
+        assert_eq!(public_inputs.capabilities, Capabilities::SSH);
+        assert_eq!(public_inputs.score, F::from_canonical_u64(60));
+        for locus in [alice.public_key, bob] {
+            assert!(!bob_voucher
+                .proof_data
+                .public_inputs
+                .windows(PUBLIC_KEY_LENGTH)
+                .any(|window| window == locus));
+        }
+
+        // a verifier decodes the voucher without its opening, which the holder attaches
+        let opening = bob_voucher.opening().unwrap();
+        let decoded_voucher = PrivateVoucher::from_bytes(&bob_voucher.to_bytes()).unwrap();
+        assert_eq!(decoded_voucher.opening(), None);
+        let other_opening = LocusOpening::new(bob);
+        assert!(PrivateVoucher::from_bytes(&bob_voucher.to_bytes())
+            .unwrap()
+            .with_opening(other_opening)
+            .is_err());
+        let decoded_opening = LocusOpening::from_bytes(&opening.to_bytes()).unwrap();
+        assert!(decoded_voucher.with_opening(decoded_opening).is_ok());
+
+        // a regular path voucher cannot start a private path
+        let path_voucher = origin_voucher.extend_voucher(origin_key_pair.private_key, bob);
+        assert!(PrivateVoucher::from_origin(
+            &path_voucher,
+            origin_key_pair.private_key,
+            bob,
+            Capabilities::ALL,
+            MAX_TRUST_WEIGHT,
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,558 @@
+//! Rate limited presentations, in the style of rate limiting nullifiers (RLN).
+//!
+//! A verifier that wants to bound how often one holder gets in, without learning who it is,
+//! picks a scope and an epoch. Every [`RateLimitedPresentation`] publishes the nullifier
+//! Poseidon(private key || scope || epoch): the [`NullifierStore`] of the verifier refuses a
+//! nullifier it has seen in the epoch, while presentations in distinct epochs stay
+//! unlinkable. Optionally the presentation also carries a share of the private key, so that
+//! presenting twice in one epoch hands the private key to the verifier.
+//!
+//! These epochs are picked by the verifier and unrelated to the epochs an origin announces to
+//! revoke subtrees (see [`crate::epoch`]), which rate limited presentations check as well.
+
+use anyhow::{anyhow, Result};
+use plonky2::{
+    field::types::{Field, PrimeField64},
+    hash::poseidon::PoseidonHash,
+    iop::witness::PartialWitness,
+    plonk::{
+        circuit_builder::CircuitBuilder,
+        circuit_data::{CircuitConfig, CircuitData},
+        config::Hasher,
+        proof::ProofWithPublicInputs,
+    },
+};
+use simple_crypto::{
+    field_elements_from_bytes, field_elements_to_bytes, Digest, KeyPair, PrivateKey, PublicKey, C,
+    D, DIGEST_LENGTH, F, PRIVATE_KEY_LENGTH, PUBLIC_KEY_LENGTH,
+};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::capabilities::Capabilities;
+use crate::circuit_builder::{
+    fill_rate_limited_presentation_circuit, make_rate_limited_presentation_circuit, MAX_DEGREE,
+};
+use crate::epoch::{EpochRegistry, EpochWindow};
+use crate::private_voucher::{private_voucher_circuit_data, PrivateVoucher};
+use crate::voucher::{voucher_circuit_data, Voucher};
+
+// public inputs: origin, degree, capabilities, score, epoch scope, origin epoch, epoch grace,
+// scope, epoch, challenge, nullifier and optionally the share
+const DEGREE_INDEX: usize = PUBLIC_KEY_LENGTH;
+const EPOCH_SCOPE_INDEX: usize = DEGREE_INDEX + 3;
+const ORIGIN_EPOCH_INDEX: usize = EPOCH_SCOPE_INDEX + DIGEST_LENGTH;
+const SCOPE_INDEX: usize = ORIGIN_EPOCH_INDEX + 2;
+const EPOCH_INDEX: usize = SCOPE_INDEX + DIGEST_LENGTH;
+const CHALLENGE_INDEX: usize = EPOCH_INDEX + 1;
+const NULLIFIER_INDEX: usize = CHALLENGE_INDEX + DIGEST_LENGTH;
+const SHARE_INDEX: usize = NULLIFIER_INDEX + DIGEST_LENGTH;
+const PUBLIC_INPUTS_LENGTH: usize = SHARE_INDEX;
+const PUBLIC_INPUTS_WITH_SHARE_LENGTH: usize = SHARE_INDEX + PRIVATE_KEY_LENGTH;
+
+/// What the verifier asks for: presentations for `scope` in `epoch`, and whether they must
+/// carry a share of the private key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub scope: Digest,
+    pub epoch: u64,
+    pub with_share: bool,
+}
+
+/// The public inputs of a rate limited presentation proof.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimitedPublicInputs {
+    pub origin: PublicKey,
+    pub degree: F,
+    pub capabilities: Capabilities,
+    pub score: F,
+    pub epoch_scope: Digest,
+    /// the current epoch of the epoch scope, which the voucher is at most `epoch_grace` behind
+    pub origin_epoch: F,
+    pub epoch_grace: F,
+    pub scope: Digest,
+    pub epoch: F,
+    pub challenge: Digest,
+    pub nullifier: Digest,
+    pub share: Option<PrivateKey>,
+}
+
+impl RateLimitedPublicInputs {
+    pub fn decode(public_inputs: &[F]) -> Result<Self> {
+        let share = match public_inputs.len() {
+            PUBLIC_INPUTS_LENGTH => None,
+            PUBLIC_INPUTS_WITH_SHARE_LENGTH => Some(public_inputs[SHARE_INDEX..].try_into()?),
+            length => return Err(anyhow!("unexpected number of public inputs {length}")),
+        };
+        Ok(RateLimitedPublicInputs {
+            origin: public_inputs[..PUBLIC_KEY_LENGTH].try_into()?,
+            degree: public_inputs[DEGREE_INDEX],
+            capabilities: Capabilities::from_field(public_inputs[DEGREE_INDEX + 1])?,
+            score: public_inputs[DEGREE_INDEX + 2],
+            epoch_scope: public_inputs[EPOCH_SCOPE_INDEX..ORIGIN_EPOCH_INDEX].try_into()?,
+            origin_epoch: public_inputs[ORIGIN_EPOCH_INDEX],
+            epoch_grace: public_inputs[ORIGIN_EPOCH_INDEX + 1],
+            scope: public_inputs[SCOPE_INDEX..EPOCH_INDEX].try_into()?,
+            epoch: public_inputs[EPOCH_INDEX],
+            challenge: public_inputs[CHALLENGE_INDEX..NULLIFIER_INDEX].try_into()?,
+            nullifier: public_inputs[NULLIFIER_INDEX..SHARE_INDEX].try_into()?,
+            share,
+        })
+    }
+}
+
+/// Poseidon(private key || scope || epoch)
+pub fn derive_nullifier(private_key: PrivateKey, scope: Digest, epoch: F) -> Digest {
+    PoseidonHash::hash_no_pad(&[&private_key[..], &scope[..], &[epoch][..]].concat()).elements
+}
+
+/// the point a challenge evaluates the line of the share at
+pub fn share_point(challenge: Digest) -> F {
+    PoseidonHash::hash_no_pad(&challenge).elements[0]
+}
+
+/// the share private key + slope * x, on a line whose slope only depends on the private key
+/// and the nullifier
+fn derive_share(private_key: PrivateKey, nullifier: Digest, x: F) -> PrivateKey {
+    let slope = PoseidonHash::hash_no_pad(&[private_key, nullifier].concat()).elements;
+    let mut share = private_key;
+    for i in 0..PRIVATE_KEY_LENGTH {
+        share[i] += slope[i] * x;
+    }
+    share
+}
+
+/// Recover the private key from two shares on the same line, at distinct points.
+pub fn recover_private_key(
+    (first_x, first_share): (F, PrivateKey),
+    (second_x, second_share): (F, PrivateKey),
+) -> Option<PrivateKey> {
+    if first_x == second_x {
+        return None;
+    }
+    let mut private_key = first_share;
+    for i in 0..PRIVATE_KEY_LENGTH {
+        let slope = (first_share[i] - second_share[i]) / (first_x - second_x);
+        private_key[i] = first_share[i] - slope * first_x;
+    }
+    Some(private_key)
+}
+
+pub struct RateLimitedPresentation {
+    /// whether the presented voucher is a private voucher, which selects the inner circuit
+    pub(crate) private_path: bool,
+    pub(crate) public_inputs: RateLimitedPublicInputs,
+    pub(crate) circuit_data: CircuitData<F, C, D>,
+    pub(crate) proof_data: ProofWithPublicInputs<F, C, D>,
+}
+
+impl RateLimitedPresentation {
+    /// present `voucher` under `rate_limit`, with the private key of its locus, proving it
+    /// within the epoch `window` of the verifier
+    pub fn present(
+        voucher: &Voucher,
+        private_key: PrivateKey,
+        rate_limit: RateLimit,
+        challenge: Digest,
+        window: EpochWindow,
+    ) -> Result<Self> {
+        if KeyPair::from_private_key(private_key).public_key != voucher.locus() {
+            return Err(anyhow!("only the locus can present a voucher"));
+        }
+        let (inner_circuit_data, inner_proof_data) = voucher.voucher_proof_data.data();
+        prove_rate_limited_presentation(
+            inner_circuit_data,
+            inner_proof_data,
+            false,
+            voucher.origin(),
+            voucher.degree(),
+            voucher.capabilities(),
+            voucher.score,
+            voucher.epoch_scope,
+            voucher.epoch,
+            private_key,
+            [F::ZERO; DIGEST_LENGTH],
+            rate_limit,
+            challenge,
+            window,
+        )
+    }
+
+    /// present a private voucher under `rate_limit`, which needs its locus opening
+    pub fn present_private(
+        voucher: &PrivateVoucher,
+        private_key: PrivateKey,
+        rate_limit: RateLimit,
+        challenge: Digest,
+        window: EpochWindow,
+    ) -> Result<Self> {
+        let opening = voucher
+            .opening()
+            .ok_or_else(|| anyhow!("only the holder of the locus opening can present"))?;
+        if KeyPair::from_private_key(private_key).public_key != opening.locus {
+            return Err(anyhow!("only the locus can present a voucher"));
+        }
+        prove_rate_limited_presentation(
+            &voucher.circuit_data,
+            &voucher.proof_data,
+            true,
+            voucher.origin(),
+            voucher.degree(),
+            voucher.capabilities(),
+            voucher.score,
+            voucher.epoch_scope,
+            voucher.epoch,
+            private_key,
+            opening.blinding,
+            rate_limit,
+            challenge,
+            window,
+        )
+    }
+
+    pub fn public_inputs(&self) -> RateLimitedPublicInputs {
+        self.public_inputs
+    }
+
+    pub fn nullifier(&self) -> Digest {
+        self.public_inputs.nullifier
+    }
+
+    /// verify the proof, and that it attests the claimed public inputs
+    pub fn verify(&self) -> bool {
+        match RateLimitedPublicInputs::decode(&self.proof_data.public_inputs) {
+            Ok(public_inputs) if public_inputs == self.public_inputs => {
+                self.circuit_data.verify(self.proof_data.clone()).is_ok()
+            }
+            _ => false,
+        }
+    }
+
+    /// check that the presentation answers the rate limit and the challenge of the verifier
+    pub fn check(&self, rate_limit: RateLimit, challenge: Digest) -> Result<()> {
+        let public_inputs = &self.public_inputs;
+        if public_inputs.scope != rate_limit.scope {
+            return Err(anyhow!("presentation is meant for another scope"));
+        }
+        if public_inputs.epoch != F::from_canonical_u64(rate_limit.epoch) {
+            return Err(anyhow!("presentation is meant for another epoch"));
+        }
+        if public_inputs.share.is_some() != rate_limit.with_share {
+            return Err(anyhow!("presentation does not carry the expected share"));
+        }
+        if public_inputs.challenge != challenge {
+            return Err(anyhow!("presentation answers another challenge"));
+        }
+        Ok(())
+    }
+
+    /// check that the presentation proves the voucher current in the epoch its origin last
+    /// announced for the epoch scope, with a grace window of at most `grace` epochs
+    pub fn check_epoch(&self, registry: &EpochRegistry, grace: u64) -> Result<()> {
+        registry.check_window(
+            self.public_inputs.origin,
+            self.public_inputs.epoch_scope,
+            self.public_inputs.origin_epoch,
+            self.public_inputs.epoch_grace,
+            grace,
+        )
+    }
+
+    /// serialize the presentation as the kind of voucher (0 for a voucher, 1 for a private
+    /// voucher) and the public inputs, followed by the proof bytes
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = field_elements_to_bytes(&[F::from_bool(self.private_path)]);
+        bytes.extend((self.proof_data.public_inputs.len() as u64).to_le_bytes());
+        bytes.extend(field_elements_to_bytes(&self.proof_data.public_inputs));
+        bytes.extend(self.proof_data.to_bytes());
+        bytes
+    }
+
+    /// deserialize a presentation, rebuilding the circuit data for the kind and degree of
+    /// the presented voucher and verifying the proof against it
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < 16 {
+            return Err(anyhow!("presentation encoding is too short"));
+        }
+        let (kind, bytes) = bytes.split_at(8);
+        let (length, bytes) = bytes.split_at(8);
+        let private_path = match u64::from_le_bytes(kind.try_into()?) {
+            0 => false,
+            1 => true,
+            kind => return Err(anyhow!("unknown kind of voucher {kind}")),
+        };
+        let length = u64::from_le_bytes(length.try_into()?) as usize;
+        if length != PUBLIC_INPUTS_LENGTH && length != PUBLIC_INPUTS_WITH_SHARE_LENGTH {
+            return Err(anyhow!("unexpected number of public inputs {length}"));
+        }
+        if bytes.len() < length * 8 {
+            return Err(anyhow!("presentation encoding is too short"));
+        }
+
+        let (header, proof_bytes) = bytes.split_at(length * 8);
+        let public_inputs = RateLimitedPublicInputs::decode(&field_elements_from_bytes(header)?)?;
+        let degree = public_inputs.degree.to_canonical_u64();
+        let with_share = public_inputs.share.is_some();
+        // refuse before rebuilding a circuit per degree
+        if degree > MAX_DEGREE {
+            return Err(anyhow!("presentation degree is beyond {MAX_DEGREE}"));
+        }
+
+        let inner_circuit_data = if private_path {
+            if degree == 0 {
+                return Err(anyhow!("private vouchers have at least degree one"));
+            }
+            private_voucher_circuit_data(degree)
+        } else {
+            voucher_circuit_data(degree)
+        };
+        let config = CircuitConfig::standard_recursion_zk_config();
+        let mut circuit_builder = CircuitBuilder::<F, D>::new(config);
+        make_rate_limited_presentation_circuit(
+            &mut circuit_builder,
+            &inner_circuit_data,
+            private_path,
+            with_share,
+        );
+        let circuit_data = circuit_builder.build::<C>();
+
+        let proof_data =
+            ProofWithPublicInputs::from_bytes(proof_bytes.to_vec(), &circuit_data.common)?;
+        let presentation = RateLimitedPresentation {
+            private_path,
+            public_inputs,
+            circuit_data,
+            proof_data,
+        };
+        if !presentation.verify() {
+            return Err(anyhow!("presentation proof does not verify"));
+        }
+        Ok(presentation)
+    }
+}
+
+fn prove_rate_limited_presentation(
+    inner_circuit_data: &CircuitData<F, C, D>,
+    inner_proof_data: &ProofWithPublicInputs<F, C, D>,
+    inner_is_private: bool,
+    origin: PublicKey,
+    degree: F,
+    capabilities: Capabilities,
+    score: F,
+    epoch_scope: Digest,
+    voucher_epoch: F,
+    private_key: PrivateKey,
+    inner_blinding: Digest,
+    rate_limit: RateLimit,
+    challenge: Digest,
+    window: EpochWindow,
+) -> Result<RateLimitedPresentation> {
+    window.check_range()?;
+    if !window.admits(epoch_scope, voucher_epoch.to_canonical_u64()) {
+        return Err(anyhow!("the voucher was issued outside the epoch window"));
+    }
+    let (origin_epoch, epoch_grace) = (
+        F::from_canonical_u64(window.epoch),
+        F::from_canonical_u64(window.grace),
+    );
+    let epoch = F::from_canonical_u64(rate_limit.epoch);
+    let nullifier = derive_nullifier(private_key, rate_limit.scope, epoch);
+    let share = rate_limit
+        .with_share
+        .then(|| derive_share(private_key, nullifier, share_point(challenge)));
+
+    let config = CircuitConfig::standard_recursion_zk_config();
+    let mut circuit_builder = CircuitBuilder::<F, D>::new(config);
+    let mut partial_witness = PartialWitness::<F>::new();
+
+    let presentation_targets = make_rate_limited_presentation_circuit(
+        &mut circuit_builder,
+        inner_circuit_data,
+        inner_is_private,
+        rate_limit.with_share,
+    );
+    fill_rate_limited_presentation_circuit(
+        &mut partial_witness,
+        presentation_targets,
+        origin,
+        degree,
+        capabilities.to_field(),
+        score,
+        epoch_scope,
+        origin_epoch,
+        epoch_grace,
+        rate_limit.scope,
+        epoch,
+        challenge,
+        nullifier,
+        share,
+        private_key,
+        inner_blinding,
+        inner_proof_data,
+    );
+
+    let circuit_data = circuit_builder.build::<C>();
+    let proof_data = circuit_data
+        .prove(partial_witness)
+        .map_err(|e| anyhow!("Failed to prove rate limited presentation circuit: {e}"))?;
+
+    Ok(RateLimitedPresentation {
+        private_path: inner_is_private,
+        public_inputs: RateLimitedPublicInputs {
+            origin,
+            degree,
+            capabilities,
+            score,
+            epoch_scope,
+            origin_epoch,
+            epoch_grace,
+            scope: rate_limit.scope,
+            epoch,
+            challenge,
+            nullifier,
+            share,
+        },
+        circuit_data,
+        proof_data,
+    })
+}
+
+/// The outcome of recording a nullifier.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NullifierCheck {
+    /// first presentation of this holder in the epoch
+    Fresh,
+    /// the holder already presented in the epoch; with shares at two distinct points, its
+    /// private key is recovered
+    Repeated { private_key: Option<PrivateKey> },
+}
+
+/// The nullifiers a verifier has seen, by epoch, with the share they came with.
+#[derive(Default)]
+pub struct NullifierStore {
+    epochs: BTreeMap<u64, HashMap<[u64; DIGEST_LENGTH], Option<(F, PrivateKey)>>>,
+}
+
+impl NullifierStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the nullifier of a verified presentation, allowing one presentation per
+    /// nullifier and epoch.
+    pub fn record(&mut self, presentation: &RateLimitedPresentation) -> NullifierCheck {
+        let public_inputs = &presentation.public_inputs;
+        let nullifier = public_inputs
+            .nullifier
+            .map(|element| element.to_canonical_u64());
+        let share = public_inputs
+            .share
+            .map(|share| (share_point(public_inputs.challenge), share));
+
+        let seen = self
+            .epochs
+            .entry(public_inputs.epoch.to_canonical_u64())
+            .or_default();
+        match seen.get(&nullifier) {
+            None => {
+                seen.insert(nullifier, share);
+                NullifierCheck::Fresh
+            }
+            Some(&known_share) => NullifierCheck::Repeated {
+                private_key: known_share
+                    .zip(share)
+                    .and_then(|(first, second)| recover_private_key(first, second)),
+            },
+        }
+    }
+
+    /// forget the nullifiers of the epochs before `epoch`
+    pub fn discard_before(&mut self, epoch: u64) {
+        self.epochs = self.epochs.split_off(&epoch);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::epoch::DEFAULT_EPOCH_SCOPE;
+
+    #[test]
+    fn it_works_rate_limited_presentation() {
+        let origin_key_pair = KeyPair::generate_key_pair();
+        let locus_key_pair = KeyPair::generate_key_pair();
+        let voucher = Voucher::new_origin(origin_key_pair.public_key, origin_key_pair.private_key)
+            .extend_voucher(origin_key_pair.private_key, locus_key_pair.public_key);
+        let rate_limit = RateLimit {
+            scope: [F::ONE; DIGEST_LENGTH],
+            epoch: 7,
+            with_share: true,
+        };
+        let (challenge, other_challenge) = ([F::ONE; DIGEST_LENGTH], [F::TWO; DIGEST_LENGTH]);
+        let window = EpochWindow {
+            scope: DEFAULT_EPOCH_SCOPE,
+            epoch: 0,
+            grace: 0,
+        };
+
+        let presentation = RateLimitedPresentation::present(
+            &voucher,
+            locus_key_pair.private_key,
+            rate_limit,
+            challenge,
+            window,
+        )
+        .unwrap();
+        let decoded = RateLimitedPresentation::from_bytes(&presentation.to_bytes()).unwrap();
+        assert!(decoded.check(rate_limit, challenge).is_ok());
+        assert!(decoded.check(rate_limit, other_challenge).is_err());
+        let next_epoch = RateLimit {
+            epoch: 8,
+            ..rate_limit
+        };
+        assert!(decoded.check(next_epoch, challenge).is_err());
+
+        let mut nullifiers = NullifierStore::new();
+        assert_eq!(nullifiers.record(&decoded), NullifierCheck::Fresh);
+        // a replay repeats the nullifier, but does not reveal the key
+        assert_eq!(
+            nullifiers.record(&decoded),
+            NullifierCheck::Repeated { private_key: None }
+        );
+
+        // presenting twice in one epoch reveals the key
+        let again = RateLimitedPresentation::present(
+            &voucher,
+            locus_key_pair.private_key,
+            rate_limit,
+            other_challenge,
+            window,
+        )
+        .unwrap();
+        assert_eq!(again.nullifier(), presentation.nullifier());
+        assert_eq!(
+            nullifiers.record(&again),
+            NullifierCheck::Repeated {
+                private_key: Some(locus_key_pair.private_key)
+            }
+        );
+
+        // the next epoch has another nullifier
+        let later = RateLimitedPresentation::present(
+            &voucher,
+            locus_key_pair.private_key,
+            RateLimit {
+                with_share: false,
+                ..next_epoch
+            },
+            challenge,
+            window,
+        )
+        .unwrap();
+        assert_ne!(later.nullifier(), presentation.nullifier());
+        assert_eq!(later.public_inputs().share, None);
+        assert_eq!(nullifiers.record(&later), NullifierCheck::Fresh);
+
+        nullifiers.discard_before(8);
+        assert_eq!(nullifiers.record(&decoded), NullifierCheck::Fresh);
+    }
+}
@@ -0,0 +1,137 @@
+use anyhow::{anyhow, Result};
+use plonky2::{
+    hash::poseidon::PoseidonHash,
+    iop::witness::PartialWitness,
+    plonk::{
+        circuit_builder::CircuitBuilder,
+        circuit_data::{CircuitConfig, CircuitData},
+        config::Hasher,
+        proof::ProofWithPublicInputs,
+    },
+};
+use simple_crypto::{
+    field_elements_from_bytes, field_elements_to_bytes, Digest, KeyPair, PrivateKey, PublicKey, C,
+    D, DIGEST_LENGTH, F, PUBLIC_KEY_LENGTH,
+};
+
+use crate::circuit_builder::{fill_signature_circuit, make_signature_circuit};
+
+/// A signature by a poseidon key pair over a message of field elements.
+pub struct Signature {
+    pub(crate) signer: PublicKey,
+    pub(crate) message: Digest,
+    pub(crate) proof_data: ProofWithPublicInputs<F, C, D>,
+}
+
+impl Signature {
+    pub fn sign(private_key: PrivateKey, message: &[F]) -> Self {
+        let signer = KeyPair::from_private_key(private_key).public_key;
+        let message: Digest = PoseidonHash::hash_no_pad(message).elements;
+
+        let config = CircuitConfig::standard_recursion_zk_config();
+        let mut circuit_builder = CircuitBuilder::<F, D>::new(config);
+        let mut partial_witness = PartialWitness::<F>::new();
+
+        let signature_targets = make_signature_circuit(&mut circuit_builder);
+        fill_signature_circuit(
+            &mut partial_witness,
+            signature_targets,
+            signer,
+            message,
+            private_key,
+        );
+
+        let circuit_data = circuit_builder.build::<C>();
+        let proof_data = circuit_data
+            .prove(partial_witness)
+            .expect("Failed to prove signature circuit");
+
+        Signature {
+            signer,
+            message,
+            proof_data,
+        }
+    }
+
+    pub fn signer(&self) -> PublicKey {
+        self.signer
+    }
+
+    /// check that the signer signed exactly `message`
+    pub fn verify(&self, message: &[F]) -> bool {
+        let message: Digest = PoseidonHash::hash_no_pad(message).elements;
+        message == self.message
+            && self.proof_data.public_inputs == [self.signer, self.message].concat()
+            && signature_circuit_data()
+                .verify(self.proof_data.clone())
+                .is_ok()
+    }
+
+    /// serialize the signature as signer and message digest (as little-endian u64s)
+    /// followed by the proof bytes
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = field_elements_to_bytes(&self.signer);
+        bytes.extend(field_elements_to_bytes(&self.message));
+        bytes.extend(self.proof_data.to_bytes());
+        bytes
+    }
+
+    /// deserialize a signature; it still has to be verified against its message
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        const HEADER_LENGTH: usize = (PUBLIC_KEY_LENGTH + DIGEST_LENGTH) * 8;
+        if bytes.len() < HEADER_LENGTH {
+            return Err(anyhow!("signature encoding is too short"));
+        }
+
+        let (header, proof_bytes) = bytes.split_at(HEADER_LENGTH);
+        let header = field_elements_from_bytes(header)?;
+        let signer: PublicKey = header[..PUBLIC_KEY_LENGTH].try_into()?;
+        let message: Digest = header[PUBLIC_KEY_LENGTH..].try_into()?;
+
+        let circuit_data = signature_circuit_data();
+        let proof_data =
+            ProofWithPublicInputs::from_bytes(proof_bytes.to_vec(), &circuit_data.common)?;
+
+        Ok(Signature {
+            signer,
+            message,
+            proof_data,
+        })
+    }
+}
+
+/// the signature circuit does not depend on the witness, so it is rebuilt for every use
+fn signature_circuit_data() -> CircuitData<F, C, D> {
+    let config = CircuitConfig::standard_recursion_zk_config();
+    let mut circuit_builder = CircuitBuilder::<F, D>::new(config);
+    make_signature_circuit(&mut circuit_builder);
+    circuit_builder.build::<C>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use plonky2::field::types::Field;
+
+    #[test]
+    fn it_works_signature() {
+        let key_pair = KeyPair::generate_key_pair();
+        let message = [F::ONE, F::TWO, F::ZERO];
+        let signature = Signature::sign(key_pair.private_key, &message);
+
+        assert_eq!(signature.signer(), key_pair.public_key);
+        assert!(signature.verify(&message));
+        assert!(!signature.verify(&[F::ONE]));
+
+        let decoded = Signature::from_bytes(&signature.to_bytes()).unwrap();
+        assert!(decoded.verify(&message));
+
+        // a signature cannot be claimed by another key
+        let stranger = KeyPair::generate_key_pair().public_key;
+        let mut forged = Signature::from_bytes(&signature.to_bytes()).unwrap();
+        forged.signer = stranger;
+        assert!(!forged.verify(&message));
+        forged.proof_data.public_inputs[..PUBLIC_KEY_LENGTH].copy_from_slice(&stranger);
+        assert!(!forged.verify(&message));
+    }
+}
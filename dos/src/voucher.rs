@@ -1,11 +1,20 @@
-use simple_crypto::{Digest, PrivateKey, PublicKey, C, D, F};
+use anyhow::{anyhow, Result};
+use dos_core::VoucherPublicInputs;
+use simple_crypto::{
+    field_elements_from_bytes, field_elements_to_bytes, Digest, KeyPair, PrivateKey, PublicKey, C,
+    D, DIGEST_LENGTH, F, PUBLIC_KEY_LENGTH,
+};
 
+use crate::capabilities::Capabilities;
 use crate::circuit_builder::{
     fill_extend_voucher_circuit, fill_origin_voucher_circuit, make_extended_voucher_circuit,
-    make_origin_voucher_circuit,
+    make_origin_voucher_circuit, CAPABILITIES_PUBLIC_INPUT_INDEX, DEFAULT_MAX_DEGREE,
+    DEGREE_PUBLIC_INPUT_INDEX, EPOCH_PUBLIC_INPUT_INDEX, EPOCH_SCOPE_PUBLIC_INPUT_INDEX,
+    MAX_DEGREE, MAX_DEGREE_PUBLIC_INPUT_INDEX, MAX_TRUST_WEIGHT, SCORE_PUBLIC_INPUT_INDEX,
 };
+use crate::epoch::{DEFAULT_EPOCH_SCOPE, MAX_EPOCH};
 use plonky2::{
-    field::types::Field,
+    field::types::{Field, PrimeField64},
     hash::poseidon::PoseidonHash,
     iop::witness::PartialWitness,
     plonk::{
@@ -15,29 +24,178 @@ use plonky2::{
         proof::ProofWithPublicInputs,
     },
 };
+use std::sync::{Arc, Mutex, PoisonError};
+
+// public inputs: origin, locus, signature, degree, capabilities, score, maximum degree, epoch
+// scope, epoch, and for paths the same for the inner voucher, without its signature
+const ORIGIN_PUBLIC_INPUTS_LENGTH: usize = EPOCH_PUBLIC_INPUT_INDEX + 1;
+const INNER_DEGREE_INDEX: usize = ORIGIN_PUBLIC_INPUTS_LENGTH + 2 * PUBLIC_KEY_LENGTH;
+const INNER_EPOCH_SCOPE_INDEX: usize = INNER_DEGREE_INDEX + 4;
+const PATH_PUBLIC_INPUTS_LENGTH: usize = INNER_EPOCH_SCOPE_INDEX + DIGEST_LENGTH + 1;
 
 pub struct Voucher {
     pub(crate) origin: PublicKey,
     pub(crate) locus: PublicKey,
     pub(crate) degree: F,
+    pub(crate) capabilities: Capabilities,
+    /// the lowest trust weight along the path, [`MAX_TRUST_WEIGHT`] for an origin voucher
+    pub(crate) score: F,
+    /// the highest degree the origin lets the path reach
+    pub(crate) max_degree: F,
+    /// the scope and epoch the origin issued the path in
+    pub(crate) epoch_scope: Digest,
+    pub(crate) epoch: F,
     pub(crate) voucher_proof_data: VoucherProofData,
 }
 
+/// The circuit data is shared, decoded vouchers of the same degree all hold the same
+/// circuit data (see [`cached_voucher_circuit_data`]).
 pub enum VoucherProofData {
     PathProofData {
-        circuit_data: CircuitData<F, C, D>,
+        circuit_data: Arc<CircuitData<F, C, D>>,
         proof_data: ProofWithPublicInputs<F, C, D>,
     },
     OriginProofData {
-        circuit_data: CircuitData<F, C, D>,
+        circuit_data: Arc<CircuitData<F, C, D>>,
         proof_data: ProofWithPublicInputs<F, C, D>,
     },
 }
 
+/// The public inputs of a voucher proof, decoded from the positions the circuits register
+/// them at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PublicInputs {
+    pub origin: PublicKey,
+    pub locus: PublicKey,
+    pub signature: Digest,
+    pub degree: F,
+    pub capabilities: Capabilities,
+    pub score: F,
+    pub max_degree: F,
+    pub epoch_scope: Digest,
+    pub epoch: F,
+    /// only path circuits expose the voucher they extend
+    pub inner: Option<InnerPublicInputs>,
+}
+
+/// The public inputs of a path circuit about the voucher it extends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InnerPublicInputs {
+    pub origin: PublicKey,
+    pub locus: PublicKey,
+    pub degree: F,
+    pub capabilities: Capabilities,
+    pub score: F,
+    pub max_degree: F,
+    pub epoch_scope: Digest,
+    pub epoch: F,
+}
+
+impl PublicInputs {
+    /// decode the public inputs of an origin or a path circuit
+    pub fn decode(public_inputs: &[F]) -> Result<Self> {
+        let key = |start: usize| -> PublicKey {
+            public_inputs[start..start + PUBLIC_KEY_LENGTH]
+                .try_into()
+                .expect("slice has public key length")
+        };
+        let inner = match public_inputs.len() {
+            ORIGIN_PUBLIC_INPUTS_LENGTH => None,
+            PATH_PUBLIC_INPUTS_LENGTH => Some(InnerPublicInputs {
+                origin: key(ORIGIN_PUBLIC_INPUTS_LENGTH),
+                locus: key(ORIGIN_PUBLIC_INPUTS_LENGTH + PUBLIC_KEY_LENGTH),
+                degree: public_inputs[INNER_DEGREE_INDEX],
+                capabilities: Capabilities::from_field(public_inputs[INNER_DEGREE_INDEX + 1])?,
+                score: public_inputs[INNER_DEGREE_INDEX + 2],
+                max_degree: public_inputs[INNER_DEGREE_INDEX + 3],
+                epoch_scope: public_inputs
+                    [INNER_EPOCH_SCOPE_INDEX..INNER_EPOCH_SCOPE_INDEX + DIGEST_LENGTH]
+                    .try_into()
+                    .expect("slice has digest length"),
+                epoch: public_inputs[PATH_PUBLIC_INPUTS_LENGTH - 1],
+            }),
+            length => return Err(anyhow!("unexpected number of public inputs {length}")),
+        };
+
+        Ok(PublicInputs {
+            origin: key(0),
+            locus: key(PUBLIC_KEY_LENGTH),
+            signature: public_inputs[2 * PUBLIC_KEY_LENGTH..DEGREE_PUBLIC_INPUT_INDEX]
+                .try_into()
+                .expect("slice has digest length"),
+            degree: public_inputs[DEGREE_PUBLIC_INPUT_INDEX],
+            capabilities: Capabilities::from_field(public_inputs[CAPABILITIES_PUBLIC_INPUT_INDEX])?,
+            score: public_inputs[SCORE_PUBLIC_INPUT_INDEX],
+            max_degree: public_inputs[MAX_DEGREE_PUBLIC_INPUT_INDEX],
+            epoch_scope: public_inputs[EPOCH_SCOPE_PUBLIC_INPUT_INDEX..EPOCH_PUBLIC_INPUT_INDEX]
+                .try_into()
+                .expect("slice has digest length"),
+            epoch: public_inputs[EPOCH_PUBLIC_INPUT_INDEX],
+            inner,
+        })
+    }
+}
+
 impl Voucher {
-    fn new_origin(origin: PublicKey, private_key: PrivateKey) -> Self {
+    /// an origin voucher granting every capability
+    pub fn new_origin(origin: PublicKey, private_key: PrivateKey) -> Self {
+        Self::new_origin_with_capabilities(origin, private_key, Capabilities::ALL)
+    }
+
+    /// an origin voucher granting `capabilities`, which bound those of every extension
+    pub fn new_origin_with_capabilities(
+        origin: PublicKey,
+        private_key: PrivateKey,
+        capabilities: Capabilities,
+    ) -> Self {
+        Self::new_origin_with_max_degree(origin, private_key, capabilities, DEFAULT_MAX_DEGREE)
+            .expect("the default maximum degree is in range")
+    }
+
+    /// An origin voucher granting `capabilities`, which paths extend up to `max_degree`
+    /// hops, at most [`MAX_DEGREE`]. The circuits refuse to extend beyond it.
+    pub fn new_origin_with_max_degree(
+        origin: PublicKey,
+        private_key: PrivateKey,
+        capabilities: Capabilities,
+        max_degree: u64,
+    ) -> Result<Self> {
+        Self::new_origin_in_epoch(
+            origin,
+            private_key,
+            capabilities,
+            max_degree,
+            DEFAULT_EPOCH_SCOPE,
+            0,
+        )
+    }
+
+    /// An origin voucher issued in `epoch` of `epoch_scope`, at most [`MAX_EPOCH`], which
+    /// every extension inherits. Once the origin announces a later epoch for the scope,
+    /// presentations of the path only pass within the grace window of the verifier, see
+    /// [`crate::epoch`].
+    pub fn new_origin_in_epoch(
+        origin: PublicKey,
+        private_key: PrivateKey,
+        capabilities: Capabilities,
+        max_degree: u64,
+        epoch_scope: Digest,
+        epoch: u64,
+    ) -> Result<Self> {
+        if max_degree > MAX_DEGREE {
+            return Err(anyhow!("maximum degree {max_degree} is above {MAX_DEGREE}"));
+        }
+        if epoch > MAX_EPOCH {
+            return Err(anyhow!("epoch {epoch} is above {MAX_EPOCH}"));
+        }
+        if KeyPair::from_private_key(private_key).public_key != origin {
+            return Err(anyhow!("only the origin can issue its origin voucher"));
+        }
+        let max_degree = F::from_canonical_u64(max_degree);
+        let epoch = F::from_canonical_u64(epoch);
+
         // compute "signature" to pass as public inputs
-        let locus: PublicKey = origin.clone();
+        let locus: PublicKey = origin;
         let signature: Digest = PoseidonHash::hash_no_pad(&[origin, locus].concat()).elements;
 
         let config = CircuitConfig::standard_recursion_zk_config();
@@ -52,47 +210,113 @@ impl Voucher {
             locus,
             private_key,
             signature,
+            capabilities.to_field(),
+            max_degree,
+            epoch_scope,
+            epoch,
         );
 
         let circuit_data = circuit_builder.build::<C>();
         let proof_with_pis = circuit_data
             .prove(partial_witness)
-            .expect("Failed to prove origin voucher circuit");
+            .map_err(|e| anyhow!("failed to prove origin voucher circuit: {e}"))?;
 
-        Voucher {
+        Ok(Voucher {
             origin,
             locus: origin,
             degree: F::ZERO,
+            capabilities,
+            score: F::from_canonical_u64(MAX_TRUST_WEIGHT),
+            max_degree,
+            epoch_scope,
+            epoch,
             voucher_proof_data: VoucherProofData::OriginProofData {
-                circuit_data: circuit_data,
+                circuit_data: Arc::new(circuit_data),
                 proof_data: proof_with_pis,
             },
-        }
+        })
     }
 
-    fn extend_voucher(
+    /// extend the voucher to `outer_locus`, delegating all of its capabilities; panics if
+    /// the voucher is at its maximum degree or the private key is not that of its locus
+    pub fn extend_voucher(
         &self,
         inner_private_key_locus: PrivateKey,
         outer_locus: PublicKey,
     ) -> Voucher {
-        let outer_origin: PublicKey = self.origin.clone();
-        let inner_locus: PublicKey = self.locus.clone();
+        self.extend_voucher_with_capabilities(
+            inner_private_key_locus,
+            outer_locus,
+            self.capabilities,
+        )
+        .expect("the locus of a voucher below its maximum degree can delegate its capabilities")
+    }
+
+    /// extend the voucher to `outer_locus`, delegating only `outer_capabilities`, which
+    /// must be a subset of the capabilities of this voucher, with full trust
+    pub fn extend_voucher_with_capabilities(
+        &self,
+        inner_private_key_locus: PrivateKey,
+        outer_locus: PublicKey,
+        outer_capabilities: Capabilities,
+    ) -> Result<Voucher> {
+        self.extend_voucher_weighted(
+            inner_private_key_locus,
+            outer_locus,
+            outer_capabilities,
+            MAX_TRUST_WEIGHT,
+        )
+    }
+
+    /// Extend the voucher to `outer_locus` with a trust `weight` in 1..=[`MAX_TRUST_WEIGHT`],
+    /// signed along with the locus. The score of the extended voucher is the lower of the
+    /// score of this voucher and the weight.
+    pub fn extend_voucher_weighted(
+        &self,
+        inner_private_key_locus: PrivateKey,
+        outer_locus: PublicKey,
+        outer_capabilities: Capabilities,
+        weight: u64,
+    ) -> Result<Voucher> {
+        if !outer_capabilities.is_subset_of(self.capabilities) {
+            return Err(anyhow!(
+                "cannot delegate capabilities {outer_capabilities}, the voucher holds {}",
+                self.capabilities
+            ));
+        }
+        if !(1..=MAX_TRUST_WEIGHT).contains(&weight) {
+            return Err(anyhow!(
+                "trust weight {weight} is not between 1 and {MAX_TRUST_WEIGHT}"
+            ));
+        }
+        if self.degree() >= self.max_degree() {
+            return Err(anyhow!(
+                "the origin limits paths to degree {}",
+                self.max_degree()
+            ));
+        }
+        if outer_locus == self.origin {
+            return Err(anyhow!("cannot extend a voucher back to its origin"));
+        }
+        if KeyPair::from_private_key(inner_private_key_locus).public_key != self.locus {
+            return Err(anyhow!("only the locus can extend a voucher"));
+        }
+        let outer_origin: PublicKey = self.origin;
+        let inner_locus: PublicKey = self.locus;
         let inner_degree: F = self.degree;
+        let outer_score = F::from_canonical_u64(weight.min(self.score()));
+        let weight = F::from_canonical_u64(weight);
 
-        let outer_signature: Digest =
-            PoseidonHash::hash_no_pad(&[outer_origin, outer_locus].concat()).elements;
+        let outer_signature: Digest = PoseidonHash::hash_no_pad(
+            &[&inner_locus[..], &outer_locus[..], &[weight][..]].concat(),
+        )
+        .elements;
 
         let config = CircuitConfig::standard_recursion_zk_config();
         let mut circuit_builder = CircuitBuilder::<F, D>::new(config);
         let mut partial_witness = PartialWitness::<F>::new();
 
-        let (inner_circuit_data, inner_proof_with_pis) = match &self.voucher_proof_data {
-            VoucherProofData::PathProofData { .. } => unimplemented!(),
-            VoucherProofData::OriginProofData {
-                circuit_data,
-                proof_data,
-            } => (circuit_data, proof_data),
-        };
+        let (inner_circuit_data, inner_proof_with_pis) = self.voucher_proof_data.data();
         let voucher_targets =
             make_extended_voucher_circuit(&mut circuit_builder, inner_circuit_data);
 
@@ -102,41 +326,478 @@ impl Voucher {
             outer_origin,
             inner_locus,
             inner_degree,
+            self.capabilities.to_field(),
+            self.score,
+            self.max_degree,
+            self.epoch_scope,
+            self.epoch,
             outer_locus,
+            outer_capabilities.to_field(),
+            weight,
             inner_private_key_locus,
             outer_signature,
-            inner_circuit_data,
             inner_proof_with_pis,
         );
 
         let circuit_data = circuit_builder.build::<C>();
         let proof_with_pis = circuit_data
             .prove(partial_witness)
-            .expect("Failed to prove origin voucher circuit");
+            .map_err(|e| anyhow!("failed to prove extended voucher circuit: {e}"))?;
 
-        Voucher {
+        Ok(Voucher {
             origin: outer_origin,
             locus: outer_locus,
             degree: inner_degree + F::ONE,
+            capabilities: outer_capabilities,
+            score: outer_score,
+            max_degree: self.max_degree,
+            epoch_scope: self.epoch_scope,
+            epoch: self.epoch,
             voucher_proof_data: VoucherProofData::PathProofData {
-                circuit_data,
+                circuit_data: Arc::new(circuit_data),
                 proof_data: proof_with_pis,
             },
+        })
+    }
+
+    /// Adopt the proof of any circuit with the voucher public inputs (origin, locus,
+    /// signature, degree, capabilities, score, maximum degree, epoch scope, epoch), such as
+    /// the ed25519 bridge of `dos-ed25519`, so that it can be extended with Poseidon hops.
+    /// The proof is verified before returning.
+    pub fn from_proof(
+        circuit_data: CircuitData<F, C, D>,
+        proof_data: ProofWithPublicInputs<F, C, D>,
+    ) -> Result<Self> {
+        let public_inputs = PublicInputs::decode(&proof_data.public_inputs)?;
+        let (origin, locus, degree, capabilities, score, max_degree) = (
+            public_inputs.origin,
+            public_inputs.locus,
+            public_inputs.degree,
+            public_inputs.capabilities,
+            public_inputs.score,
+            public_inputs.max_degree,
+        );
+        let (epoch_scope, epoch) = (public_inputs.epoch_scope, public_inputs.epoch);
+
+        let circuit_data = Arc::new(circuit_data);
+        let voucher_proof_data = if degree == F::ZERO {
+            VoucherProofData::OriginProofData {
+                circuit_data,
+                proof_data,
+            }
+        } else {
+            VoucherProofData::PathProofData {
+                circuit_data,
+                proof_data,
+            }
+        };
+        let voucher = Voucher {
+            origin,
+            locus,
+            degree,
+            capabilities,
+            score,
+            max_degree,
+            epoch_scope,
+            epoch,
+            voucher_proof_data,
+        };
+        if !voucher.verify() {
+            return Err(anyhow!("voucher proof does not verify"));
+        }
+
+        Ok(voucher)
+    }
+
+    pub fn origin(&self) -> PublicKey {
+        self.origin
+    }
+
+    pub fn locus(&self) -> PublicKey {
+        self.locus
+    }
+
+    pub fn degree(&self) -> F {
+        self.degree
+    }
+
+    /// what the holder of the voucher is allowed to do
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    /// how much the path is trusted, the lowest weight of its extensions
+    pub fn score(&self) -> u64 {
+        self.score.to_canonical_u64()
+    }
+
+    /// the highest degree the origin lets the path reach
+    pub fn max_degree(&self) -> u64 {
+        self.max_degree.to_canonical_u64()
+    }
+
+    /// the scope the origin issued the path in
+    pub fn epoch_scope(&self) -> Digest {
+        self.epoch_scope
+    }
+
+    /// the epoch of the scope the origin issued the path in
+    pub fn epoch(&self) -> u64 {
+        self.epoch.to_canonical_u64()
+    }
+
+    /// the locus that signed the last edge of the path, the origin itself for an origin
+    /// voucher, or for a path circuit that does not expose its inner voucher
+    pub fn issuer(&self) -> PublicKey {
+        self.proof_public_inputs()
+            .ok()
+            .and_then(|public_inputs| public_inputs.inner)
+            .map_or(self.origin, |inner| inner.locus)
+    }
+
+    /// the typed public inputs of the proof
+    pub fn proof_public_inputs(&self) -> Result<PublicInputs> {
+        let (_, proof_data) = self.voucher_proof_data.data();
+        PublicInputs::decode(&proof_data.public_inputs)
+    }
+
+    /// Check that the proof attests the origin, locus, degree, capabilities, score, maximum
+    /// degree and epoch this voucher claims, that the degree is within the maximum degree,
+    /// and that a path proof extends a voucher of the same origin, maximum degree and epoch,
+    /// one degree less, with at least the same capabilities and score.
+    pub fn check_public_inputs(&self) -> Result<()> {
+        let public_inputs = self.proof_public_inputs()?;
+        if public_inputs.origin != self.origin {
+            return Err(anyhow!("proof attests another origin"));
+        }
+        if public_inputs.locus != self.locus {
+            return Err(anyhow!("proof attests another locus"));
+        }
+        if public_inputs.degree != self.degree {
+            return Err(anyhow!("proof attests another degree"));
+        }
+        if public_inputs.capabilities != self.capabilities {
+            return Err(anyhow!("proof attests other capabilities"));
+        }
+        if public_inputs.score != self.score {
+            return Err(anyhow!("proof attests another score"));
+        }
+        if public_inputs.max_degree != self.max_degree {
+            return Err(anyhow!("proof attests another maximum degree"));
+        }
+        if public_inputs.epoch_scope != self.epoch_scope || public_inputs.epoch != self.epoch {
+            return Err(anyhow!("proof attests another epoch"));
+        }
+        if self.max_degree() > MAX_DEGREE || self.degree() > self.max_degree() {
+            return Err(anyhow!("degree is beyond the maximum degree"));
+        }
+        if self.is_origin() != (self.degree == F::ZERO) {
+            return Err(anyhow!("only origin vouchers have degree zero"));
+        }
+        if let Some(inner) = public_inputs.inner {
+            if self.is_origin() {
+                return Err(anyhow!("origin proof extends another voucher"));
+            }
+            if inner.origin != self.origin
+                || inner.degree + F::ONE != self.degree
+                || inner.max_degree != self.max_degree
+                || inner.epoch_scope != self.epoch_scope
+                || inner.epoch != self.epoch
+            {
+                return Err(anyhow!("proof extends an inconsistent voucher"));
+            }
+            if !self.capabilities.is_subset_of(inner.capabilities) {
+                return Err(anyhow!("proof widens the capabilities it extends"));
+            }
+            if self.score() > inner.score.to_canonical_u64() {
+                return Err(anyhow!("proof raises the score it extends"));
+            }
         }
+        Ok(())
+    }
+
+    /// the digest of the circuit the proof was made for, which pins its verifier data
+    pub fn circuit_digest(&self) -> Digest {
+        let (circuit_data, _) = self.voucher_proof_data.data();
+        circuit_data.verifier_only.circuit_digest.elements
+    }
+
+    pub fn is_origin(&self) -> bool {
+        matches!(
+            self.voucher_proof_data,
+            VoucherProofData::OriginProofData { .. }
+        )
+    }
+
+    /// verify the proof, and that it attests what the voucher claims
+    pub fn verify(&self) -> bool {
+        if self.check_public_inputs().is_err() {
+            return false;
+        }
+        let (circuit_data, proof_data) = self.voucher_proof_data.data();
+        circuit_data.verify(proof_data.clone()).is_ok()
+    }
+
+    /// serialize the voucher as origin, locus, degree, capabilities, score, maximum degree,
+    /// epoch scope and epoch (as little-endian u64s) followed by the proof bytes
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let (_, proof_data) = self.voucher_proof_data.data();
+        let mut bytes = field_elements_to_bytes(&self.origin);
+        bytes.extend(field_elements_to_bytes(&self.locus));
+        bytes.extend(field_elements_to_bytes(&[
+            self.degree,
+            self.capabilities.to_field(),
+            self.score,
+            self.max_degree,
+        ]));
+        bytes.extend(field_elements_to_bytes(&self.epoch_scope));
+        bytes.extend(field_elements_to_bytes(&[self.epoch]));
+        bytes.extend(proof_data.to_bytes());
+        bytes
+    }
+
+    /// deserialize a voucher; the circuit data is rebuilt for the encoded degree
+    /// and the proof is verified against it before returning. Only paths from origin
+    /// vouchers of this crate decode, see [`Voucher::from_bytes_with_root`] for paths from
+    /// a proof adopted with [`Voucher::from_proof`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        Self::decode(bytes, None)
+    }
+
+    /// read the degree from the header of an encoded voucher without decoding the proof, so
+    /// that receivers can refuse vouchers beyond their policy before rebuilding any circuit
+    pub fn encoded_degree(bytes: &[u8]) -> Result<u64> {
+        const DEGREE_OFFSET: usize = 2 * PUBLIC_KEY_LENGTH * 8;
+        let degree = bytes
+            .get(DEGREE_OFFSET..DEGREE_OFFSET + 8)
+            .ok_or_else(|| anyhow!("voucher encoding is too short"))?;
+        Ok(field_elements_from_bytes(degree)?[0].to_canonical_u64())
     }
 
-    // fn verify
+    /// Deserialize a voucher whose path starts with a proof of `root_circuit_data` of degree
+    /// `root_degree`, adopted with [`Voucher::from_proof`], such as the ed25519 bridge of
+    /// `dos-ed25519`. The circuit data is rebuilt by extending the root circuit.
+    pub fn from_bytes_with_root(
+        bytes: &[u8],
+        root_circuit_data: CircuitData<F, C, D>,
+        root_degree: u64,
+    ) -> Result<Self> {
+        Self::decode(bytes, Some((root_circuit_data, root_degree)))
+    }
+
+    fn decode(bytes: &[u8], root: Option<(CircuitData<F, C, D>, u64)>) -> Result<Self> {
+        const HEADER_LENGTH: usize = (2 * PUBLIC_KEY_LENGTH + 4 + DIGEST_LENGTH + 1) * 8;
+        if bytes.len() < HEADER_LENGTH {
+            return Err(anyhow!("voucher encoding is too short"));
+        }
+
+        let (header, proof_bytes) = bytes.split_at(HEADER_LENGTH);
+        let header = field_elements_from_bytes(header)?;
+        let origin: PublicKey = header[..PUBLIC_KEY_LENGTH].try_into()?;
+        let locus: PublicKey = header[PUBLIC_KEY_LENGTH..2 * PUBLIC_KEY_LENGTH].try_into()?;
+        let degree = header[2 * PUBLIC_KEY_LENGTH];
+        let capabilities = Capabilities::from_field(header[2 * PUBLIC_KEY_LENGTH + 1])?;
+        let score = header[2 * PUBLIC_KEY_LENGTH + 2];
+        let max_degree = header[2 * PUBLIC_KEY_LENGTH + 3];
+        let epoch_scope: Digest = header
+            [2 * PUBLIC_KEY_LENGTH + 4..2 * PUBLIC_KEY_LENGTH + 4 + DIGEST_LENGTH]
+            .try_into()?;
+        let epoch = header[2 * PUBLIC_KEY_LENGTH + 4 + DIGEST_LENGTH];
+
+        // refuse before rebuilding a circuit per degree
+        if max_degree.to_canonical_u64() > MAX_DEGREE
+            || degree.to_canonical_u64() > max_degree.to_canonical_u64()
+        {
+            return Err(anyhow!("voucher degree is beyond the maximum degree"));
+        }
+        let is_adopted = root.is_some();
+        let circuit_data = match root {
+            None => cached_voucher_circuit_data(degree.to_canonical_u64()),
+            Some((root_circuit_data, root_degree)) => {
+                let hops = degree
+                    .to_canonical_u64()
+                    .checked_sub(root_degree)
+                    .ok_or_else(|| anyhow!("voucher degree is below the degree of its root"))?;
+                Arc::new(extend_circuit_data(root_circuit_data, hops))
+            }
+        };
+        let proof_data =
+            ProofWithPublicInputs::from_bytes(proof_bytes.to_vec(), &circuit_data.common)
+                .map_err(|e| anyhow!("voucher proof does not decode: {e}"))?;
+
+        let voucher_proof_data = if degree == F::ZERO {
+            VoucherProofData::OriginProofData {
+                circuit_data,
+                proof_data,
+            }
+        } else {
+            VoucherProofData::PathProofData {
+                circuit_data,
+                proof_data,
+            }
+        };
+
+        let voucher = Voucher {
+            origin,
+            locus,
+            degree,
+            capabilities,
+            score,
+            max_degree,
+            epoch_scope,
+            epoch,
+            voucher_proof_data,
+        };
+        voucher
+            .check_public_inputs()
+            .map_err(|e| anyhow!("voucher header does not match the proof: {e}"))?;
+        if !voucher.verify() {
+            // the encoding does not tell the root of a path, and an adopted root makes
+            // every circuit along the path differ from the ones rebuilt here
+            if !is_adopted {
+                return Err(anyhow!(
+                    "voucher proof does not verify as a path from an origin voucher, paths \
+                     from an adopted proof such as a bridged ed25519 voucher decode with \
+                     from_bytes_with_root"
+                ));
+            }
+            return Err(anyhow!("voucher proof does not verify"));
+        }
+
+        Ok(voucher)
+    }
+}
+
+impl dos_core::Voucher for Voucher {
+    fn origin(&self) -> Vec<u8> {
+        field_elements_to_bytes(&self.origin)
+    }
+
+    fn locus(&self) -> Vec<u8> {
+        field_elements_to_bytes(&self.locus)
+    }
+
+    fn degree(&self) -> u64 {
+        self.degree.to_canonical_u64()
+    }
+
+    fn public_inputs(&self) -> Result<VoucherPublicInputs> {
+        let public_inputs = self.proof_public_inputs()?;
+        Ok(VoucherPublicInputs {
+            origin: field_elements_to_bytes(&public_inputs.origin),
+            locus: field_elements_to_bytes(&public_inputs.locus),
+            degree: public_inputs.degree.to_canonical_u64(),
+        })
+    }
+
+    fn verify(&self) -> bool {
+        Voucher::verify(self)
+    }
+
+    fn extend(&self, locus_secret_key: &[u8], locus: &[u8]) -> Result<Box<dyn dos_core::Voucher>> {
+        let private_key: PrivateKey = field_elements_from_bytes(locus_secret_key)?
+            .try_into()
+            .map_err(|_| anyhow!("private key has the wrong length"))?;
+        let locus: PublicKey = field_elements_from_bytes(locus)?
+            .try_into()
+            .map_err(|_| anyhow!("locus has the wrong length"))?;
+
+        Ok(Box::new(self.extend_voucher_with_capabilities(
+            private_key,
+            locus,
+            self.capabilities,
+        )?))
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        Voucher::to_bytes(self)
+    }
+}
+
+impl VoucherProofData {
+    pub(crate) fn data(&self) -> (&CircuitData<F, C, D>, &ProofWithPublicInputs<F, C, D>) {
+        match self {
+            VoucherProofData::PathProofData {
+                circuit_data,
+                proof_data,
+            }
+            | VoucherProofData::OriginProofData {
+                circuit_data,
+                proof_data,
+            } => (circuit_data.as_ref(), proof_data),
+        }
+    }
+}
+
+/// the circuit data of the degrees decoded so far, indexed by degree
+static VOUCHER_CIRCUIT_DATA: Mutex<Vec<Arc<CircuitData<F, C, D>>>> = Mutex::new(Vec::new());
+
+/// the circuit data for a voucher of the given degree, built once per process: decoding
+/// would otherwise rebuild `degree + 1` circuits for every voucher received. Callers bound
+/// the degree, every degree up to it stays cached.
+pub(crate) fn cached_voucher_circuit_data(degree: u64) -> Arc<CircuitData<F, C, D>> {
+    let mut levels = VOUCHER_CIRCUIT_DATA
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    while levels.len() as u64 <= degree {
+        let circuit_data = match levels.last() {
+            None => voucher_circuit_data(0),
+            Some(inner_circuit_data) => {
+                let config = CircuitConfig::standard_recursion_zk_config();
+                let mut circuit_builder = CircuitBuilder::<F, D>::new(config);
+                make_extended_voucher_circuit(&mut circuit_builder, inner_circuit_data);
+                circuit_builder.build::<C>()
+            }
+        };
+        levels.push(Arc::new(circuit_data));
+    }
+    levels[degree as usize].clone()
+}
+
+/// rebuild the (witness independent) circuit data for a voucher of the given degree,
+/// by building the origin circuit and wrapping it in `degree` extension circuits
+pub(crate) fn voucher_circuit_data(degree: u64) -> CircuitData<F, C, D> {
+    let config = CircuitConfig::standard_recursion_zk_config();
+    let mut circuit_builder = CircuitBuilder::<F, D>::new(config);
+    make_origin_voucher_circuit(&mut circuit_builder);
+    extend_circuit_data(circuit_builder.build::<C>(), degree)
+}
+
+/// wrap `circuit_data` in `hops` extension circuits
+fn extend_circuit_data(mut circuit_data: CircuitData<F, C, D>, hops: u64) -> CircuitData<F, C, D> {
+    for _ in 0..hops {
+        let config = CircuitConfig::standard_recursion_zk_config();
+        let mut circuit_builder = CircuitBuilder::<F, D>::new(config);
+        make_extended_voucher_circuit(&mut circuit_builder, &circuit_data);
+        circuit_data = circuit_builder.build::<C>();
+    }
+
+    circuit_data
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use simple_crypto::KeyPair;
 
     #[test]
     fn it_works_origin_voucher() {
         let key_pair = KeyPair::generate_key_pair();
-        let origin_voucher = Voucher::new_origin(key_pair.public_key, key_pair.private_key);
+        let mut origin_voucher = Voucher::new_origin(key_pair.public_key, key_pair.private_key);
+
+        let public_inputs = origin_voucher.proof_public_inputs().unwrap();
+        assert_eq!(public_inputs.origin, key_pair.public_key);
+        assert_eq!(public_inputs.locus, key_pair.public_key);
+        assert_eq!(public_inputs.degree, F::ZERO);
+        assert_eq!(public_inputs.capabilities, Capabilities::ALL);
+        assert_eq!(public_inputs.inner, None);
+        assert!(origin_voucher.verify());
+
+        // the voucher must not claim more than its proof attests
+        origin_voucher.degree = F::ONE;
+        assert!(!origin_voucher.verify());
+        origin_voucher.degree = F::ZERO;
+        origin_voucher.locus = KeyPair::generate_key_pair().public_key;
+        assert!(!origin_voucher.verify());
 
         match origin_voucher.voucher_proof_data {
             VoucherProofData::PathProofData { .. } => panic!("Expected origin voucher proof data"),
@@ -152,7 +813,7 @@ mod tests {
     #[test]
     fn it_works_extended_voucher() {
         let origin_key_pair = KeyPair::generate_key_pair();
-        let origin_private_key = origin_key_pair.private_key.clone();
+        let origin_private_key = origin_key_pair.private_key;
         let outer_locus = KeyPair::generate_key_pair().public_key;
         let origin_voucher =
             Voucher::new_origin(origin_key_pair.public_key, origin_key_pair.private_key);
@@ -171,6 +832,28 @@ mod tests {
         let origin_voucher_copy =
             Voucher::new_origin(origin_key_pair.public_key, origin_key_pair.private_key);
         let extended_voucher = origin_voucher_copy.extend_voucher(origin_private_key, outer_locus);
+        assert!(origin_voucher_copy
+            .extend_voucher_with_capabilities(
+                origin_private_key,
+                origin_key_pair.public_key,
+                Capabilities::ALL,
+            )
+            .is_err());
+        // only the locus can extend its voucher
+        let stranger = KeyPair::generate_key_pair();
+        assert!(origin_voucher_copy
+            .extend_voucher_weighted(stranger.private_key, outer_locus, Capabilities::ALL, 50)
+            .is_err());
+        assert_eq!(origin_voucher_copy.issuer(), origin_key_pair.public_key);
+        assert_eq!(extended_voucher.issuer(), origin_key_pair.public_key);
+        let inner = extended_voucher
+            .proof_public_inputs()
+            .unwrap()
+            .inner
+            .unwrap();
+        assert_eq!(inner.origin, origin_key_pair.public_key);
+        assert_eq!(inner.degree, F::ZERO);
+        assert!(extended_voucher.verify());
 
         match extended_voucher.voucher_proof_data {
             VoucherProofData::OriginProofData { .. } => panic!("No cuteness today"),
@@ -182,4 +865,328 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn it_refuses_foreign_inner_circuit() {
+        let origin_key_pair = KeyPair::generate_key_pair();
+        let outer_locus = KeyPair::generate_key_pair().public_key;
+        let origin_circuit_data = voucher_circuit_data(0);
+
+        // a foreign circuit of the same shape as the origin circuit, differing by one
+        // operation, which is enough to give it another digest
+        let config = CircuitConfig::standard_recursion_zk_config();
+        let mut circuit_builder = CircuitBuilder::<F, D>::new(config);
+        let voucher_targets = make_origin_voucher_circuit(&mut circuit_builder);
+        circuit_builder.add(
+            voucher_targets.origin_targets[0],
+            voucher_targets.origin_targets[1],
+        );
+        let mut partial_witness = PartialWitness::<F>::new();
+        fill_origin_voucher_circuit(
+            &mut partial_witness,
+            voucher_targets,
+            origin_key_pair.public_key,
+            origin_key_pair.public_key,
+            origin_key_pair.private_key,
+            PoseidonHash::hash_no_pad(
+                &[origin_key_pair.public_key, origin_key_pair.public_key].concat(),
+            )
+            .elements,
+            Capabilities::ALL.to_field(),
+            F::from_canonical_u64(DEFAULT_MAX_DEGREE),
+            DEFAULT_EPOCH_SCOPE,
+            F::ZERO,
+        );
+        let foreign_circuit_data = circuit_builder.build::<C>();
+        assert_eq!(foreign_circuit_data.common, origin_circuit_data.common);
+        assert_ne!(
+            foreign_circuit_data.verifier_only.circuit_digest,
+            origin_circuit_data.verifier_only.circuit_digest
+        );
+        let foreign_proof = foreign_circuit_data.prove(partial_witness).unwrap();
+        assert!(foreign_circuit_data.verify(foreign_proof.clone()).is_ok());
+
+        // the extension circuit only verifies proofs of the origin circuit it was built over
+        let config = CircuitConfig::standard_recursion_zk_config();
+        let mut circuit_builder = CircuitBuilder::<F, D>::new(config);
+        let voucher_targets =
+            make_extended_voucher_circuit(&mut circuit_builder, &origin_circuit_data);
+        let weight = F::from_canonical_u64(MAX_TRUST_WEIGHT);
+        let mut partial_witness = PartialWitness::<F>::new();
+        fill_extend_voucher_circuit(
+            &mut partial_witness,
+            voucher_targets,
+            origin_key_pair.public_key,
+            origin_key_pair.public_key,
+            F::ZERO,
+            Capabilities::ALL.to_field(),
+            weight,
+            F::from_canonical_u64(DEFAULT_MAX_DEGREE),
+            DEFAULT_EPOCH_SCOPE,
+            F::ZERO,
+            outer_locus,
+            Capabilities::ALL.to_field(),
+            weight,
+            origin_key_pair.private_key,
+            PoseidonHash::hash_no_pad(
+                &[
+                    &origin_key_pair.public_key[..],
+                    &outer_locus[..],
+                    &[weight][..],
+                ]
+                .concat(),
+            )
+            .elements,
+            &foreign_proof,
+        );
+        let circuit_data = circuit_builder.build::<C>();
+        let proved = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            circuit_data.prove(partial_witness)
+        }));
+        if let Ok(Ok(proof)) = proved {
+            assert!(circuit_data.verify(proof).is_err());
+        }
+    }
+
+    #[test]
+    fn it_works_attenuated_voucher() {
+        let origin_key_pair = KeyPair::generate_key_pair();
+        let locus_key_pair = KeyPair::generate_key_pair();
+        let origin_voucher = Voucher::new_origin_with_capabilities(
+            origin_key_pair.public_key,
+            origin_key_pair.private_key,
+            Capabilities::SSH | Capabilities::GOSSIP,
+        );
+        assert_eq!(
+            origin_voucher.proof_public_inputs().unwrap().capabilities,
+            Capabilities::SSH | Capabilities::GOSSIP
+        );
+
+        // a capability the origin did not grant cannot be delegated
+        let wider = Capabilities::SSH | Capabilities::from_bits(1 << 7);
+        assert!(origin_voucher
+            .extend_voucher_with_capabilities(
+                origin_key_pair.private_key,
+                locus_key_pair.public_key,
+                wider,
+            )
+            .is_err());
+
+        let mut ssh_voucher = origin_voucher
+            .extend_voucher_with_capabilities(
+                origin_key_pair.private_key,
+                locus_key_pair.public_key,
+                Capabilities::SSH,
+            )
+            .unwrap();
+        assert!(ssh_voucher.verify());
+        let public_inputs = ssh_voucher.proof_public_inputs().unwrap();
+        assert_eq!(public_inputs.capabilities, Capabilities::SSH);
+        assert_eq!(
+            public_inputs.inner.unwrap().capabilities,
+            Capabilities::SSH | Capabilities::GOSSIP
+        );
+
+        // the next hop inherits the narrower capabilities
+        let next_voucher = ssh_voucher.extend_voucher(
+            locus_key_pair.private_key,
+            KeyPair::generate_key_pair().public_key,
+        );
+        assert_eq!(next_voucher.capabilities(), Capabilities::SSH);
+        assert!(Voucher::from_bytes(&next_voucher.to_bytes()).is_ok());
+
+        // the voucher must not claim capabilities its proof does not attest
+        ssh_voucher.capabilities = Capabilities::SSH | Capabilities::GOSSIP;
+        assert!(!ssh_voucher.verify());
+    }
+
+    #[test]
+    fn it_works_weighted_voucher() {
+        let origin_key_pair = KeyPair::generate_key_pair();
+        let alice = KeyPair::generate_key_pair();
+        let bob = KeyPair::generate_key_pair();
+        let origin_voucher =
+            Voucher::new_origin(origin_key_pair.public_key, origin_key_pair.private_key);
+        assert_eq!(origin_voucher.score(), MAX_TRUST_WEIGHT);
+
+        for weight in [0, MAX_TRUST_WEIGHT + 1] {
+            assert!(origin_voucher
+                .extend_voucher_weighted(
+                    origin_key_pair.private_key,
+                    alice.public_key,
+                    Capabilities::ALL,
+                    weight,
+                )
+                .is_err());
+        }
+
+        // the score is the lowest weight along the path
+        let alice_voucher = origin_voucher
+            .extend_voucher_weighted(
+                origin_key_pair.private_key,
+                alice.public_key,
+                Capabilities::ALL,
+                50,
+            )
+            .unwrap();
+        assert_eq!(alice_voucher.score(), 50);
+        let mut bob_voucher = alice_voucher
+            .extend_voucher_weighted(alice.private_key, bob.public_key, Capabilities::ALL, 90)
+            .unwrap();
+        assert!(bob_voucher.verify());
+        let public_inputs = bob_voucher.proof_public_inputs().unwrap();
+        assert_eq!(public_inputs.score, F::from_canonical_u64(50));
+        assert_eq!(
+            public_inputs.inner.unwrap().score,
+            F::from_canonical_u64(50)
+        );
+        assert_eq!(
+            Voucher::from_bytes(&bob_voucher.to_bytes())
+                .unwrap()
+                .score(),
+            50
+        );
+
+        // the voucher must not claim a higher score than its proof attests
+        bob_voucher.score = F::from_canonical_u64(90);
+        assert!(!bob_voucher.verify());
+    }
+
+    #[test]
+    fn it_works_max_degree() {
+        let origin_key_pair = KeyPair::generate_key_pair();
+        let alice = KeyPair::generate_key_pair();
+        let bob = KeyPair::generate_key_pair().public_key;
+        assert!(Voucher::new_origin_with_max_degree(
+            origin_key_pair.public_key,
+            origin_key_pair.private_key,
+            Capabilities::ALL,
+            MAX_DEGREE + 1,
+        )
+        .is_err());
+
+        let origin_voucher = Voucher::new_origin_with_max_degree(
+            origin_key_pair.public_key,
+            origin_key_pair.private_key,
+            Capabilities::ALL,
+            1,
+        )
+        .unwrap();
+        let alice_voucher =
+            origin_voucher.extend_voucher(origin_key_pair.private_key, alice.public_key);
+        assert!(alice_voucher.verify());
+        let public_inputs = alice_voucher.proof_public_inputs().unwrap();
+        assert_eq!(public_inputs.max_degree, F::ONE);
+        assert_eq!(public_inputs.inner.unwrap().max_degree, F::ONE);
+
+        // the path cannot get any longer
+        assert!(alice_voucher
+            .extend_voucher_with_capabilities(alice.private_key, bob, Capabilities::ALL)
+            .is_err());
+
+        // a header beyond the maximum degree is refused before rebuilding any circuit
+        let mut bytes = alice_voucher.to_bytes();
+        let degree_offset = 2 * PUBLIC_KEY_LENGTH * 8;
+        bytes[degree_offset..degree_offset + 8].copy_from_slice(&300u64.to_le_bytes());
+        assert_eq!(Voucher::encoded_degree(&bytes).unwrap(), 300);
+        assert!(Voucher::from_bytes(&bytes).is_err());
+        assert!(Voucher::encoded_degree(&bytes[..degree_offset]).is_err());
+        assert_eq!(
+            Voucher::from_bytes(&alice_voucher.to_bytes())
+                .unwrap()
+                .max_degree(),
+            1
+        );
+    }
+
+    #[test]
+    fn it_works_voucher_serialization() {
+        let key_pair = KeyPair::generate_key_pair();
+        let origin_voucher = Voucher::new_origin(key_pair.public_key, key_pair.private_key);
+        let bytes = origin_voucher.to_bytes();
+
+        let decoded_voucher = Voucher::from_bytes(&bytes).expect("Failed to decode voucher");
+        assert_eq!(decoded_voucher.origin(), key_pair.public_key);
+        assert_eq!(decoded_voucher.locus(), key_pair.public_key);
+        assert_eq!(decoded_voucher.degree(), F::ZERO);
+        assert!(decoded_voucher.is_origin());
+
+        // claiming another locus in the header must not decode either
+        let mut relabeled_bytes = bytes.clone();
+        relabeled_bytes[PUBLIC_KEY_LENGTH * 8] ^= 1;
+        assert!(Voucher::from_bytes(&relabeled_bytes).is_err());
+
+        // flipping a proof byte must not decode into a valid voucher
+        let mut tampered_bytes = bytes;
+        let last = tampered_bytes.len() - 1;
+        tampered_bytes[last] ^= 1;
+        assert!(Voucher::from_bytes(&tampered_bytes).is_err());
+    }
+
+    #[test]
+    fn it_works_voucher_from_proof() {
+        let origin_key_pair = KeyPair::generate_key_pair();
+        let origin_voucher =
+            Voucher::new_origin(origin_key_pair.public_key, origin_key_pair.private_key);
+        let proof_data = match origin_voucher.voucher_proof_data {
+            VoucherProofData::OriginProofData { proof_data, .. } => proof_data,
+            VoucherProofData::PathProofData { .. } => panic!("Expected origin voucher proof data"),
+        };
+
+        // a proof whose public inputs do not verify is refused
+        let mut tampered_proof_data = proof_data.clone();
+        tampered_proof_data.public_inputs[PUBLIC_KEY_LENGTH] += F::ONE;
+        let circuit_data = match Voucher::from_proof(voucher_circuit_data(0), tampered_proof_data) {
+            Ok(_) => panic!("Expected a tampered proof to be refused"),
+            Err(_) => voucher_circuit_data(0),
+        };
+
+        // the adopted proof is extended like any other voucher
+        let adopted_voucher = Voucher::from_proof(circuit_data, proof_data).unwrap();
+        assert_eq!(adopted_voucher.origin(), origin_key_pair.public_key);
+        assert!(adopted_voucher.is_origin());
+        let outer_locus = KeyPair::generate_key_pair().public_key;
+        let extended_voucher =
+            adopted_voucher.extend_voucher(origin_key_pair.private_key, outer_locus);
+        assert_eq!(extended_voucher.locus(), outer_locus);
+        assert!(extended_voucher.verify());
+    }
+
+    #[test]
+    fn it_works_dyn_voucher() {
+        let origin_key_pair = KeyPair::generate_key_pair();
+        let origin_voucher: Box<dyn dos_core::Voucher> = Box::new(Voucher::new_origin(
+            origin_key_pair.public_key,
+            origin_key_pair.private_key,
+        ));
+        assert!(origin_voucher.is_origin());
+        assert_eq!(
+            origin_voucher.public_inputs().unwrap(),
+            VoucherPublicInputs {
+                origin: origin_voucher.origin(),
+                locus: origin_voucher.origin(),
+                degree: 0,
+            }
+        );
+
+        // only the locus can extend
+        let outer_locus = field_elements_to_bytes(&KeyPair::generate_key_pair().public_key);
+        let stranger = KeyPair::generate_key_pair();
+        assert!(origin_voucher
+            .extend(
+                &field_elements_to_bytes(&stranger.private_key),
+                &outer_locus
+            )
+            .is_err());
+
+        let private_key = field_elements_to_bytes(&origin_key_pair.private_key);
+        let extended_voucher = origin_voucher.extend(&private_key, &outer_locus).unwrap();
+        assert!(extended_voucher.verify());
+        assert_eq!(extended_voucher.degree(), 1);
+        assert_eq!(extended_voucher.origin(), origin_voucher.origin());
+        assert_eq!(extended_voucher.locus(), outer_locus);
+
+        let decoded_voucher = Voucher::from_bytes(&extended_voucher.to_bytes()).unwrap();
+        assert_eq!(dos_core::Voucher::locus(&decoded_voucher), outer_locus);
+    }
 }
@@ -0,0 +1,306 @@
+//! A persistent store for the vouchers a client has received.
+//!
+//! Vouchers themselves only attest origin, locus and degree. The wallet keeps the scope a
+//! voucher was issued for and when it expires next to it, and for a [`VoucherPolicy`] picks
+//! the voucher with the lowest degree that the verifier accepts.
+
+use anyhow::{anyhow, Result};
+use plonky2::field::types::PrimeField64;
+use simple_crypto::PublicKey;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::capabilities::Capabilities;
+use crate::voucher::Voucher;
+
+const WALLET_FILE: &str = "vouchers.bin";
+
+/// A voucher together with what the wallet knows about it besides the proof.
+pub struct WalletEntry {
+    pub voucher: Voucher,
+    /// what the voucher was issued for, e.g. a topic or a group of edge machines
+    pub scope: String,
+    /// unix timestamp in seconds after which the voucher is no longer presented
+    pub expires_at: u64,
+}
+
+impl WalletEntry {
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at <= now
+    }
+}
+
+/// What a verifier accepts: a scope, the origins it trusts (any if empty), a maximum degree,
+/// the capabilities a voucher must hold and the lowest score it accepts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VoucherPolicy {
+    pub scope: String,
+    pub origins: Vec<PublicKey>,
+    pub max_degree: u64,
+    pub capabilities: Capabilities,
+    pub min_score: u64,
+}
+
+impl VoucherPolicy {
+    pub fn accepts(&self, entry: &WalletEntry) -> bool {
+        entry.scope == self.scope
+            && (self.origins.is_empty() || self.origins.contains(&entry.voucher.origin()))
+            && entry.voucher.degree().to_canonical_u64() <= self.max_degree
+            && entry.voucher.capabilities().contains(self.capabilities)
+            && entry.voucher.score() >= self.min_score
+    }
+}
+
+/// Entries are ordered by scope, origin and degree, so the vouchers of one scope and origin
+/// are adjacent and the lowest degree comes first. Two proofs of the same statement are
+/// duplicates, of which the one expiring last is kept.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct EntryKey {
+    scope: String,
+    origin: [u64; 4],
+    degree: u64,
+    locus: [u64; 4],
+}
+
+impl EntryKey {
+    fn new(entry: &WalletEntry) -> Self {
+        EntryKey {
+            scope: entry.scope.clone(),
+            origin: canonical_key(&entry.voucher.origin()),
+            degree: entry.voucher.degree().to_canonical_u64(),
+            locus: canonical_key(&entry.voucher.locus()),
+        }
+    }
+}
+
+/// Vouchers stored in a single file in a directory, rewritten on every change.
+pub struct VoucherWallet {
+    path: PathBuf,
+    entries: BTreeMap<EntryKey, WalletEntry>,
+}
+
+impl VoucherWallet {
+    /// open the wallet in `path`, creating the directory if needed; every stored voucher
+    /// is verified again while loading
+    pub fn open(path: &Path) -> Result<Self> {
+        fs::create_dir_all(path)?;
+        let mut wallet = VoucherWallet {
+            path: path.to_path_buf(),
+            entries: BTreeMap::new(),
+        };
+
+        let wallet_file = wallet.path.join(WALLET_FILE);
+        if wallet_file.exists() {
+            for entry in decode_entries(&fs::read(wallet_file)?)? {
+                wallet.add_entry(entry);
+            }
+        }
+        Ok(wallet)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// store a voucher unless it has expired or the wallet already holds the same
+    /// statement for longer; returns whether the wallet changed
+    pub fn insert(
+        &mut self,
+        voucher: Voucher,
+        scope: &str,
+        expires_at: u64,
+        now: u64,
+    ) -> Result<bool> {
+        if !voucher.verify() {
+            return Err(anyhow!("voucher proof does not verify"));
+        }
+        let entry = WalletEntry {
+            voucher,
+            scope: scope.to_string(),
+            expires_at,
+        };
+        if entry.is_expired(now) || !self.add_entry(entry) {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    /// drop expired vouchers, returning how many were removed
+    pub fn discard_expired(&mut self, now: u64) -> Result<usize> {
+        let before = self.entries.len();
+        self.entries.retain(|_, entry| !entry.is_expired(now));
+        let removed = before - self.entries.len();
+        if removed > 0 {
+            self.save()?;
+        }
+        Ok(removed)
+    }
+
+    /// all stored vouchers, by scope, origin and ascending degree
+    pub fn entries(&self) -> impl Iterator<Item = &WalletEntry> {
+        self.entries.values()
+    }
+
+    pub fn entries_for_scope<'a>(
+        &'a self,
+        scope: &'a str,
+    ) -> impl Iterator<Item = &'a WalletEntry> + 'a {
+        self.entries().filter(move |entry| entry.scope == scope)
+    }
+
+    pub fn entries_for_origin(&self, origin: PublicKey) -> impl Iterator<Item = &WalletEntry> {
+        self.entries()
+            .filter(move |entry| entry.voucher.origin() == origin)
+    }
+
+    /// the unexpired voucher of lowest degree accepted by `policy`, preferring the one
+    /// that stays valid longest among equal degrees
+    pub fn select(&self, policy: &VoucherPolicy, now: u64) -> Option<&Voucher> {
+        self.entries_for_scope(&policy.scope)
+            .filter(|entry| !entry.is_expired(now) && policy.accepts(entry))
+            .min_by_key(|entry| {
+                (
+                    entry.voucher.degree().to_canonical_u64(),
+                    Reverse(entry.expires_at),
+                )
+            })
+            .map(|entry| &entry.voucher)
+    }
+
+    fn add_entry(&mut self, entry: WalletEntry) -> bool {
+        let key = EntryKey::new(&entry);
+        match self.entries.get(&key) {
+            Some(existing) if existing.expires_at >= entry.expires_at => false,
+            _ => {
+                self.entries.insert(key, entry);
+                true
+            }
+        }
+    }
+
+    /// write to a temporary file first, so a crash never leaves a truncated wallet behind
+    fn save(&self) -> Result<()> {
+        let mut bytes = Vec::new();
+        for entry in self.entries.values() {
+            encode_entry(entry, &mut bytes);
+        }
+        let wallet_file = self.path.join(WALLET_FILE);
+        let temporary_file = wallet_file.with_extension("tmp");
+        fs::write(&temporary_file, bytes)?;
+        fs::rename(temporary_file, wallet_file)?;
+        Ok(())
+    }
+}
+
+fn canonical_key(key: &PublicKey) -> [u64; 4] {
+    key.map(|element| element.to_canonical_u64())
+}
+
+// entry encoding: expiry (u64), scope length (u32), scope, voucher length (u32), voucher,
+// all integers little-endian
+fn encode_entry(entry: &WalletEntry, bytes: &mut Vec<u8>) {
+    let voucher_bytes = entry.voucher.to_bytes();
+    bytes.extend(entry.expires_at.to_le_bytes());
+    bytes.extend((entry.scope.len() as u32).to_le_bytes());
+    bytes.extend(entry.scope.as_bytes());
+    bytes.extend((voucher_bytes.len() as u32).to_le_bytes());
+    bytes.extend(voucher_bytes);
+}
+
+fn decode_entries(mut bytes: &[u8]) -> Result<Vec<WalletEntry>> {
+    fn take<'a>(bytes: &mut &'a [u8], length: usize) -> Result<&'a [u8]> {
+        if bytes.len() < length {
+            return Err(anyhow!("wallet file is truncated"));
+        }
+        let (head, tail) = bytes.split_at(length);
+        *bytes = tail;
+        Ok(head)
+    }
+    fn take_u32(bytes: &mut &[u8]) -> Result<usize> {
+        Ok(u32::from_le_bytes(take(bytes, 4)?.try_into()?) as usize)
+    }
+
+    let mut entries = Vec::new();
+    while !bytes.is_empty() {
+        let expires_at = u64::from_le_bytes(take(&mut bytes, 8)?.try_into()?);
+        let scope_length = take_u32(&mut bytes)?;
+        let scope = String::from_utf8(take(&mut bytes, scope_length)?.to_vec())?;
+        let voucher_length = take_u32(&mut bytes)?;
+        let voucher = Voucher::from_bytes(take(&mut bytes, voucher_length)?)?;
+        entries.push(WalletEntry {
+            voucher,
+            scope,
+            expires_at,
+        });
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simple_crypto::KeyPair;
+
+    fn wallet_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("dos-wallet-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        path
+    }
+
+    #[test]
+    fn it_works_wallet_selection() {
+        let origin_key_pair = KeyPair::generate_key_pair();
+        let locus_key_pair = KeyPair::generate_key_pair();
+        let other_origin = KeyPair::generate_key_pair().public_key;
+        let origin_voucher =
+            Voucher::new_origin(origin_key_pair.public_key, origin_key_pair.private_key);
+        let path_voucher =
+            origin_voucher.extend_voucher(origin_key_pair.private_key, locus_key_pair.public_key);
+
+        let path = wallet_path("selection");
+        let mut wallet = VoucherWallet::open(&path).unwrap();
+        assert!(wallet.insert(path_voucher, "ssh", 200, 100).unwrap());
+        assert!(wallet.insert(origin_voucher, "ssh", 150, 100).unwrap());
+        assert_eq!(wallet.len(), 2);
+
+        // the lowest degree wins while it is valid
+        let mut policy = VoucherPolicy {
+            scope: "ssh".to_string(),
+            origins: vec![origin_key_pair.public_key],
+            max_degree: 1,
+            capabilities: Capabilities::SSH,
+            min_score: 1,
+        };
+        assert!(wallet.select(&policy, 100).unwrap().is_origin());
+        assert!(!wallet.select(&policy, 160).unwrap().is_origin());
+        assert!(wallet.select(&policy, 200).is_none());
+
+        policy.max_degree = 0;
+        assert!(wallet.select(&policy, 160).is_none());
+        policy.max_degree = 1;
+        policy.origins = vec![other_origin];
+        assert!(wallet.select(&policy, 100).is_none());
+        policy.origins.clear();
+        policy.scope = "chat".to_string();
+        assert!(wallet.select(&policy, 100).is_none());
+
+        // the wallet survives reopening, and a duplicate expiring earlier is ignored
+        let mut wallet = VoucherWallet::open(&path).unwrap();
+        assert_eq!(wallet.len(), 2);
+        assert_eq!(wallet.entries_for_origin(other_origin).count(), 0);
+        let duplicate =
+            Voucher::new_origin(origin_key_pair.public_key, origin_key_pair.private_key);
+        assert!(!wallet.insert(duplicate, "ssh", 120, 100).unwrap());
+
+        assert_eq!(wallet.discard_expired(160).unwrap(), 1);
+        assert_eq!(VoucherWallet::open(&path).unwrap().len(), 1);
+        fs::remove_dir_all(path).unwrap();
+    }
+}